@@ -6,7 +6,7 @@ fi
 rm logs/dealer.log logs/player1.log logs/player2.log logs/player3.log
 
 cargo build --release
-cargo run --bin dealer --release > logs/dealer.log 2>&1 &
+cargo run --bin dealer --release -- --params "${1:-mpc-secure}" > logs/dealer.log 2>&1 &
 sleep 3
 cargo run --bin player --release > logs/player1.log 2>&1 &
 sleep 1
@@ -0,0 +1,254 @@
+//! Setup shared by the binaries that take part in the protocol as a player.
+
+use std::{
+    error::Error,
+    io,
+    net::{SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use homomorphic_encryption_project::{
+    encryption::*,
+    mpc::{decryption_proof, dkg, zk::ChallengeMode, PlayerState, Sec},
+    protocol::{Facilitator, OnlineMessage, PrepMessage},
+    serialization::key_material_from_bytes,
+};
+
+pub struct FacilitatorImpl {
+    players: Vec<SocketAddr>,
+    player_number: usize,
+    receivers: Vec<Receiver<OnlineMessage>>,
+    join_handle: JoinHandle<()>,
+    stop_signal: Arc<AtomicBool>,
+}
+
+impl FacilitatorImpl {
+    pub fn new(players: Vec<SocketAddr>, listener: TcpListener) -> Self {
+        let player_number = players
+            .iter()
+            .position(|&addr| addr == listener.local_addr().unwrap())
+            .unwrap();
+
+        let mut transmitters = Vec::new();
+        let mut receivers = Vec::new();
+        for _ in 0..players.len() {
+            let (tx, rx) = mpsc::channel();
+            transmitters.push(tx);
+            receivers.push(rx);
+        }
+        let stop_signal = Arc::new(AtomicBool::new(false));
+
+        let cloned_players = players.clone();
+
+        let stop_signal_clone = stop_signal.clone();
+        let join_handle = thread::spawn(move || {
+            listener.set_nonblocking(true).unwrap();
+
+            while !stop_signal_clone.load(Ordering::SeqCst) {
+                match listener.accept() {
+                    Ok((stream, _)) => {
+                        let (msg, sender): (OnlineMessage, SocketAddr) =
+                            serde_json::from_reader(stream).unwrap();
+
+                        let player_number = match cloned_players
+                            .iter()
+                            .position(|&player_addr| player_addr == sender)
+                        {
+                            Some(n) => n,
+                            None => {
+                                panic!(
+                                    "Could not find player with addr {} ({:?})",
+                                    sender, cloned_players
+                                )
+                            }
+                        };
+
+                        transmitters[player_number].send(msg).unwrap();
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(e) => panic!("{}", e),
+                }
+            }
+        });
+
+        Self {
+            players,
+            player_number,
+            receivers,
+            join_handle,
+            stop_signal,
+        }
+    }
+}
+
+impl Facilitator for FacilitatorImpl {
+    fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    fn player_number(&self) -> usize {
+        self.player_number
+    }
+
+    fn send(&self, player: usize, msg: &OnlineMessage) {
+        //println!("send to   [{:02}] {:?}", player, msg);
+        //println!("{:?}", self.players[player]);
+        let stream = TcpStream::connect(self.players[player]).unwrap();
+        serde_json::to_writer(stream, &(msg, self.players[self.player_number])).unwrap();
+    }
+
+    fn broadcast(&self, msg: &OnlineMessage) {
+        for i in 0..self.player_count() {
+            self.send(i, msg);
+        }
+    }
+
+    fn receive(&self, player: usize) -> OnlineMessage {
+        self.receivers[player].recv().unwrap()
+    }
+
+    fn receive_from_all(&self) -> Vec<OnlineMessage> {
+        let n = self.player_count();
+        let mut msgs = Vec::with_capacity(n);
+        for i in 0..n {
+            let msg = self.receive(i);
+            msgs.push(msg);
+        }
+        msgs
+    }
+
+    fn stop(self) {
+        self.stop_signal.store(true, Ordering::SeqCst);
+        self.join_handle.join().unwrap();
+    }
+}
+
+/// Connects to the dealer and the other players, and sets up our state with the key material and
+/// settings of the session
+///
+/// With `stored_preprocessing` the dealer is told that we produce or use a preprocessing store, so
+/// that it only starts sessions whose key can be used again.
+pub fn connect(
+    stored_preprocessing: bool,
+) -> Result<(PlayerState<FacilitatorImpl>, Parameters), Box<dyn Error>> {
+    let setup = initialize_mpc(stored_preprocessing)?;
+    let params = setup.params;
+
+    let facilitator = FacilitatorImpl::new(setup.players, setup.listener);
+
+    println!("Using parameters with N={}", params.n);
+    let key_material = match setup.key_material {
+        Some(bytes) => key_material_from_bytes(&params, &bytes)
+            .unwrap_or_else(|e| panic!("could not decode key material: {:?}", e)),
+        None => {
+            println!("Generating keys...");
+            dkg::generate_keys(&params, setup.sec, &facilitator)?
+        }
+    };
+    let mut state = match setup.threshold {
+        Some(threshold) => {
+            println!(
+                "Using {}-of-{} key shares",
+                threshold + 1,
+                facilitator.player_count()
+            );
+            PlayerState::new_threshold(facilitator, key_material, threshold)
+        }
+        None => PlayerState::new(facilitator, key_material),
+    };
+    if setup.challenge_mode == ChallengeMode::Interactive {
+        println!("Using interactive ZKPoPK challenges");
+    }
+    state.set_challenge_mode(setup.challenge_mode);
+    println!("Using statistical security of {} bits", setup.sec.bits());
+    state.set_sec(setup.sec);
+
+    println!("Committing to key shares...");
+    decryption_proof::commit_key_shares(&params, &mut state)?;
+
+    Ok((state, params))
+}
+
+/// The preprocessing store of the player with number `player_number` in `dir`
+pub fn store_path(dir: &Path, player_number: usize) -> PathBuf {
+    dir.join(format!("player-{}.prep", player_number + 1))
+}
+
+/// What a player gets from the dealer before the protocol starts
+struct Setup {
+    listener: TcpListener,
+    players: Vec<SocketAddr>,
+    params: Parameters,
+    /// Serialized key material, or `None` if the players generate their keys themselves
+    key_material: Option<Vec<u8>>,
+    threshold: Option<usize>,
+    challenge_mode: ChallengeMode,
+    sec: Sec,
+}
+
+fn initialize_mpc(stored_preprocessing: bool) -> Result<Setup, io::Error> {
+    let listener = TcpListener::bind("localhost:0")?;
+
+    println!("Connecting to dealer...");
+    let stream = TcpStream::connect("localhost:9000")?;
+
+    println!("Sending Start...");
+    let start_msg = PrepMessage::Start {
+        addr: listener.local_addr()?,
+        stored_preprocessing,
+    };
+    serde_json::to_writer(stream, &start_msg)?;
+
+    println!("Waiting for players to connect...");
+    let mut players = vec![];
+
+    let (params, key_material, threshold);
+    let mut challenge_mode = ChallengeMode::default();
+    let mut sec = Sec::default();
+    loop {
+        let (stream, _) = listener.accept()?;
+        match serde_json::from_reader::<_, PrepMessage>(stream).unwrap() {
+            PrepMessage::PlayerConnected(player_addr) => {
+                println!("New player connected: {}", player_addr);
+                players.push(player_addr);
+            }
+            PrepMessage::ChallengeMode(mode) => challenge_mode = mode,
+            PrepMessage::Sec(s) => sec = s,
+            PrepMessage::KeyMaterial {
+                params: p,
+                key_material: km,
+                threshold: t,
+            } => {
+                params = p;
+                key_material = Some(km);
+                threshold = t;
+                break;
+            }
+            PrepMessage::Parameters(p) => {
+                params = p;
+                key_material = None;
+                threshold = None;
+                break;
+            }
+            PrepMessage::Abort(reason) => return Err(io::Error::other(reason)),
+            _ => todo!("got weird message"),
+        };
+    }
+    println!("Received parameters!");
+
+    Ok(Setup {
+        listener,
+        players,
+        params,
+        key_material,
+        threshold,
+        challenge_mode,
+        sec,
+    })
+}
@@ -1,13 +1,16 @@
 use homomorphic_encryption_project::encryption::*;
 use std::{
-    io,
+    env, io,
     net::{SocketAddr, TcpListener, TcpStream},
 };
 
 use homomorphic_encryption_project::{
     encryption::{generate_key_pair, Parameters, PublicKey, SecretKey},
+    mpc::{threshold::threshold_key_materials, zk::ChallengeMode, Sec},
     prob::sample_from_uniform,
     protocol::{KeyMaterial, PrepMessage},
+    serialization::key_material_to_bytes,
+    storage::load_or_generate_key_pair,
 };
 
 const NUM_PLAYERS: usize = 3;
@@ -18,17 +21,64 @@ trait DealerFacilitator {
     fn wait_until_ready(&mut self) -> io::Result<()>;
     /// Return the number of players currently connected.
     fn player_count(&self) -> usize;
-    /// Send the public key and secret key shares to all players.
-    fn send_key_material(&mut self, key_materials: Vec<KeyMaterial>) -> io::Result<()>;
+    /// Whether some player takes its preprocessing from a store, which was produced under the
+    /// public key of an earlier session
+    fn stored_preprocessing(&self) -> bool;
+    /// Send the public key and secret key shares to all players, along with the threshold if the
+    /// shares are Shamir shares.
+    fn send_key_material(
+        &mut self,
+        params: &Parameters,
+        key_materials: Vec<KeyMaterial>,
+        threshold: Option<usize>,
+    ) -> io::Result<()>;
+    /// Send only the parameters to all players, who then generate their keys themselves.
+    fn send_parameters(&mut self, params: &Parameters) -> io::Result<()>;
 }
 
 struct DealerFacilitatorImpl {
     players: Vec<SocketAddr>,
+    stored_preprocessing: bool,
+    challenge_mode: ChallengeMode,
+    sec: Sec,
 }
 
 impl DealerFacilitatorImpl {
-    fn new() -> Self {
-        Self { players: vec![] }
+    fn new(challenge_mode: ChallengeMode, sec: Sec) -> Self {
+        Self {
+            players: vec![],
+            stored_preprocessing: false,
+            challenge_mode,
+            sec,
+        }
+    }
+
+    /// Tell the players how to choose the ZKPoPK challenges and the statistical security
+    /// parameter, unless they are the defaults
+    fn send_settings(&self) -> io::Result<()> {
+        let mut msgs = vec![];
+        if self.challenge_mode != ChallengeMode::default() {
+            msgs.push(PrepMessage::ChallengeMode(self.challenge_mode));
+        }
+        if self.sec != Sec::default() {
+            msgs.push(PrepMessage::Sec(self.sec));
+        }
+        for msg in &msgs {
+            for player in &self.players {
+                let stream = TcpStream::connect(player)?;
+                serde_json::to_writer(stream, msg)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tell the players that the session cannot be set up
+    fn abort(&self, reason: &str) -> io::Result<()> {
+        for player in &self.players {
+            let stream = TcpStream::connect(player)?;
+            serde_json::to_writer(stream, &PrepMessage::Abort(reason.to_string()))?;
+        }
+        Ok(())
     }
 }
 
@@ -41,7 +91,13 @@ impl DealerFacilitator for DealerFacilitatorImpl {
             let (stream, _) = listener.accept().unwrap();
             let msg: PrepMessage = serde_json::from_reader(stream).unwrap();
             let new_player = match msg {
-                PrepMessage::Start(player_addr) => player_addr,
+                PrepMessage::Start {
+                    addr,
+                    stored_preprocessing,
+                } => {
+                    self.stored_preprocessing |= stored_preprocessing;
+                    addr
+                }
                 _ => panic!("Expected Start message, got {:?}", msg),
             };
             println!("{} is ready to start.", new_player);
@@ -72,41 +128,147 @@ impl DealerFacilitator for DealerFacilitatorImpl {
         self.players.len()
     }
 
-    fn send_key_material(&mut self, key_materials: Vec<KeyMaterial>) -> io::Result<()> {
+    fn stored_preprocessing(&self) -> bool {
+        self.stored_preprocessing
+    }
+
+    fn send_key_material(
+        &mut self,
+        params: &Parameters,
+        key_materials: Vec<KeyMaterial>,
+        threshold: Option<usize>,
+    ) -> io::Result<()> {
+        self.send_settings()?;
         for (player, key_material) in self.players.iter().zip(key_materials) {
-            let msg = PrepMessage::KeyMaterial(key_material);
+            let msg = PrepMessage::KeyMaterial {
+                params: params.clone(),
+                key_material: key_material_to_bytes(params, &key_material),
+                threshold,
+            };
             let stream = TcpStream::connect(player).unwrap();
             serde_json::to_writer(stream, &msg).unwrap();
         }
 
         Ok(())
     }
+
+    fn send_parameters(&mut self, params: &Parameters) -> io::Result<()> {
+        self.send_settings()?;
+        for player in &self.players {
+            let msg = PrepMessage::Parameters(params.clone());
+            let stream = TcpStream::connect(player)?;
+            serde_json::to_writer(stream, &msg)?;
+        }
+
+        Ok(())
+    }
 }
 
 fn main() -> io::Result<()> {
-    let mut facilitator = DealerFacilitatorImpl::new();
+    let mut params_arg = String::from("mpc-secure");
+    let mut key_dir = None;
+    let mut threshold = None;
+    let mut dkg = false;
+    let mut challenge_mode = ChallengeMode::FiatShamir;
+    let mut sec = Sec::default();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--dkg" {
+            dkg = true;
+            continue;
+        }
+        if arg == "--interactive-proofs" {
+            challenge_mode = ChallengeMode::Interactive;
+            continue;
+        }
+        match (arg.as_str(), args.next()) {
+            ("--params", Some(value)) => params_arg = value,
+            ("--keys", Some(value)) => key_dir = Some(value),
+            ("--threshold", Some(value)) => match value.parse() {
+                Ok(t) if t < NUM_PLAYERS => threshold = Some(t),
+                _ => {
+                    eprintln!("Threshold must be a number below {}", NUM_PLAYERS);
+                    return Err(io::Error::from(io::ErrorKind::InvalidInput));
+                }
+            },
+            ("--sec", Some(value)) => match value.parse().map(Sec::new) {
+                Ok(Ok(s)) => sec = s,
+                _ => {
+                    eprintln!("Statistical security must be one of {:?}", Sec::SUPPORTED);
+                    return Err(io::Error::from(io::ErrorKind::InvalidInput));
+                }
+            },
+            _ => {
+                eprintln!(
+                    "Usage: dealer [--params <preset or file>] [--keys <directory>] [--threshold <t>] [--dkg] [--interactive-proofs] [--sec <bits>]"
+                );
+                eprintln!("Presets: {}", PRESETS.join(", "));
+                return Err(io::Error::from(io::ErrorKind::InvalidInput));
+            }
+        }
+    }
+
+    let params = parameters_from_preset_or_file(&params_arg)?;
+    println!("Using parameters {} with N={}", params_arg, params.n);
+    println!("Using statistical security of {} bits", sec.bits());
+    let mut facilitator = DealerFacilitatorImpl::new(challenge_mode, sec);
+
+    if dkg && (key_dir.is_some() || threshold.is_some()) {
+        eprintln!("--dkg cannot be combined with --keys or --threshold");
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+
+    facilitator.wait_until_ready()?;
 
-    println!("Generating and distributing key material...");
-    let params = mpc_secure_params();
-    let (pk, sk) = generate_key_pair(&params);
-    distribute_keys(&mut facilitator, pk, sk, &params)
+    // Stored preprocessing is only valid under the key it was produced with, so the key must
+    // be the one from the key directory in every session
+    if facilitator.stored_preprocessing() && key_dir.is_none() {
+        let reason = "stored preprocessing needs the same key in every session, start the dealer with --keys <directory> and without --dkg";
+        eprintln!("{}", reason);
+        facilitator.abort(reason)?;
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+
+    if dkg {
+        println!("Sending parameters, the players generate their keys themselves...");
+        return facilitator.send_parameters(&params);
+    }
+
+    let (pk, sk) = match key_dir {
+        Some(key_dir) => {
+            println!("Using the key pair in {}...", key_dir);
+            load_or_generate_key_pair(&key_dir, &params).map_err(io::Error::other)?
+        }
+        None => generate_key_pair(&params),
+    };
+
+    println!("Distributing key material...");
+    distribute_keys(&mut facilitator, pk, sk, &params, threshold)
 }
 
 /// Function for functionality in Fkey_gen figure 2 of the MPC article.
+///
+/// Without a threshold the players get n-of-n additive shares of sk and sk^2, with threshold t
+/// they get Shamir shares such that any t + 1 players can decrypt.
 fn distribute_keys<Facilitator>(
     facilitator: &mut Facilitator,
     pk: PublicKey,
     sk: SecretKey,
     params: &Parameters,
+    threshold: Option<usize>,
 ) -> io::Result<()>
 where
     Facilitator: DealerFacilitator,
 {
-    facilitator.wait_until_ready()?;
-
     let rq = &params.quotient_ring;
     let n = facilitator.player_count();
 
+    if let Some(t) = threshold {
+        let key_materials =
+            threshold_key_materials(params, &pk, &sk, t, n).map_err(io::Error::other)?;
+        return facilitator.send_key_material(params, key_materials, threshold);
+    }
+
     let mut key_materials = vec![];
 
     // set sk shares and pk for the first n-1 players.
@@ -134,13 +296,14 @@ where
     });
 
     // Send all key material
-    facilitator.send_key_material(key_materials)
+    facilitator.send_key_material(params, key_materials, None)
 }
 
 #[cfg(test)]
 mod tests {
     use homomorphic_encryption_project::{
         encryption::{decrypt, encrypt},
+        mpc::threshold::reconstruct,
         poly::Polynomial,
         polynomial,
     };
@@ -152,12 +315,13 @@ mod tests {
         let mut facilitator = TestDealerFacilitator {
             player_count: NUM_PLAYERS,
             key_materials: None,
+            threshold: None,
         };
 
         let params = Parameters::default();
         let (pk, sk) = generate_key_pair(&params);
         let pk_clone = pk.clone();
-        distribute_keys(&mut facilitator, pk, sk, &params).unwrap();
+        distribute_keys(&mut facilitator, pk, sk, &params, None).unwrap();
 
         for key_material in &facilitator.key_materials.unwrap() {
             assert_eq!(pk_clone, key_material.pk);
@@ -169,13 +333,14 @@ mod tests {
         let mut facilitator = TestDealerFacilitator {
             player_count: NUM_PLAYERS,
             key_materials: None,
+            threshold: None,
         };
 
         let params = Parameters::default();
         let rq = &params.quotient_ring;
         let (pk, sk) = generate_key_pair(&params);
         let pk_clone = pk.clone();
-        distribute_keys(&mut facilitator, pk, sk, &params).unwrap();
+        distribute_keys(&mut facilitator, pk, sk, &params, None).unwrap();
 
         let key_materials = facilitator.key_materials.unwrap();
         let mut s = polynomial![0];
@@ -200,9 +365,41 @@ mod tests {
         assert_eq!(s_mul_s, s_mul_s_from_players);
     }
 
+    #[test]
+    fn send_threshold_sk_shares() {
+        let mut facilitator = TestDealerFacilitator {
+            player_count: NUM_PLAYERS,
+            key_materials: None,
+            threshold: None,
+        };
+
+        let params = Parameters::default();
+        let rq = &params.quotient_ring;
+        let (pk, sk) = generate_key_pair(&params);
+        distribute_keys(&mut facilitator, pk, sk.clone(), &params, Some(1)).unwrap();
+
+        assert_eq!(facilitator.threshold, Some(1));
+        let key_materials = facilitator.key_materials.unwrap();
+
+        // Any two players can reconstruct sk and sk^2
+        for players in [[0, 1], [0, 2], [1, 2]] {
+            let sk_i1s: Vec<(usize, Polynomial)> = players
+                .iter()
+                .map(|&i| (i, key_materials[i].sk_i1.clone()))
+                .collect();
+            let sk_i2s: Vec<(usize, Polynomial)> = players
+                .iter()
+                .map(|&i| (i, key_materials[i].sk_i2.clone()))
+                .collect();
+            assert_eq!(reconstruct(&params, &sk_i1s).unwrap(), sk.modulo(&rq.q));
+            assert_eq!(reconstruct(&params, &sk_i2s).unwrap(), rq.mul(&sk, &sk));
+        }
+    }
+
     struct TestDealerFacilitator {
         player_count: usize,
         key_materials: Option<Vec<KeyMaterial>>,
+        threshold: Option<usize>,
     }
 
     impl DealerFacilitator for TestDealerFacilitator {
@@ -214,8 +411,22 @@ mod tests {
             self.player_count
         }
 
-        fn send_key_material(&mut self, key_materials: Vec<KeyMaterial>) -> io::Result<()> {
+        fn stored_preprocessing(&self) -> bool {
+            false
+        }
+
+        fn send_key_material(
+            &mut self,
+            _params: &Parameters,
+            key_materials: Vec<KeyMaterial>,
+            threshold: Option<usize>,
+        ) -> io::Result<()> {
             self.key_materials = Some(key_materials);
+            self.threshold = threshold;
+            Ok(())
+        }
+
+        fn send_parameters(&mut self, _params: &Parameters) -> io::Result<()> {
             Ok(())
         }
     }
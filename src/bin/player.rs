@@ -1,178 +1,42 @@
+mod common;
+
 use std::{
+    env,
+    error::Error,
     io,
-    net::{SocketAddr, TcpListener, TcpStream},
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        mpsc::{self, Receiver},
-        Arc,
-    },
-    thread::{self, JoinHandle},
+    path::{Path, PathBuf},
     time::Instant,
 };
 
+use common::{connect, store_path, FacilitatorImpl};
 use homomorphic_encryption_project::{
     encryption::*,
-    mpc::{online, prep, PlayerState},
+    mpc::{online, prep, AngleShare, MulTriple, PlayerState},
     prob::sample_single,
-    protocol::{Facilitator, KeyMaterial, OnlineMessage, PrepMessage},
+    protocol::Facilitator,
+    storage::{load_preprocessing_store, save_preprocessing_store},
 };
 use rug::Integer;
 
-struct FacilitatorImpl {
-    players: Vec<SocketAddr>,
-    player_number: usize,
-    receivers: Vec<Receiver<OnlineMessage>>,
-    join_handle: JoinHandle<()>,
-    stop_signal: Arc<AtomicBool>,
-}
-
-impl FacilitatorImpl {
-    fn new(players: Vec<SocketAddr>, listener: TcpListener) -> Self {
-        let player_number = players
-            .iter()
-            .position(|&addr| addr == listener.local_addr().unwrap())
-            .unwrap();
-
-        let mut transmitters = Vec::new();
-        let mut receivers = Vec::new();
-        for _ in 0..players.len() {
-            let (tx, rx) = mpsc::channel();
-            transmitters.push(tx);
-            receivers.push(rx);
-        }
-        let stop_signal = Arc::new(AtomicBool::new(false));
-
-        let cloned_players = players.clone();
-
-        let stop_signal_clone = stop_signal.clone();
-        let join_handle = thread::spawn(move || {
-            listener.set_nonblocking(true).unwrap();
-
-            while !stop_signal_clone.load(Ordering::SeqCst) {
-                match listener.accept() {
-                    Ok((stream, _)) => {
-                        let (msg, sender): (OnlineMessage, SocketAddr) =
-                            serde_json::from_reader(stream).unwrap();
-
-                        let player_number = match cloned_players
-                            .iter()
-                            .position(|&player_addr| player_addr == sender)
-                        {
-                            Some(n) => n,
-                            None => {
-                                panic!(
-                                    "Could not find player with addr {} ({:?})",
-                                    sender, cloned_players
-                                )
-                            }
-                        };
-
-                        transmitters[player_number].send(msg).unwrap();
-                    }
-                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
-                    Err(e) => panic!("{}", e),
-                }
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut store_dir = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--store", Some(value)) => store_dir = Some(PathBuf::from(value)),
+            _ => {
+                eprintln!("Usage: player [--store <directory>]");
+                return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
             }
-        });
-
-        Self {
-            players,
-            player_number,
-            receivers,
-            join_handle,
-            stop_signal,
-        }
-    }
-}
-
-impl Facilitator for FacilitatorImpl {
-    fn player_count(&self) -> usize {
-        self.players.len()
-    }
-
-    fn player_number(&self) -> usize {
-        self.player_number
-    }
-
-    fn send(&self, player: usize, msg: &OnlineMessage) {
-        //println!("send to   [{:02}] {:?}", player, msg);
-        //println!("{:?}", self.players[player]);
-        let stream = TcpStream::connect(self.players[player]).unwrap();
-        serde_json::to_writer(stream, &(msg, self.players[self.player_number])).unwrap();
-    }
-
-    fn broadcast(&self, msg: &OnlineMessage) {
-        for i in 0..self.player_count() {
-            self.send(i, msg);
         }
     }
 
-    fn receive(&self, player: usize) -> OnlineMessage {
-        self.receivers[player].recv().unwrap()
-    }
-
-    fn receive_from_all(&self) -> Vec<OnlineMessage> {
-        let n = self.player_count();
-        let mut msgs = Vec::with_capacity(n);
-        for i in 0..n {
-            let msg = self.receive(i);
-            msgs.push(msg);
-        }
-        msgs
-    }
-
-    fn stop(self) {
-        self.stop_signal.store(true, Ordering::SeqCst);
-        self.join_handle.join().unwrap();
-    }
-}
-
-fn main() -> io::Result<()> {
-    let (listener, players, key_material) = initialize_mpc()?;
-
-    let facilitator = FacilitatorImpl::new(players, listener);
-
-    let params = mpc_secure_params();
-    println!("Using parameters with N={}", params.n);
-    let state = PlayerState::new(facilitator, key_material);
+    let (state, params) = connect(store_dir.is_some())?;
 
     let input = sample_single(&Integer::from(50));
 
     let protocol = Protocol::X1MulX2PlusX3;
-    protocol.run(state, params, input)
-}
-
-fn initialize_mpc() -> Result<(TcpListener, Vec<SocketAddr>, KeyMaterial), io::Error> {
-    let listener = TcpListener::bind("localhost:0")?;
-
-    println!("Connecting to dealer...");
-    let stream = TcpStream::connect("localhost:9000")?;
-
-    println!("Sending Start...");
-    let start_msg = PrepMessage::Start(listener.local_addr()?);
-    serde_json::to_writer(stream, &start_msg)?;
-
-    println!("Waiting for players to connect...");
-    let mut players = vec![];
-
-    let key_material;
-    loop {
-        let (stream, _) = listener.accept()?;
-        match serde_json::from_reader::<_, PrepMessage>(stream).unwrap() {
-            PrepMessage::PlayerConnected(player_addr) => {
-                println!("New player connected: {}", player_addr);
-                players.push(player_addr);
-            }
-            PrepMessage::KeyMaterial(km) => {
-                key_material = km;
-                break;
-            }
-            _ => todo!("got weird message"),
-        };
-    }
-    println!("Received key material!");
-
-    Ok((listener, players, key_material))
+    protocol.run(state, params, input, store_dir.as_deref())
 }
 
 #[allow(dead_code)]
@@ -188,7 +52,8 @@ impl Protocol {
         mut state: PlayerState<FacilitatorImpl>,
         params: Parameters,
         input: Integer,
-    ) -> io::Result<()> {
+        store_dir: Option<&Path>,
+    ) -> Result<(), Box<dyn Error>> {
         let protocol_name = match self {
             Protocol::AddAll => "ADD_ALL_INPUTS",
             Protocol::MulAll => "MULTIPLY_ALL_INPUTS",
@@ -196,30 +61,44 @@ impl Protocol {
         };
         println!("Running protocol for: {}", protocol_name);
 
-        let now = Instant::now();
-
-        match self {
-            Protocol::AddAll => {
-                let player_count = state.facilitator.player_count();
+        // A pair for each input and a triple for each multiplication
+        let player_count = state.facilitator.player_count();
+        let (pair_count, triple_count) = match self {
+            Protocol::AddAll => (player_count, 0),
+            Protocol::MulAll => (player_count, player_count - 1),
+            Protocol::X1MulX2PlusX3 => {
+                assert!(player_count == 3, "incorrect number of players");
+                (player_count, 1)
+            }
+        };
 
+        let now = Instant::now();
+        let (mut pairs, mut triples) = match store_dir {
+            Some(dir) => take_preprocessing(&params, &mut state, dir, pair_count, triple_count)?,
+            None => {
                 println!("Begin preprocessing...");
-                prep::protocol::initialize(&params, &mut state);
+                prep::protocol::initialize(&params, &mut state)?;
+                (
+                    prep::protocol::pairs(&params, &state, pair_count)?,
+                    prep::protocol::triples(&params, &state, triple_count)?,
+                )
+            }
+        };
+        println!("Preprocessing took {} secs", now.elapsed().as_secs_f32());
 
-                let mut pairs = Vec::with_capacity(player_count);
-                for _ in 0..player_count {
-                    let pair = prep::protocol::pair(&params, &state);
-                    pairs.push(pair);
-                }
+        let now = Instant::now();
 
+        match self {
+            Protocol::AddAll => {
                 println!("Sharing inputs...");
                 let mut input_shares = Vec::with_capacity(player_count);
                 for i in 0..player_count {
                     let r_pair = pairs.pop().unwrap();
                     let input_share = if i == state.facilitator.player_number() {
                         println!("My input is: {}", input);
-                        online::protocol::give_input(&params, input.clone(), r_pair, &state)
+                        online::protocol::give_input(&params, input.clone(), r_pair, &state)?
                     } else {
-                        online::protocol::receive_input(r_pair, i, &state)
+                        online::protocol::receive_input(r_pair, i, &state)?
                     };
                     input_shares.push(input_share);
                 }
@@ -232,7 +111,7 @@ impl Protocol {
                     });
 
                 println!("Getting output...");
-                let output = online::protocol::output(&params, added_shares, &state);
+                let output = online::protocol::output(&params, added_shares, &state)?;
 
                 let elapsed_time = now.elapsed();
 
@@ -248,38 +127,15 @@ impl Protocol {
                 Ok(())
             }
             Protocol::MulAll => {
-                let player_count = state.facilitator.player_count();
-
-                println!("Begin preprocessing...");
-                prep::protocol::initialize(&params, &mut state);
-
-                let mut pairs = Vec::with_capacity(player_count + (player_count - 1));
-                for _ in 0..player_count {
-                    let pair = prep::protocol::pair(&params, &state);
-                    pairs.push(pair);
-                }
-
-                let mut triples = Vec::with_capacity(player_count - 1);
-                for _ in 0..(player_count - 1) {
-                    let triple = prep::protocol::triple(&params, &state);
-                    triples.push(triple);
-                    let triple = prep::protocol::triple(&params, &state);
-                    triples.push(triple);
-
-                    //Extra pair for multiply
-                    let pair = prep::protocol::pair(&params, &state);
-                    pairs.push(pair);
-                }
-
                 println!("Sharing inputs...");
                 let mut input_shares = Vec::with_capacity(player_count);
                 for i in 0..player_count {
                     let r_pair = pairs.pop().unwrap();
                     let input_share = if i == state.facilitator.player_number() {
                         println!("My input is: {}", input);
-                        online::protocol::give_input(&params, input.clone(), r_pair, &state)
+                        online::protocol::give_input(&params, input.clone(), r_pair, &state)?
                     } else {
-                        online::protocol::receive_input(r_pair, i, &state)
+                        online::protocol::receive_input(r_pair, i, &state)?
                     };
                     input_shares.push(input_share);
                 }
@@ -292,14 +148,12 @@ impl Protocol {
                         multiplied_shares,
                         input_share.clone(),
                         triples.pop().unwrap(),
-                        triples.pop().unwrap(),
-                        pairs.pop().unwrap().0,
                         &mut state,
-                    );
+                    )?;
                 }
 
                 println!("Getting output...");
-                let output = online::protocol::output(&params, multiplied_shares, &state);
+                let output = online::protocol::output(&params, multiplied_shares, &state)?;
 
                 let elapsed_time = now.elapsed();
 
@@ -315,39 +169,15 @@ impl Protocol {
                 Ok(())
             }
             Protocol::X1MulX2PlusX3 => {
-                let player_count = state.facilitator.player_count();
-                assert!(player_count == 3, "incorrect number of players");
-
-                println!("Begin preprocessing...");
-                prep::protocol::initialize(&params, &mut state);
-
-                let mut pairs = Vec::with_capacity(player_count + (player_count - 1));
-                for _ in 0..player_count {
-                    let pair = prep::protocol::pair(&params, &state);
-                    pairs.push(pair);
-                }
-
-                let mut triples = Vec::with_capacity(player_count - 1);
-                for _ in 0..(player_count - 1) {
-                    let triple = prep::protocol::triple(&params, &state);
-                    triples.push(triple);
-                    let triple = prep::protocol::triple(&params, &state);
-                    triples.push(triple);
-
-                    //Extra pair for multiply
-                    let pair = prep::protocol::pair(&params, &state);
-                    pairs.push(pair);
-                }
-
                 println!("Sharing inputs...");
                 let mut input_shares = Vec::with_capacity(player_count);
                 for i in 0..player_count {
                     let r_pair = pairs.pop().unwrap();
                     let input_share = if i == state.facilitator.player_number() {
                         println!("My input is: {}", input);
-                        online::protocol::give_input(&params, input.clone(), r_pair, &state)
+                        online::protocol::give_input(&params, input.clone(), r_pair, &state)?
                     } else {
-                        online::protocol::receive_input(r_pair, i, &state)
+                        online::protocol::receive_input(r_pair, i, &state)?
                     };
                     input_shares.push(input_share);
                 }
@@ -359,17 +189,15 @@ impl Protocol {
                     multiplied_shares,
                     input_shares[1].clone(),
                     triples.pop().unwrap(),
-                    triples.pop().unwrap(),
-                    pairs.pop().unwrap().0,
                     &mut state,
-                );
+                )?;
 
                 println!("Adding x_3 to previous result...");
                 let added_share =
                     online::protocol::add(&multiplied_shares, &input_shares[2].clone());
 
                 println!("Getting output...");
-                let output = online::protocol::output(&params, added_share, &state);
+                let output = online::protocol::output(&params, added_share, &state)?;
 
                 let elapsed_time = now.elapsed();
 
@@ -388,8 +216,41 @@ impl Protocol {
     }
 }
 
+/// Takes the pairs and triples for a run from our preprocessing store in `dir`, and saves the rest
+/// of the store before they are used, so that no material is used twice
+#[allow(clippy::type_complexity)]
+fn take_preprocessing(
+    params: &Parameters,
+    state: &mut PlayerState<FacilitatorImpl>,
+    dir: &Path,
+    pair_count: usize,
+    triple_count: usize,
+) -> Result<(Vec<(Integer, AngleShare)>, Vec<MulTriple>), Box<dyn Error>> {
+    let path = store_path(dir, state.facilitator.player_number());
+    println!("Loading preprocessing from {}...", path.display());
+    let mut store = load_preprocessing_store(&path, params)?;
+    store.restore_mac_key(params, state)?;
+
+    let pairs = store.take_pairs(pair_count)?;
+    let triples = store.take_triples(triple_count)?;
+    save_preprocessing_store(&path, params, &store)?;
+
+    let left = store.amounts();
+    println!("{} pairs and {} triples left", left.pairs, left.triples);
+    Ok((pairs, triples))
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{fs, net::TcpListener};
+
+    use homomorphic_encryption_project::{
+        error,
+        mpc::store::{Amounts, PreprocessingStore},
+        protocol::KeyMaterial,
+        storage::load_or_generate_key_pair,
+    };
+
     use super::*;
 
     #[test]
@@ -408,11 +269,11 @@ mod tests {
 
         let mut state = PlayerState::new(facilitator, key_material);
 
-        prep::protocol::initialize(&params, &mut state);
-        let pair = prep::protocol::pair(&params, &state);
+        prep::protocol::initialize(&params, &mut state).unwrap();
+        let pair = prep::protocol::pair(&params, &state).unwrap();
 
-        let input_share = online::protocol::give_input(&params, Integer::from(42_i32), pair, &state);
-        let output = online::protocol::output(&params, input_share, &state);
+        let input_share = online::protocol::give_input(&params, Integer::from(42_i32), pair, &state).unwrap();
+        let output = online::protocol::output(&params, input_share, &state).unwrap();
 
         assert_eq!(Integer::from(42_i32), output)
     }
@@ -433,14 +294,14 @@ mod tests {
 
         let mut state = PlayerState::new(facilitator, key_material);
 
-        prep::protocol::initialize(&params, &mut state);
-        let pair_1 = prep::protocol::pair(&params, &state);
-        let pair_2 = prep::protocol::pair(&params, &state);
+        prep::protocol::initialize(&params, &mut state).unwrap();
+        let pair_1 = prep::protocol::pair(&params, &state).unwrap();
+        let pair_2 = prep::protocol::pair(&params, &state).unwrap();
 
-        let input_share_x = online::protocol::give_input(&params, Integer::from(2_i32), pair_1, &state);
-        let input_share_y = online::protocol::give_input(&params, Integer::from(7_i32), pair_2, &state);
+        let input_share_x = online::protocol::give_input(&params, Integer::from(2_i32), pair_1, &state).unwrap();
+        let input_share_y = online::protocol::give_input(&params, Integer::from(7_i32), pair_2, &state).unwrap();
         let res_share = online::protocol::add(&input_share_x, &input_share_y);
-        let output = online::protocol::output(&params, res_share, &state);
+        let output = online::protocol::output(&params, res_share, &state).unwrap();
 
         assert_eq!(Integer::from(9_i32), output)
     }
@@ -461,30 +322,84 @@ mod tests {
 
         let mut state = PlayerState::new(facilitator, key_material);
 
-        prep::protocol::initialize(&params, &mut state);
-        let pair_1 = prep::protocol::pair(&params, &state);
-        let pair_2 = prep::protocol::pair(&params, &state);
-        let (t_share, _) = prep::protocol::pair(&params, &state);
-        let mul_triple_1 = prep::protocol::triple(&params, &state);
-        let mul_triple_2 = prep::protocol::triple(&params, &state);
+        prep::protocol::initialize(&params, &mut state).unwrap();
+        let pair_1 = prep::protocol::pair(&params, &state).unwrap();
+        let pair_2 = prep::protocol::pair(&params, &state).unwrap();
+        let mul_triple = prep::protocol::triple(&params, &state).unwrap();
 
-        let input_share_x = online::protocol::give_input(&params, Integer::from(2_i32), pair_1, &state);
-        let input_share_y = online::protocol::give_input(&params, Integer::from(7_i32), pair_2, &state);
+        let input_share_x = online::protocol::give_input(&params, Integer::from(2_i32), pair_1, &state).unwrap();
+        let input_share_y = online::protocol::give_input(&params, Integer::from(7_i32), pair_2, &state).unwrap();
 
         let res_share = online::protocol::multiply(
             &params,
             input_share_x,
             input_share_y,
-            mul_triple_1,
-            mul_triple_2,
-            t_share,
+            mul_triple,
             &mut state
-        );
+        ).unwrap();
 
-        let output = online::protocol::output(&params, res_share, &state);
+        let output = online::protocol::output(&params, res_share, &state).unwrap();
 
         assert_eq!(Integer::from(14_i32), output)
     }
+
+    #[test]
+    fn stored_preprocessing_runs_with_keys_of_same_directory() {
+        let params = secure_params();
+        let dir = env::temp_dir().join(format!("he-player-{}", std::process::id()));
+        let key_dir = dir.join("keys");
+
+        // A session whose dealer takes the key pair from `key_dir`
+        let session = |key_dir: &Path| {
+            let listener = TcpListener::bind("localhost:0").unwrap();
+            let local_address = listener.local_addr().unwrap();
+            let facilitator = FacilitatorImpl::new(vec![local_address], listener);
+
+            let (pk, sk) = load_or_generate_key_pair(key_dir, &params).unwrap();
+            let key_material = KeyMaterial {
+                sk_i2: params.quotient_ring.mul(&sk, &sk),
+                sk_i1: sk,
+                pk,
+            };
+            PlayerState::new(facilitator, key_material)
+        };
+
+        let mut state = session(&key_dir);
+        let amounts = Amounts {
+            pairs: 2,
+            triples: 1,
+            ..Amounts::default()
+        };
+        let store = PreprocessingStore::generate(&params, &mut state, amounts).unwrap();
+        save_preprocessing_store(store_path(&dir, 0), &params, &store).unwrap();
+        state.stop();
+
+        // A session under a new key cannot use the store
+        let mut state = session(&dir.join("other-keys"));
+        let err = take_preprocessing(&params, &mut state, &dir, 2, 1).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(error::Error::PreprocessingMismatch("public key"))
+        ));
+        state.stop();
+
+        let mut state = session(&key_dir);
+        let (mut pairs, mut triples) = take_preprocessing(&params, &mut state, &dir, 2, 1).unwrap();
+        let x = online::protocol::give_input(&params, Integer::from(2), pairs.remove(0), &state);
+        let y = online::protocol::give_input(&params, Integer::from(7), pairs.remove(0), &state);
+        let res_share = online::protocol::multiply(
+            &params,
+            x.unwrap(),
+            y.unwrap(),
+            triples.remove(0),
+            &mut state,
+        );
+        let output = online::protocol::output(&params, res_share.unwrap(), &state).unwrap();
+        assert_eq!(output, Integer::from(14));
+        state.stop();
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
 
 
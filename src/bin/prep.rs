@@ -0,0 +1,58 @@
+mod common;
+
+use std::{env, error::Error, fs, io, path::PathBuf, time::Instant};
+
+use common::{connect, store_path};
+use homomorphic_encryption_project::{
+    mpc::store::{Amounts, PreprocessingStore},
+    protocol::Facilitator,
+    storage::save_preprocessing_store,
+};
+
+/// Runs the preprocessing with the other players ahead of time, and stores the material for later
+/// runs of `player --store <directory>`, replacing any earlier store in the directory
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut store_dir = PathBuf::from("prep");
+    // Enough for one run of the player binary
+    let mut amounts = Amounts {
+        pairs: 3,
+        triples: 1,
+        ..Amounts::default()
+    };
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = args.next();
+        let count = value.as_ref().and_then(|value| value.parse().ok());
+        match (arg.as_str(), value, count) {
+            ("--store", Some(value), _) => store_dir = PathBuf::from(value),
+            ("--pairs", _, Some(count)) => amounts.pairs = count,
+            ("--triples", _, Some(count)) => amounts.triples = count,
+            ("--squares", _, Some(count)) => amounts.squares = count,
+            ("--bits", _, Some(count)) => amounts.bits = count,
+            _ => {
+                eprintln!(
+                    "Usage: prep [--store <directory>] [--pairs <n>] [--triples <n>] [--squares <n>] [--bits <n>]"
+                );
+                return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
+            }
+        }
+    }
+
+    let (mut state, params) = connect(true)?;
+
+    println!(
+        "Producing {} pairs, {} triples, {} square pairs and {} bits...",
+        amounts.pairs, amounts.triples, amounts.squares, amounts.bits
+    );
+    let now = Instant::now();
+    let store = PreprocessingStore::generate(&params, &mut state, amounts)?;
+    println!("Preprocessing took {} secs", now.elapsed().as_secs_f32());
+
+    fs::create_dir_all(&store_dir)?;
+    let path = store_path(&store_dir, state.facilitator.player_number());
+    save_preprocessing_store(&path, &params, &store)?;
+    println!("Stored preprocessing in {}", path.display());
+
+    state.stop();
+    Ok(())
+}
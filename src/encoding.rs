@@ -0,0 +1,306 @@
+//! Plaintext encoders for mapping values into R_p = Z_p[X]/(X^N + 1) and back.
+
+use rug::{ops::RemRounding, Integer, Rational};
+
+use crate::{encryption::Parameters, poly::Polynomial};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EncodingError {
+    /// The value does not fit in the plaintext space of the parameters
+    ValueOutOfRange,
+    /// The value needs more coefficients than the plaintext polynomial has
+    TooManyCoefficients { needed: usize, available: usize },
+}
+
+/// Maps values of type `Value` into plaintext polynomials and back.
+pub trait Encoder {
+    type Value;
+
+    fn encode(&self, params: &Parameters, value: &Self::Value)
+        -> Result<Polynomial, EncodingError>;
+    fn decode(&self, params: &Parameters, pol: &Polynomial) -> Self::Value;
+}
+
+/// Encodes a signed integer in the constant coefficient, using the centered representation of Z_p.
+///
+/// Values must be in the range [-p/2, p/2], and results are decoded to the same range.
+pub struct SignedIntegerEncoder;
+
+impl Encoder for SignedIntegerEncoder {
+    type Value = Integer;
+
+    fn encode(&self, params: &Parameters, value: &Integer) -> Result<Polynomial, EncodingError> {
+        if !in_centered_range(value, &params.p) {
+            return Err(EncodingError::ValueOutOfRange);
+        }
+
+        Ok(Polynomial::new(vec![value.rem_euc(&params.p).into()]))
+    }
+
+    fn decode(&self, params: &Parameters, pol: &Polynomial) -> Integer {
+        center(&pol.coefficient(0), &params.p)
+    }
+}
+
+/// Encodes a vector of integers mod p, one entry per coefficient.
+///
+/// Decoding always returns a vector of length N.
+pub struct VectorEncoder;
+
+impl Encoder for VectorEncoder {
+    type Value = Vec<Integer>;
+
+    fn encode(
+        &self,
+        params: &Parameters,
+        value: &Vec<Integer>,
+    ) -> Result<Polynomial, EncodingError> {
+        if value.len() > params.n {
+            return Err(EncodingError::TooManyCoefficients {
+                needed: value.len(),
+                available: params.n,
+            });
+        }
+
+        let coefficients = value.iter().map(|v| v.rem_euc(&params.p).into()).collect();
+        Ok(Polynomial::new(coefficients).trim_res())
+    }
+
+    fn decode(&self, params: &Parameters, pol: &Polynomial) -> Vec<Integer> {
+        (0..params.n)
+            .map(|i| pol.coefficient(i).rem_euc(&params.p))
+            .collect()
+    }
+}
+
+/// Encodes a (possibly large) signed integer by its binary expansion, such that the value is the
+/// polynomial evaluated in X = 2.
+///
+/// Decoding is correct as long as no coefficient has grown beyond p/2 in absolute value, and the
+/// degree has not wrapped around X^N + 1.
+pub struct BinaryEncoder;
+
+impl Encoder for BinaryEncoder {
+    type Value = Integer;
+
+    fn encode(&self, params: &Parameters, value: &Integer) -> Result<Polynomial, EncodingError> {
+        let bits = value.significant_bits() as usize;
+        if bits > params.n {
+            return Err(EncodingError::TooManyCoefficients {
+                needed: bits,
+                available: params.n,
+            });
+        }
+
+        // Negative values are encoded as the negated expansion of the absolute value
+        let sign = if *value < 0 { -1_i32 } else { 1_i32 };
+        let abs = value.clone().abs();
+        let coefficients = (0..bits.max(1))
+            .map(|i| Integer::from(abs.get_bit(i as u32) as i32 * sign).rem_euc(&params.p))
+            .collect();
+
+        Ok(Polynomial::new(coefficients).trim_res())
+    }
+
+    fn decode(&self, params: &Parameters, pol: &Polynomial) -> Integer {
+        // Horner's method in X = 2, starting from the highest coefficient
+        let mut value = Integer::ZERO;
+        for coefficient in pol.coefficients().rev() {
+            value = value * 2_i32 + center(coefficient, &params.p);
+        }
+        value
+    }
+}
+
+/// Encodes a rational number in fixed-point representation with `precision` fractional bits,
+/// using the binary encoding of the scaled value.
+///
+/// Multiplying two encodings doubles the number of fractional bits, so a product must be decoded
+/// with an encoder of twice the precision.
+pub struct FixedPointEncoder {
+    pub precision: u32,
+}
+
+impl FixedPointEncoder {
+    pub fn new(precision: u32) -> Self {
+        Self { precision }
+    }
+
+    fn scale(&self) -> Integer {
+        Integer::from(1) << self.precision
+    }
+}
+
+impl Encoder for FixedPointEncoder {
+    type Value = Rational;
+
+    fn encode(&self, params: &Parameters, value: &Rational) -> Result<Polynomial, EncodingError> {
+        let scaled = Rational::from(value * self.scale()).round();
+        BinaryEncoder.encode(params, &scaled.numer().to_owned())
+    }
+
+    fn decode(&self, params: &Parameters, pol: &Polynomial) -> Rational {
+        Rational::from((BinaryEncoder.decode(params, pol), self.scale()))
+    }
+}
+
+/// Map x mod p to the range [-p/2, p/2], using the same convention as `Polynomial::normalized_coefficients`.
+fn center(x: &Integer, p: &Integer) -> Integer {
+    let x: Integer = x.rem_euc(p).into();
+    let p_half: Integer = (p / 2_i32).into();
+    if x > p_half {
+        x - p
+    } else {
+        x
+    }
+}
+
+fn in_centered_range(value: &Integer, p: &Integer) -> bool {
+    center(value, p) == *value
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::{Integer, Rational};
+
+    use crate::encryption::{
+        add, decrypt, encrypt, generate_key_pair, mpc_secure_params, mul, Parameters,
+    };
+
+    use super::*;
+
+    fn round_trip<E: Encoder>(params: &Parameters, encoder: &E, value: &E::Value) -> E::Value {
+        let (pk, sk) = generate_key_pair(params);
+        let encoded = encoder.encode(params, value).unwrap();
+        let c = encrypt(params, encoded, &pk);
+        let decrypted = decrypt(params, c, &sk).unwrap();
+        encoder.decode(params, &decrypted)
+    }
+
+    #[test]
+    fn signed_integer_round_trip() {
+        let params = mpc_secure_params();
+
+        for v in [0, 1, -1, 42, -42, 63, -63] {
+            let value = Integer::from(v);
+            assert_eq!(round_trip(&params, &SignedIntegerEncoder, &value), value);
+        }
+    }
+
+    #[test]
+    fn signed_integer_rejects_out_of_range() {
+        let params = mpc_secure_params();
+
+        let res = SignedIntegerEncoder.encode(&params, &Integer::from(100));
+        assert_eq!(res, Err(EncodingError::ValueOutOfRange));
+    }
+
+    #[test]
+    fn signed_integer_mul_is_homomorphic() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+
+        let x = SignedIntegerEncoder
+            .encode(&params, &Integer::from(-5))
+            .unwrap();
+        let y = SignedIntegerEncoder
+            .encode(&params, &Integer::from(7))
+            .unwrap();
+        let c = mul(
+            &params,
+            &encrypt(&params, x, &pk),
+            &encrypt(&params, y, &pk),
+        );
+        let decrypted = decrypt(&params, c, &sk).unwrap();
+
+        assert_eq!(
+            SignedIntegerEncoder.decode(&params, &decrypted),
+            Integer::from(-35)
+        );
+    }
+
+    #[test]
+    fn vector_round_trip() {
+        let params = mpc_secure_params();
+        let mut value: Vec<Integer> = (0..params.n).map(|i| Integer::from(i * 3)).collect();
+        value[params.n - 1] = Integer::from(126);
+
+        assert_eq!(round_trip(&params, &VectorEncoder, &value), value);
+    }
+
+    #[test]
+    fn vector_decodes_to_full_length() {
+        let params = mpc_secure_params();
+        let value = vec![Integer::from(1), Integer::from(2)];
+
+        let decoded = round_trip(&params, &VectorEncoder, &value);
+        assert_eq!(decoded.len(), params.n);
+        assert_eq!(decoded[..2], value[..]);
+        assert!(decoded[2..].iter().all(|x| *x == 0));
+    }
+
+    #[test]
+    fn vector_rejects_too_long() {
+        let params = mpc_secure_params();
+        let value = vec![Integer::from(1); params.n + 1];
+
+        assert!(matches!(
+            VectorEncoder.encode(&params, &value),
+            Err(EncodingError::TooManyCoefficients { .. })
+        ));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let params = mpc_secure_params();
+
+        for v in [0_i64, 1, -1, 1_000_000, -123_456_789, 4_294_967_295] {
+            let value = Integer::from(v);
+            assert_eq!(round_trip(&params, &BinaryEncoder, &value), value);
+        }
+    }
+
+    #[test]
+    fn binary_add_and_mul_are_homomorphic() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+
+        let x = Integer::from(1234);
+        let y = Integer::from(-567);
+        let e_x = encrypt(&params, BinaryEncoder.encode(&params, &x).unwrap(), &pk);
+        let e_y = encrypt(&params, BinaryEncoder.encode(&params, &y).unwrap(), &pk);
+
+        let sum = decrypt(&params, add(&params, &e_x, &e_y), &sk).unwrap();
+        assert_eq!(BinaryEncoder.decode(&params, &sum), Integer::from(&x + &y));
+
+        let product = decrypt(&params, mul(&params, &e_x, &e_y), &sk).unwrap();
+        assert_eq!(BinaryEncoder.decode(&params, &product), x * y);
+    }
+
+    #[test]
+    fn fixed_point_round_trip() {
+        let params = mpc_secure_params();
+        let encoder = FixedPointEncoder::new(8);
+
+        for (num, den) in [(1, 2), (-3, 4), (1234, 16), (0, 1)] {
+            let value = Rational::from((num, den));
+            assert_eq!(round_trip(&params, &encoder, &value), value);
+        }
+    }
+
+    #[test]
+    fn fixed_point_mul_doubles_precision() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+        let encoder = FixedPointEncoder::new(4);
+
+        let x = Rational::from((3, 2));
+        let y = Rational::from((-5, 4));
+        let e_x = encrypt(&params, encoder.encode(&params, &x).unwrap(), &pk);
+        let e_y = encrypt(&params, encoder.encode(&params, &y).unwrap(), &pk);
+
+        let product = decrypt(&params, mul(&params, &e_x, &e_y), &sk).unwrap();
+        let decoded = FixedPointEncoder::new(8).decode(&params, &product);
+        assert_eq!(decoded, x * y);
+    }
+}
@@ -1,18 +1,45 @@
-use rug::Integer;
+use rug::{ops::DivRounding, Integer};
 
 use crate::{
+    error::Error,
     poly::Polynomial,
     polynomial,
     prob::{sample_from_gaussian, sample_from_uniform},
     quotient_ring::*,
 };
 
-use std::{cmp, str::FromStr};
+use serde::{Deserialize, Serialize};
+use std::{cmp, fs, io, path::Path, str::FromStr};
 
 pub type SecretKey = Polynomial;
 pub type PublicKey = (Polynomial, Polynomial);
 pub type Ciphertext = Vec<Polynomial>;
 
+/// Names of the parameter presets accepted by `parameters_from_preset`.
+pub const PRESETS: [&str; 12] = [
+    "default",
+    "secure",
+    "mpc-secure",
+    "degree-8",
+    "degree-16",
+    "degree-32",
+    "degree-64",
+    "degree-128",
+    "degree-256",
+    "degree-512",
+    "degree-1024",
+    "degree-2048",
+];
+
+/// The largest degree N of the cyclotomic polynomial that parameters may be read with
+pub const MAX_DEGREE: usize = 1 << 16;
+
+/// Parameters are (de)serialized as `q`, `p`, `r`, `r_prime` and `n`, with the quotient ring
+/// reconstructed on deserialization. `q` and `p` are written as decimal strings.
+///
+/// Deserialization fails unless N is a power of two up to `MAX_DEGREE` and 2 <= p < q.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "ParameterSpec", into = "ParameterSpec")]
 pub struct Parameters {
     pub quotient_ring: Rq,
     pub p: Integer,
@@ -51,6 +78,105 @@ impl Default for Parameters {
     }
 }
 
+#[derive(Serialize, Deserialize)]
+struct ParameterSpec {
+    #[serde(with = "decimal")]
+    q: Integer,
+    #[serde(with = "decimal")]
+    p: Integer,
+    r: f64,
+    r_prime: f64,
+    n: usize,
+}
+
+impl TryFrom<ParameterSpec> for Parameters {
+    type Error = Error;
+
+    fn try_from(spec: ParameterSpec) -> Result<Self, Error> {
+        let ParameterSpec {
+            q,
+            p,
+            r,
+            r_prime,
+            n,
+        } = spec;
+        let invalid = |reason: &str| Err(Error::InvalidParameters(reason.to_string()));
+        if !n.is_power_of_two() || n > MAX_DEGREE {
+            return invalid(&format!("n must be a power of two up to {}", MAX_DEGREE));
+        }
+        if q <= 1 {
+            return invalid("q must be larger than 1");
+        }
+        if p < 2 || p >= q {
+            return invalid("p must be at least 2 and smaller than q");
+        }
+        Ok(Parameters::new(q, r, r_prime, n, p))
+    }
+}
+
+impl From<Parameters> for ParameterSpec {
+    fn from(params: Parameters) -> Self {
+        ParameterSpec {
+            q: params.quotient_ring.q,
+            p: params.p,
+            r: params.r,
+            r_prime: params.r_prime,
+            n: params.n,
+        }
+    }
+}
+
+/// Serialize integers as decimal strings, so parameter files are easy to write by hand
+mod decimal {
+    use std::str::FromStr;
+
+    use rug::Integer;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(i: &Integer, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&i.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Integer, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Integer::from_str(&s).map_err(D::Error::custom)
+    }
+}
+
+/// Returns the parameters with the given preset name, see `PRESETS`.
+pub fn parameters_from_preset(name: &str) -> Option<Parameters> {
+    let params = match name {
+        "default" => Parameters::default(),
+        "secure" => secure_params(),
+        "mpc-secure" => mpc_secure_params(),
+        "degree-8" => params_8degree(),
+        "degree-16" => params_16degree(),
+        "degree-32" => params_32degree(),
+        "degree-64" => params_64degree(),
+        "degree-128" => params_128degree(),
+        "degree-256" => params_256degree(),
+        "degree-512" => params_512degree(),
+        "degree-1024" => params_1024degree(),
+        "degree-2048" => params_2048degree(),
+        _ => return None,
+    };
+    Some(params)
+}
+
+/// Load parameters from a JSON file with the fields `q`, `p`, `r`, `r_prime` and `n`.
+pub fn parameters_from_file<P: AsRef<Path>>(path: P) -> io::Result<Parameters> {
+    let file = fs::File::open(path)?;
+    serde_json::from_reader(file).map_err(io::Error::from)
+}
+
+/// Select parameters by preset name, or otherwise load them from the file at the given path.
+pub fn parameters_from_preset_or_file(name_or_path: &str) -> io::Result<Parameters> {
+    match parameters_from_preset(name_or_path) {
+        Some(params) => Ok(params),
+        None => parameters_from_file(name_or_path),
+    }
+}
+
 // Loosely based on http://homomorphicencryption.org/wp-content/uploads/2018/11/HomomorphicEncryptionStandardv1.1.pdf
 // security level (quantum): 128 bits
 // q: 27 bit prime, ex. 80708963
@@ -159,6 +285,11 @@ pub fn encrypt(params: &Parameters, m: Polynomial, pk: &PublicKey) -> Ciphertext
     encrypt_det(params, m, pk, (v, e_prime, e_prime_prime))
 }
 
+/// The trivial encryption (m, 0) of `m`, which has noise m and no randomness
+pub fn encrypt_trivial(params: &Parameters, m: Polynomial) -> Ciphertext {
+    vec![m.modulo(&params.quotient_ring.q), polynomial![0]]
+}
+
 pub fn encrypt_with_rand(
     params: &Parameters,
     m: Polynomial,
@@ -179,16 +310,27 @@ pub fn encrypt_with_rand(
     )
 }
 
-#[derive(Debug)]
-pub enum DecryptionError {
-    LInfNormTooBig(Integer),
+pub fn decrypt(params: &Parameters, c: Ciphertext, sk: &Polynomial) -> Result<Polynomial, Error> {
+    let msg_minus_q = decryption_value(params, &c, sk);
+
+    let q_half: Integer = (&params.quotient_ring.q / 2_i32).into();
+    if msg_minus_q.l_inf_norm() >= q_half {
+        return Err(Error::NoiseOverflow {
+            norm: msg_minus_q.l_inf_norm(),
+        });
+    }
+
+    // Reduce polynomial modulo the coefficients
+    Ok(msg_minus_q.modulo(&params.p))
 }
 
-pub fn decrypt(
-    params: &Parameters,
-    c: Ciphertext,
-    sk: &Polynomial,
-) -> Result<Polynomial, DecryptionError> {
+/// Returns the noise of a ciphertext, i.e. the infinity norm of `m + p * e`, as seen by the decryption.
+pub fn noise(params: &Parameters, c: &Ciphertext, sk: &Polynomial) -> Integer {
+    decryption_value(params, c, sk).l_inf_norm()
+}
+
+/// Computes `<c, (1, s, s^2, ...)>` with coefficients in the range [-q/2, q/2)
+fn decryption_value(params: &Parameters, c: &Ciphertext, sk: &Polynomial) -> Polynomial {
     let rq = &params.quotient_ring;
 
     // Construct secret key vector
@@ -209,15 +351,7 @@ pub fn decrypt(
         msg = rq.add(&msg, &ci_mul_sk_veci);
     }
 
-    let msg_minus_q = msg.normalized_coefficients(&rq.q);
-
-    let q_half: Integer = (&rq.q / 2_i32).into();
-    if msg_minus_q.l_inf_norm() >= q_half {
-        return Err(DecryptionError::LInfNormTooBig(msg_minus_q.l_inf_norm()));
-    }
-
-    // Reduce polynomial modulo the coefficients
-    Ok(msg_minus_q.modulo(&params.p))
+    msg.normalized_coefficients(&rq.q)
 }
 
 pub fn generate_key_pair(params: &Parameters) -> (PublicKey, SecretKey) {
@@ -277,6 +411,60 @@ pub fn drown_noise(
     add(params, &c, &noisy_zero)
 }
 
+/// Returns the bound U such that flooding a ciphertext with `p * u`, for u uniform in [-U, U]^N,
+/// hides any noise of infinity norm at most `noise_bound` with statistical distance at most 2^-sec.
+///
+/// By the smudging lemma, shifting a uniform distribution on [-U, U] by at most E changes it by a
+/// statistical distance of at most E / U. The noise is `p * e` with |e| <= E = ceil(noise_bound / p),
+/// and the distances of the N coefficients add up, so we need U >= N * E * 2^sec.
+pub fn flooding_bound(params: &Parameters, noise_bound: &Integer, sec: u32) -> Integer {
+    let e_bound = Integer::from(noise_bound.div_ceil(&params.p));
+    (e_bound * Integer::from(params.n)) << sec
+}
+
+/// Re-randomizes a ciphertext with at most `noise_bound` noise, such that the result only reveals
+/// the plaintext and not the computation that produced it (up to statistical distance 2^-sec).
+///
+/// This adds a fresh encryption of 0, whose `e''` term is flooded with uniform noise in
+/// [-U, U] for U from `flooding_bound`. Fails with `Error::NoiseOverflow` if q is too small to
+/// hold the flooding noise on top of the existing noise.
+///
+/// Only ciphertexts with two components are accepted, and others fail with
+/// `Error::CiphertextLengthMismatch`. The scheme has no relinearization, so the length and the
+/// last component of a product of ciphertexts would still reveal the multiplication.
+pub fn rerandomize(
+    params: &Parameters,
+    c: &Ciphertext,
+    pk: &PublicKey,
+    noise_bound: &Integer,
+    sec: u32,
+) -> Result<Ciphertext, Error> {
+    if c.len() != 2 {
+        return Err(Error::CiphertextLengthMismatch {
+            expected: 2,
+            found: c.len(),
+        });
+    }
+    let u_bound = flooding_bound(params, noise_bound, sec);
+
+    // The flooding noise dominates the noise of the fresh encryption, so we leave a factor 2 for it
+    let max_noise = Integer::from(&params.p * &u_bound) * 2_i32 + noise_bound;
+    let q_half: Integer = (&params.quotient_ring.q / 2_i32).into();
+    if max_noise >= q_half {
+        return Err(Error::NoiseOverflow { norm: max_noise });
+    }
+
+    let u = sample_from_uniform(&(Integer::from(2_i32 * &u_bound) + 1_i32), params.n)
+        - Polynomial::new(vec![u_bound; params.n]);
+
+    let v = sample_from_gaussian(params.r, params.n);
+    let e_prime = sample_from_gaussian(params.r, params.n);
+    let e_prime_prime = sample_from_gaussian(params.r_prime, params.n) + u;
+
+    let flooded_zero = encrypt_det(params, polynomial![0], pk, (v, e_prime, e_prime_prime));
+    Ok(add(params, c, &flooded_zero))
+}
+
 pub fn encode(coef: Integer) -> Polynomial {
     let coefficients = vec![coef];
     Polynomial::new(coefficients)
@@ -0,0 +1,152 @@
+//! Crate-wide error type for the encryption scheme and the MPC protocols.
+
+use std::fmt::Display;
+
+use rug::Integer;
+
+use crate::{
+    mpc::{zk::ProofError, Sec},
+    serialization::SerializationError,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    /// An object was created under different parameters than the ones in use
+    ParameterMismatch,
+    /// Parameters that do not describe a valid scheme, e.g. read from a file
+    InvalidParameters(String),
+    /// A ciphertext has an unexpected number of polynomials
+    CiphertextLengthMismatch { expected: usize, found: usize },
+    /// A vector of ciphertexts does not have the length required by a matrix
+    DimensionMismatch { expected: usize, found: usize },
+    /// The noise has grown to (or would grow to) `norm`, which does not fit below q/2
+    NoiseOverflow { norm: Integer },
+    /// A player sent a message that could not be used at this point of the protocol
+    MalformedMessage { player: usize, reason: String },
+    /// A player opened a commitment to a different value than it committed to
+    InvalidCommitment { player: usize },
+    /// The zero-knowledge proof of a player did not verify
+    ProofFailed { player: usize, reason: ProofError },
+    /// The prover of a zero-knowledge proof aborted in every attempt
+    ProofAborted { attempts: usize },
+    /// Fewer players than required by the secret sharing took part in a protocol
+    NotEnoughPlayers { required: usize, found: usize },
+    /// The set of players taking part in a protocol is not valid
+    InvalidPlayers(String),
+    /// The MACs of the opened values did not match
+    MacCheckFailed,
+    /// A multiplication triple did not satisfy ab = c
+    TripleCheckFailed { result: Integer },
+    /// A square pair did not satisfy a^2 = b
+    SquareCheckFailed { result: Integer },
+    /// Shared random bits can only be generated if the plaintext modulus is an odd prime
+    PlaintextModulusNotOddPrime(Integer),
+    /// The preprocessing store does not hold enough material of a kind
+    NotEnoughPreprocessing {
+        kind: &'static str,
+        requested: usize,
+        available: usize,
+    },
+    /// The statistical security parameter is not one of `Sec::SUPPORTED`
+    UnsupportedSecurityParameter(usize),
+    /// A preprocessing store was produced in a session with a different number of players, player,
+    /// sec or public key
+    PreprocessingMismatch(&'static str),
+}
+
+impl Error {
+    /// A player sent bytes that could not be deserialized
+    pub fn malformed(player: usize, e: SerializationError) -> Error {
+        match e {
+            SerializationError::ParameterMismatch => Error::ParameterMismatch,
+            e => Error::MalformedMessage {
+                player,
+                reason: format!("{:?}", e),
+            },
+        }
+    }
+
+    /// A player sent a message of the wrong type
+    pub fn unexpected_message(player: usize, expected: &str, found: &str) -> Error {
+        Error::MalformedMessage {
+            player,
+            reason: format!("expected {} message, got {}", expected, found),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::ParameterMismatch => write!(f, "parameters do not match"),
+            Error::InvalidParameters(reason) => write!(f, "invalid parameters: {}", reason),
+            Error::CiphertextLengthMismatch { expected, found } => write!(
+                f,
+                "expected a ciphertext of length {}, got length {}",
+                expected, found
+            ),
+            Error::DimensionMismatch { expected, found } => {
+                write!(f, "expected {} ciphertexts, got {}", expected, found)
+            }
+            Error::NoiseOverflow { norm } => write!(
+                f,
+                "noise of {} bits does not fit in the ciphertext modulus",
+                norm.significant_bits()
+            ),
+            Error::MalformedMessage { player, reason } => {
+                write!(f, "malformed message from player {}: {}", player, reason)
+            }
+            Error::InvalidCommitment { player } => {
+                write!(f, "player {} opened an invalid commitment", player)
+            }
+            Error::ProofFailed { player, reason } => write!(
+                f,
+                "zero-knowledge proof of player {} failed: {:?}",
+                player, reason
+            ),
+            Error::ProofAborted { attempts } => write!(
+                f,
+                "zero-knowledge proof aborted in all of {} attempts",
+                attempts
+            ),
+            Error::NotEnoughPlayers { required, found } => write!(
+                f,
+                "at least {} players are required, got {}",
+                required, found
+            ),
+            Error::InvalidPlayers(reason) => write!(f, "invalid set of players: {}", reason),
+            Error::MacCheckFailed => write!(f, "MACCheck failed"),
+            Error::TripleCheckFailed { result } => {
+                write!(f, "triple check failed, got {} instead of 0", result)
+            }
+            Error::SquareCheckFailed { result } => {
+                write!(f, "square check failed, got {} instead of 0", result)
+            }
+            Error::PlaintextModulusNotOddPrime(p) => {
+                write!(f, "plaintext modulus {} is not an odd prime", p)
+            }
+            Error::NotEnoughPreprocessing {
+                kind,
+                requested,
+                available,
+            } => write!(
+                f,
+                "{} {} requested, but only {} are left in the preprocessing store",
+                requested, kind, available
+            ),
+            Error::UnsupportedSecurityParameter(sec) => write!(
+                f,
+                "statistical security parameter {} is not one of {:?}",
+                sec,
+                Sec::SUPPORTED
+            ),
+            Error::PreprocessingMismatch(what) => write!(
+                f,
+                "the preprocessing store was produced for a different {}",
+                what
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
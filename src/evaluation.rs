@@ -0,0 +1,266 @@
+//! Homomorphic evaluation of public polynomials and linear maps on ciphertexts.
+//!
+//! The scheme has no relinearization, so a product of ciphertexts of degree d has d + 1
+//! polynomials. The evaluators below keep the number of ciphertext multiplications and the
+//! multiplicative depth low instead of chaining `encryption::mul` one factor at a time.
+
+use rug::{ops::RemRounding, Integer};
+
+use crate::{
+    encryption::{add, encode, mul, Ciphertext, Parameters},
+    error::Error,
+    poly::Polynomial,
+    polynomial,
+};
+
+/// Adds the public plaintext `m` to a ciphertext
+pub fn add_plain(params: &Parameters, c: &Ciphertext, m: &Polynomial) -> Ciphertext {
+    let mut res = c.clone();
+    res[0] = params.quotient_ring.add(&c[0], m);
+    res
+}
+
+/// Multiplies a ciphertext by the public scalar `a`
+///
+/// The scalar is reduced to the range [-p/2, p/2] first, as the noise grows with its size.
+pub fn mul_scalar(params: &Parameters, c: &Ciphertext, a: &Integer) -> Ciphertext {
+    let a = centered(params, a);
+    c.iter()
+        .map(|c_i| params.quotient_ring.times(c_i, &a))
+        .collect()
+}
+
+/// Evaluates the public polynomial `sum_i coefficients[i] * X^i` on the plaintext of `c`
+///
+/// Uses the Paterson–Stockmeyer method: for a polynomial of degree d, the powers c, ..., c^k with
+/// k = ceil(sqrt(d + 1)) and the giant steps c^k, c^2k, ... are computed as balanced products,
+/// and the blocks of k coefficients are evaluated on the small powers with scalar operations only.
+/// This uses O(sqrt(d)) ciphertext multiplications and a depth of about log2(d) + 1, where Horner's
+/// method would need d multiplications in sequence. Note that without modulus switching the noise
+/// still grows with the degree, so q must be large enough for the degree of the polynomial.
+pub fn evaluate_polynomial(
+    params: &Parameters,
+    c: &Ciphertext,
+    coefficients: &[Integer],
+) -> Ciphertext {
+    let zero = vec![polynomial![0]];
+    if coefficients.is_empty() {
+        return zero;
+    }
+    let d = coefficients.len() - 1;
+    if d == 0 {
+        return add_plain(params, &zero, &encode(coefficients[0].clone()));
+    }
+
+    let mut k = 1;
+    while k * k < d + 1 {
+        k += 1;
+    }
+    let block_count = d / k + 1;
+    let baby_steps = powers(params, c.clone(), if block_count > 1 { k } else { d });
+
+    // Evaluate each block q_j(X) = sum_{i < k} a_{jk + i} X^i on c
+    let blocks: Vec<Ciphertext> = coefficients
+        .chunks(k)
+        .map(|block| {
+            let mut res = add_plain(params, &zero, &encode(block[0].clone()));
+            for (a_i, c_i) in block.iter().zip(&baby_steps).skip(1) {
+                res = add(params, &res, &mul_scalar(params, c_i, a_i));
+            }
+            res
+        })
+        .collect();
+
+    if block_count == 1 {
+        return blocks[0].clone();
+    }
+
+    // c^jk for j = 1, ..., block_count - 1
+    let giant_steps = powers(params, baby_steps[k].clone(), block_count - 1);
+
+    let mut res = blocks[0].clone();
+    for (block, giant_step) in blocks.iter().zip(&giant_steps).skip(1) {
+        res = add(params, &res, &mul(params, block, giant_step));
+    }
+    res
+}
+
+/// Evaluates the public `rows x cols` matrix on a vector of `cols` ciphertexts
+///
+/// Only scalar multiplications are used, so the depth of the inputs is unchanged.
+pub fn evaluate_matrix(
+    params: &Parameters,
+    matrix: &[Vec<Integer>],
+    cs: &[Ciphertext],
+) -> Result<Vec<Ciphertext>, Error> {
+    let mut res = Vec::with_capacity(matrix.len());
+    for row in matrix {
+        if row.len() != cs.len() {
+            return Err(Error::DimensionMismatch {
+                expected: row.len(),
+                found: cs.len(),
+            });
+        }
+
+        let mut sum = vec![polynomial![0]];
+        for (a, c) in row.iter().zip(cs) {
+            sum = add(params, &sum, &mul_scalar(params, c, a));
+        }
+        res.push(sum);
+    }
+    Ok(res)
+}
+
+/// Computes `(1, c, c^2, ..., c^k)`, where `c^0` is a trivial encryption of 1
+///
+/// Every power is the product of two powers of half its exponent, so c^i has depth ceil(log2 i).
+fn powers(params: &Parameters, c: Ciphertext, k: usize) -> Vec<Ciphertext> {
+    let mut res = Vec::with_capacity(k + 1);
+    res.push(vec![polynomial![1]]);
+    if k == 0 {
+        return res;
+    }
+    res.push(c);
+    for i in 2..=k {
+        res.push(mul(params, &res[i / 2], &res[i - i / 2]));
+    }
+    res
+}
+
+fn centered(params: &Parameters, a: &Integer) -> Integer {
+    let a = a.rem_euc(&params.p).into();
+    let p_half: Integer = (&params.p / 2_i32).into();
+    if a > p_half {
+        a - &params.p
+    } else {
+        a
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use crate::{
+        encryption::{decrypt, encrypt, generate_key_pair, params_8degree, Parameters, PublicKey},
+        poly::Polynomial,
+        polynomial,
+        prob::sample_from_uniform,
+        quotient_ring::Rq,
+    };
+
+    use super::*;
+
+    /// The plaintext ring Z_p[X]/(X^N + 1)
+    fn plaintext_ring(params: &Parameters) -> Rq {
+        let mut fx = vec![Integer::ZERO; params.n + 1];
+        fx[0] = Integer::from(1);
+        fx[params.n] = Integer::from(1);
+        Rq::new(params.p.clone(), Polynomial::from(fx))
+    }
+
+    fn random_coefficients(params: &Parameters, len: usize) -> Vec<Integer> {
+        sample_from_uniform(&params.p, len)
+            .coefficients()
+            .cloned()
+            .collect()
+    }
+
+    /// Evaluates the polynomial with Horner's method, one multiplication at a time
+    fn horner(params: &Parameters, c: &Ciphertext, coefficients: &[Integer]) -> Ciphertext {
+        let mut res = add_plain(
+            params,
+            &vec![polynomial![0]],
+            &encode(coefficients.last().unwrap().clone()),
+        );
+        for a in coefficients.iter().rev().skip(1) {
+            res = add_plain(params, &mul(params, &res, c), &encode(a.clone()));
+        }
+        res
+    }
+
+    fn encrypt_all(params: &Parameters, ms: &[Polynomial], pk: &PublicKey) -> Vec<Ciphertext> {
+        ms.iter().map(|m| encrypt(params, m.clone(), pk)).collect()
+    }
+
+    #[test]
+    fn evaluate_polynomial_matches_plaintext() {
+        let params = params_8degree();
+        let r_p = plaintext_ring(&params);
+        let (pk, sk) = generate_key_pair(&params);
+
+        let m = polynomial![3, 1, 0, 5];
+        let c = encrypt(&params, m.clone(), &pk);
+
+        for len in 0..10 {
+            let coefficients = random_coefficients(&params, len);
+
+            let mut expected = polynomial![0];
+            let mut m_power = polynomial![1];
+            for a in &coefficients {
+                expected = r_p.add(&expected, &r_p.times(&m_power, a));
+                m_power = r_p.mul(&m_power, &m);
+            }
+
+            let res = evaluate_polynomial(&params, &c, &coefficients);
+            assert_eq!(decrypt(&params, res, &sk).unwrap(), expected.trim_res());
+        }
+    }
+
+    #[test]
+    fn evaluate_polynomial_agrees_with_horner() {
+        let params = params_8degree();
+        let (pk, sk) = generate_key_pair(&params);
+        let c = encrypt(&params, polynomial![2], &pk);
+
+        let coefficients = vec![Integer::from(1); 16];
+        let res = evaluate_polynomial(&params, &c, &coefficients);
+        let res_horner = horner(&params, &c, &coefficients);
+        assert_eq!(res.len(), res_horner.len());
+
+        // 1 + 2 + ... + 2^15 = 2^16 - 1
+        let expected = encode(Integer::from(65535).rem_euc(&params.p));
+        assert_eq!(decrypt(&params, res, &sk).unwrap(), expected);
+        assert_eq!(decrypt(&params, res_horner, &sk).unwrap(), expected);
+    }
+
+    #[test]
+    fn evaluate_matrix_matches_plaintext() {
+        let params = params_8degree();
+        let r_p = plaintext_ring(&params);
+        let (pk, sk) = generate_key_pair(&params);
+
+        let ms = vec![polynomial![1, 2], polynomial![-4], polynomial![0, 0, 7]];
+        let cs = encrypt_all(&params, &ms, &pk);
+        let matrix = vec![
+            vec![Integer::from(1), Integer::from(2), Integer::from(3)],
+            vec![Integer::from(-1), Integer::from(0), Integer::from(100)],
+        ];
+
+        let res = evaluate_matrix(&params, &matrix, &cs).unwrap();
+        assert_eq!(res.len(), 2);
+        for (row, c) in matrix.iter().zip(res) {
+            let mut expected = polynomial![0];
+            for (a, m) in row.iter().zip(&ms) {
+                expected = r_p.add(&expected, &r_p.times(m, a));
+            }
+            assert_eq!(decrypt(&params, c, &sk).unwrap(), expected.trim_res());
+        }
+    }
+
+    #[test]
+    fn evaluate_matrix_rejects_wrong_dimension() {
+        let params = params_8degree();
+        let (pk, _) = generate_key_pair(&params);
+        let cs = encrypt_all(&params, &[polynomial![1], polynomial![2]], &pk);
+
+        let matrix = vec![vec![Integer::from(1); 3]];
+        assert!(matches!(
+            evaluate_matrix(&params, &matrix, &cs),
+            Err(Error::DimensionMismatch {
+                expected: 3,
+                found: 2
+            })
+        ));
+    }
+}
@@ -1,9 +1,14 @@
+pub mod encoding;
 pub mod encryption;
+pub mod error;
+pub mod evaluation;
 pub mod mpc;
 pub mod poly;
 pub mod prob;
 pub mod protocol;
 pub mod quotient_ring;
+pub mod serialization;
+pub mod storage;
 
 #[cfg(test)]
 mod tests {
@@ -11,7 +16,8 @@ mod tests {
 
     use super::{encryption, prob};
     use crate::{
-        encryption::*, poly::Polynomial, polynomial, prob::sample_from_uniform, quotient_ring::Rq,
+        encryption::*, error::Error, poly::Polynomial, polynomial, prob::sample_from_uniform,
+        quotient_ring::Rq,
     };
 
     #[test]
@@ -115,6 +121,205 @@ mod tests {
         assert_eq!(c, ab);
     }
 
+    #[test]
+    fn all_presets_exist() {
+        for name in PRESETS {
+            assert!(
+                parameters_from_preset(name).is_some(),
+                "missing preset {}",
+                name
+            );
+        }
+        assert!(parameters_from_preset("does-not-exist").is_none());
+        assert_eq!(parameters_from_preset("degree-64").unwrap().n, 64);
+    }
+
+    #[test]
+    fn parameters_json_round_trip() {
+        let params = mpc_secure_params();
+
+        let json = serde_json::to_string(&params).unwrap();
+        let decoded: Parameters = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(decoded, params);
+    }
+
+    #[test]
+    fn parameters_from_json_file() {
+        let path = std::env::temp_dir().join(format!("he-params-{}.json", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"{ "q": "80708963", "p": "127", "r": 3.2, "r_prime": 3.2, "n": 16 }"#,
+        )
+        .unwrap();
+
+        let params = parameters_from_preset_or_file(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(params, Parameters::new(80708963, 3.2, 3.2, 16, 127));
+
+        let (pk, sk) = encryption::generate_key_pair(&params);
+        let c = encryption::encrypt(&params, polynomial![42], &pk);
+        assert_eq!(
+            encryption::decrypt(&params, c, &sk).unwrap(),
+            polynomial![42]
+        );
+    }
+
+    #[test]
+    fn invalid_parameter_files_are_rejected() {
+        let path = std::env::temp_dir().join(format!("he-bad-params-{}.json", std::process::id()));
+        let cases = [
+            (r#""q": "65537", "p": "127", "n": 0"#, "power of two"),
+            (r#""q": "65537", "p": "127", "n": 12"#, "power of two"),
+            (r#""q": "65537", "p": "127", "n": 131072"#, "power of two"),
+            (r#""q": "1", "p": "127", "n": 16"#, "q must be"),
+            (r#""q": "-5", "p": "127", "n": 16"#, "q must be"),
+            (r#""q": "127", "p": "127", "n": 16"#, "smaller than q"),
+            (r#""q": "65537", "p": "65538", "n": 16"#, "smaller than q"),
+            (r#""q": "65537", "p": "1", "n": 16"#, "at least 2"),
+        ];
+        for (fields, reason) in cases {
+            let json = format!(r#"{{ {}, "r": 3.2, "r_prime": 3.2 }}"#, fields);
+            std::fs::write(&path, json).unwrap();
+
+            let err = parameters_from_file(&path).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(reason), "{}: {}", fields, err);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Two-sample Kolmogorov-Smirnov statistic, the largest distance between the empirical CDFs
+    fn ks_statistic(mut xs: Vec<Integer>, mut ys: Vec<Integer>) -> f64 {
+        xs.sort();
+        ys.sort();
+
+        let (mut i, mut j) = (0, 0);
+        let mut max_distance: f64 = 0.0;
+        while i < xs.len() && j < ys.len() {
+            let x = xs[i].clone().min(ys[j].clone());
+            while i < xs.len() && xs[i] <= x {
+                i += 1;
+            }
+            while j < ys.len() && ys[j] <= x {
+                j += 1;
+            }
+            let distance = (i as f64 / xs.len() as f64 - j as f64 / ys.len() as f64).abs();
+            max_distance = max_distance.max(distance);
+        }
+        max_distance
+    }
+
+    #[test]
+    fn rerandomize_preserves_plaintext() {
+        let params = secure_params();
+        let (pk, sk) = encryption::generate_key_pair(&params);
+        let noise_bound = Integer::from(1) << 40;
+
+        let e_a = encrypt(&params, polynomial![3, 1], &pk);
+        let e_c = mul(&params, &e_a, &vec![polynomial![5]]);
+        assert!(noise(&params, &e_c, &sk) <= noise_bound);
+
+        let rerandomized = rerandomize(&params, &e_c, &pk, &noise_bound, 40).unwrap();
+
+        assert_ne!(rerandomized, e_c);
+        assert!(noise(&params, &rerandomized, &sk) > noise_bound);
+        assert_eq!(
+            decrypt(&params, rerandomized, &sk).unwrap(),
+            polynomial![15, 5]
+        );
+    }
+
+    #[test]
+    fn rerandomize_rejects_too_small_modulus() {
+        let params = Parameters::default();
+        let (pk, _) = encryption::generate_key_pair(&params);
+        let c = encrypt(&params, polynomial![1], &pk);
+
+        let res = rerandomize(&params, &c, &pk, &Integer::from(100), 40);
+        assert!(matches!(res, Err(Error::NoiseOverflow { .. })));
+    }
+
+    #[test]
+    fn rerandomize_rejects_product_of_ciphertexts() {
+        let params = secure_params();
+        let (pk, _) = encryption::generate_key_pair(&params);
+        let e_2 = encrypt(&params, polynomial![2], &pk);
+        let c = mul(&params, &e_2, &e_2);
+
+        let res = rerandomize(&params, &c, &pk, &(Integer::from(1) << 40), 40);
+        assert!(matches!(
+            res,
+            Err(Error::CiphertextLengthMismatch {
+                expected: 2,
+                found: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn rerandomized_ciphertext_does_not_reveal_circuit() {
+        let params = secure_params();
+        let (pk, sk) = encryption::generate_key_pair(&params);
+        let noise_bound = Integer::from(1) << 40;
+        let samples = 400;
+
+        // Two circuits computing the same plaintext 4, a fresh encryption and a sum of products
+        // with plaintexts, whose noise is larger
+        let fresh_circuit = || encrypt(&params, polynomial![4], &pk);
+        let product_circuit = || {
+            let e_1 = encrypt(&params, polynomial![1], &pk);
+            let e_2 = encrypt(&params, polynomial![2], &pk);
+            let e_8 = mul(&params, &e_1, &vec![polynomial![8]]);
+            add(&params, &e_8, &mul(&params, &e_2, &vec![polynomial![-2]]))
+        };
+
+        let mut fresh_noise = Vec::with_capacity(samples);
+        let mut product_noise = Vec::with_capacity(samples);
+        let mut fresh_flooded = Vec::with_capacity(samples);
+        let mut product_flooded = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            let c_fresh = fresh_circuit();
+            let c_product = product_circuit();
+            assert!(noise(&params, &c_product, &sk) <= noise_bound);
+
+            fresh_noise.push(noise(&params, &c_fresh, &sk));
+            product_noise.push(noise(&params, &c_product, &sk));
+
+            let c_fresh = rerandomize(&params, &c_fresh, &pk, &noise_bound, 40).unwrap();
+            let c_product = rerandomize(&params, &c_product, &pk, &noise_bound, 40).unwrap();
+            fresh_flooded.push((noise(&params, &c_fresh, &sk), c_fresh));
+            product_flooded.push((noise(&params, &c_product, &sk), c_product));
+        }
+
+        // Without flooding the noise trivially distinguishes the circuits
+        let before = ks_statistic(fresh_noise, product_noise);
+        assert!(before > 0.9, "KS statistic before flooding was {}", before);
+
+        // After flooding the noise and the components of the ciphertexts are consistent with the
+        // same distribution (critical value for 400 + 400 samples at significance level 10^-6 is
+        // about 0.19)
+        let after = ks_statistic(
+            fresh_flooded.iter().map(|(e, _)| e.clone()).collect(),
+            product_flooded.iter().map(|(e, _)| e.clone()).collect(),
+        );
+        assert!(after < 0.2, "KS statistic after flooding was {}", after);
+        for k in 0..2 {
+            let component = |flooded: &[(Integer, Ciphertext)]| {
+                flooded
+                    .iter()
+                    .map(|(_, c)| {
+                        assert_eq!(c.len(), 2);
+                        c[k].coefficient(0)
+                    })
+                    .collect()
+            };
+            let after = ks_statistic(component(&fresh_flooded), component(&product_flooded));
+            assert!(after < 0.2, "KS statistic of component {} was {}", k, after);
+        }
+    }
+
     /* #[test]
     fn bench_single_mpc_enc() {
         let params = mpc_secure_params();
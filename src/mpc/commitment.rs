@@ -1,10 +1,14 @@
-use crate::mpc::PlayerState;
+use crate::error::Error;
 use crate::protocol::Facilitator;
 use crate::protocol::OnlineMessage;
+use rand::Rng;
 use sha2::Digest;
 use sha2::Sha256;
 
-pub fn commit<F: Facilitator>(v: Vec<u8>, r: Vec<u8>, state: &PlayerState<F>) {
+/// Length of the randomness appended to committed values
+const RANDOMNESS_LEN: usize = 32;
+
+pub fn commit<F: Facilitator>(v: Vec<u8>, r: Vec<u8>, facilitator: &F) {
     let mut o = vec![];
     o.extend(v);
     o.extend(r);
@@ -13,9 +17,7 @@ pub fn commit<F: Facilitator>(v: Vec<u8>, r: Vec<u8>, state: &PlayerState<F>) {
     hasher.update(o);
     let c = hasher.finalize().to_vec();
 
-    state
-        .facilitator
-        .broadcast(&OnlineMessage::ShareCommitment(c));
+    facilitator.broadcast(&OnlineMessage::ShareCommitment(c));
 }
 
 pub fn open(c: Vec<u8>, o: Vec<u8>) -> Result<Vec<u8>, &'static str> {
@@ -27,3 +29,83 @@ pub fn open(c: Vec<u8>, o: Vec<u8>) -> Result<Vec<u8>, &'static str> {
     }
     Ok(o)
 }
+
+/// Every player commits to a value, and once all commitments are received, opens it.
+/// Returns the values of all players, failing if any of them opens a different value.
+pub fn commit_and_open<F: Facilitator>(facilitator: &F, v: Vec<u8>) -> Result<Vec<Vec<u8>>, Error> {
+    let r = rand::thread_rng().gen::<[u8; RANDOMNESS_LEN]>().to_vec();
+    commit(v.clone(), r.clone(), facilitator);
+
+    let commitments = receive_commitments(facilitator)?;
+
+    let mut o = v;
+    o.extend(r);
+    facilitator.broadcast(&OnlineMessage::ShareCommitOpen(o));
+
+    let openings = receive_openings(facilitator, &commitments)?;
+    openings
+        .into_iter()
+        .enumerate()
+        .map(|(j, mut opened)| {
+            if opened.len() < RANDOMNESS_LEN {
+                return Err(Error::MalformedMessage {
+                    player: j,
+                    reason: format!("expected opening of at least {} bytes", RANDOMNESS_LEN),
+                });
+            }
+            opened.truncate(opened.len() - RANDOMNESS_LEN);
+            Ok(opened)
+        })
+        .collect()
+}
+
+/// Jointly samples a random seed, which is uniform as long as one player is honest
+pub fn common_seed<F: Facilitator>(facilitator: &F) -> Result<[u8; 32], Error> {
+    let s_i = rand::thread_rng().gen::<[u8; 32]>().to_vec();
+    let seeds = commit_and_open(facilitator, s_i)?;
+
+    // XOR seeds to get s
+    let mut s = [0_u8; 32];
+    for (j, seed) in seeds.iter().enumerate() {
+        if seed.len() != 32 {
+            return Err(Error::MalformedMessage {
+                player: j,
+                reason: format!("expected seed of length {}, got {}", 32, seed.len()),
+            });
+        }
+        for (s_k, seed_k) in s.iter_mut().zip(seed) {
+            *s_k ^= seed_k;
+        }
+    }
+    Ok(s)
+}
+
+/// Receive a commitment from every player
+fn receive_commitments<F: Facilitator>(facilitator: &F) -> Result<Vec<Vec<u8>>, Error> {
+    let messages = facilitator.receive_from_all();
+    messages
+        .into_iter()
+        .enumerate()
+        .map(|(j, msg)| match msg {
+            OnlineMessage::ShareCommitment(commitment) => Ok(commitment),
+            _ => Err(Error::unexpected_message(j, "ShareCommitment", msg.name())),
+        })
+        .collect()
+}
+
+/// Receive the opening of every player's commitment, failing if any of them do not match
+fn receive_openings<F: Facilitator>(
+    facilitator: &F,
+    commitments: &[Vec<u8>],
+) -> Result<Vec<Vec<u8>>, Error> {
+    let messages = facilitator.receive_from_all();
+    messages
+        .into_iter()
+        .enumerate()
+        .map(|(j, msg)| match msg {
+            OnlineMessage::ShareCommitOpen(o_j) => open(commitments[j].clone(), o_j)
+                .map_err(|_| Error::InvalidCommitment { player: j }),
+            _ => Err(Error::unexpected_message(j, "ShareCommitOpen", msg.name())),
+        })
+        .collect()
+}
@@ -0,0 +1,659 @@
+//! Zero-knowledge proofs that decryption shares are computed from committed key shares.
+//!
+//! Every player commits to each of its key shares s in {sk_i1, sk_i2} with the pair
+//! K = (g_1 * s + rho_1, g_2 * s + rho_2), where g_1 and g_2 are sampled jointly and rho_1, rho_2
+//! are short. This hides the shares under RLWE. As the shares are uniform in Rq and not short, a
+//! single element g_1 * s + rho_1 would not bind the player: s - g_1^-1 * delta and rho_1 + delta
+//! open it as well for any short delta. Opening the pair to s' != s needs short rho_1', rho_2' with
+//! g_1 * (s - s') = rho_1' - rho_1 and g_2 * (s - s') = rho_2' - rho_2, which is a short solution
+//! of the module SIS instance given by g_1 and g_2.
+//!
+//! Along with its decryption share t_i = [c_0] + d_i of c, where d_i = w_i * (c_1 * sk_i1 +
+//! c_2 * sk_i2) + p * u_i, a player then proves that it knows the committed shares and a short
+//! u_i. As for the ZKPoPK, the challenges are 0 or monomials, which keep the norms of rho_i and u_i,
+//! and the short parts of the responses are rejection sampled.
+//!
+//! Nothing checks that the committed shares are the real key shares. A player that commits to
+//! other shares from the start is not caught, and its decryption shares pass every proof while the
+//! plaintext comes out wrong. The proofs only identify players that deviate from the shares they
+//! committed to.
+
+use rug::Integer;
+use serde::{Deserialize, Serialize};
+use sha2::digest::Update;
+use sha3::{
+    digest::{ExtendableOutput, XofReader},
+    Shake256,
+};
+
+use crate::{
+    encryption::{Ciphertext, Parameters},
+    error::Error,
+    mpc::{
+        commitment::common_seed,
+        gaussian_bound,
+        zk::{
+            add_monomial_multiple, below_bound, challenge_rows, coefficients, monomial_multiple,
+            sample_symmetric, ProofError, MAX_ATTEMPTS,
+        },
+        PlayerState, Sec,
+    },
+    poly::Polynomial,
+    polynomial,
+    prob::{sample_from_gaussian, sample_from_uniform, Prg},
+    protocol::{Facilitator, OnlineMessage},
+    serialization::polynomial_to_bytes,
+};
+
+/// The commitments of all players to their key shares
+#[derive(Clone, Debug)]
+pub struct KeyCommitments {
+    /// The public elements g_1 and g_2
+    g: [Polynomial; 2],
+    /// [K_j1, K_j2] of every player j, where each K_jl has an element for g_1 and one for g_2
+    commitments: Vec<[[Polynomial; 2]; 2]>,
+    /// Our randomness [rho_i1, rho_i2], in the same layout
+    rho_i: [[Polynomial; 2]; 2],
+    /// The statistical security parameter of the proofs
+    sec: Sec,
+}
+
+/// Proof that a decryption share was computed from the committed key shares
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecryptionProof {
+    /// First messages for the four elements of the commitments and the share
+    a: Vec<[Polynomial; 5]>,
+    /// Responses for the key shares, in Rq
+    z: Vec<[Polynomial; 2]>,
+    /// Responses for the commitment randomness
+    z_rho: Vec<[Vec<Integer>; 4]>,
+    /// Responses for the noise
+    z_u: Vec<Vec<Integer>>,
+}
+
+/// What a player proves about its part d_j of a decryption share
+struct Statement<'a> {
+    g: &'a [Polynomial; 2],
+    k: &'a [[Polynomial; 2]; 2],
+    /// The ciphertext, with 3 elements
+    c: &'a [Polynomial],
+    weight: &'a Integer,
+    d: &'a Polynomial,
+    sec: Sec,
+}
+
+/// Commits to our key shares, which makes `ddec` prove and verify every decryption share; all
+/// players must take part
+pub fn commit_key_shares<F: Facilitator>(
+    params: &Parameters,
+    state: &mut PlayerState<F>,
+) -> Result<(), Error> {
+    let rq = &params.quotient_ring;
+
+    let seed = common_seed(&state.facilitator)?;
+    let mut prg = Prg::new(&seed);
+    let g = [(); 2].map(|_| Polynomial::new(prg.sample_n_below(&rq.q, params.n)));
+
+    let rho_i = [(); 2].map(|_| [(); 2].map(|_| sample_from_gaussian(params.r, params.n)));
+    for (sk, rho) in [&state.sk_i1, &state.sk_i2].into_iter().zip(&rho_i) {
+        for (g_l, rho_l) in g.iter().zip(rho) {
+            let k = rq.add(&rq.mul(g_l, sk), rho_l);
+            state.facilitator.broadcast(&OnlineMessage::SharePoly(k));
+        }
+    }
+
+    let mut commitments = Vec::with_capacity(state.facilitator.player_count());
+    for j in 0..state.facilitator.player_count() {
+        let mut k_j = [
+            [polynomial![0], polynomial![0]],
+            [polynomial![0], polynomial![0]],
+        ];
+        for k in k_j.iter_mut().flatten() {
+            *k = receive_poly(state, j)?;
+        }
+        commitments.push(k_j);
+    }
+
+    state.key_commitments = Some(KeyCommitments {
+        g,
+        commitments,
+        rho_i,
+        sec: state.sec,
+    });
+    Ok(())
+}
+
+/// Proves that our decryption share part `d_i = weight * (c_1 * sk_i1 + c_2 * sk_i2) + p * u_i`
+/// of the 3-element ciphertext `c` is correct, where ||u_i|| <= `u_bound`
+pub(super) fn prove_decryption_share<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    key_commitments: &KeyCommitments,
+    c: &Ciphertext,
+    weight: &Integer,
+    (d_i, u_i): (&Polynomial, &Polynomial),
+    u_bound: &Integer,
+) -> Result<DecryptionProof, Error> {
+    let statement = Statement {
+        g: &key_commitments.g,
+        k: &key_commitments.commitments[state.facilitator.player_number()],
+        c,
+        weight,
+        d: d_i,
+        sec: key_commitments.sec,
+    };
+    let sk_i = [&state.sk_i1, &state.sk_i2];
+
+    for _ in 0..MAX_ATTEMPTS {
+        let proof = prove(
+            params,
+            &statement,
+            sk_i,
+            &key_commitments.rho_i,
+            u_i,
+            u_bound,
+        );
+        if let Some(proof) = proof {
+            return Ok(proof);
+        }
+    }
+    Err(Error::ProofAborted {
+        attempts: MAX_ATTEMPTS,
+    })
+}
+
+/// Verifies the proof of `player` for its decryption share part `d_j` of `c`
+pub(super) fn verify_decryption_share(
+    params: &Parameters,
+    key_commitments: &KeyCommitments,
+    player: usize,
+    c: &Ciphertext,
+    (weight, d_j): (&Integer, &Polynomial),
+    u_bound: &Integer,
+    proof: DecryptionProof,
+) -> Result<(), ProofError> {
+    let k = key_commitments
+        .commitments
+        .get(player)
+        .ok_or(ProofError::WrongLength)?;
+    let statement = Statement {
+        g: &key_commitments.g,
+        k,
+        c,
+        weight,
+        d: d_j,
+        sec: key_commitments.sec,
+    };
+    verify(params, &statement, proof, u_bound)
+}
+
+/// Bound on the noise of a decryption share part whose proof verifies, where honest players keep
+/// their noise below `u_bound`.
+///
+/// The verifier accepts noise responses up to z_u, which is larger than `u_bound`. From responses
+/// to two challenges W != W', only a witness for twice the share can be extracted, since
+/// 2 / (W - W') has coefficients in {-1, 0, 1}, so the noise of a cheating player is only known to
+/// be below 2 * N * z_u.
+pub(super) fn verified_u_bound(
+    params: &Parameters,
+    key_commitments: &KeyCommitments,
+    u_bound: &Integer,
+) -> Integer {
+    let sec = key_commitments.sec;
+    let bounds = Bounds::new(params, sec, u_bound, challenge_rows(params, sec, false));
+    bounds.z_u * (2 * params.n) as u64
+}
+
+fn prove(
+    params: &Parameters,
+    statement: &Statement,
+    sk: [&Polynomial; 2],
+    rho: &[[Polynomial; 2]; 2],
+    u: &Polynomial,
+    u_bound: &Integer,
+) -> Option<DecryptionProof> {
+    let rows = challenge_rows(params, statement.sec, false);
+    let bounds = Bounds::new(params, statement.sec, u_bound, rows);
+    let proof = respond(params, statement, sk, rho, u, &bounds);
+
+    // Rejection sampling: the accepted responses are uniform, whatever rho and u are
+    match first_long_response(&proof, &bounds) {
+        Some(_) => None,
+        None => Some(proof),
+    }
+}
+
+/// The first messages and the responses for the witness (sk, rho, u), before rejection sampling
+fn respond(
+    params: &Parameters,
+    statement: &Statement,
+    sk: [&Polynomial; 2],
+    rho: &[[Polynomial; 2]; 2],
+    u: &Polynomial,
+    bounds: &Bounds,
+) -> DecryptionProof {
+    let rq = &params.quotient_ring;
+    let n = params.n;
+    let rows = challenge_rows(params, statement.sec, false);
+
+    let mut a = Vec::with_capacity(rows);
+    let mut masks = Vec::with_capacity(rows);
+    for _ in 0..rows {
+        let y = [(); 2].map(|_| sample_from_uniform(&rq.q, n));
+        let y_rho = [(); 4].map(|_| sample_symmetric(&bounds.y_rho, n));
+        let y_u = sample_symmetric(&bounds.y_u, n);
+
+        a.push(images(params, statement, [&y[0], &y[1]], &y_rho, &y_u));
+        masks.push((y, y_rho, y_u));
+    }
+    let e = hash(params, statement, &a);
+
+    let witness_rho: Vec<Vec<Integer>> = rho
+        .iter()
+        .flatten()
+        .map(|rho| coefficients(rho, n))
+        .collect();
+    let witness_u = coefficients(u, n);
+
+    let mut z = Vec::with_capacity(rows);
+    let mut z_rho = Vec::with_capacity(rows);
+    let mut z_u = Vec::with_capacity(rows);
+    for (&e_k, (y, mut y_rho, mut y_u)) in e.iter().zip(masks) {
+        // The key shares are uniform in Rq, so y + e * sk hides them perfectly
+        let z_k = if e_k == 0 {
+            y
+        } else {
+            [
+                rq.add(&y[0], &monomial_multiple(params, sk[0], e_k)),
+                rq.add(&y[1], &monomial_multiple(params, sk[1], e_k)),
+            ]
+        };
+
+        for (y_rho_l, witness_rho_l) in y_rho.iter_mut().zip(&witness_rho) {
+            add_monomial_multiple(y_rho_l, witness_rho_l, e_k, n);
+        }
+        add_monomial_multiple(&mut y_u, &witness_u, e_k, n);
+
+        z.push(z_k);
+        z_rho.push(y_rho);
+        z_u.push(y_u);
+    }
+
+    DecryptionProof { a, z, z_rho, z_u }
+}
+
+/// The first row with a response of the short parts above its bound
+fn first_long_response(proof: &DecryptionProof, bounds: &Bounds) -> Option<usize> {
+    proof
+        .z_rho
+        .iter()
+        .zip(&proof.z_u)
+        .position(|(z_rho_k, z_u_k)| {
+            !z_rho_k.iter().all(|z| below_bound(z, &bounds.z_rho))
+                || !below_bound(z_u_k, &bounds.z_u)
+        })
+}
+
+fn verify(
+    params: &Parameters,
+    statement: &Statement,
+    proof: DecryptionProof,
+    u_bound: &Integer,
+) -> Result<(), ProofError> {
+    let rq = &params.quotient_ring;
+    let rows = challenge_rows(params, statement.sec, false);
+    let bounds = Bounds::new(params, statement.sec, u_bound, rows);
+
+    // Check the dimensions of the proof, so malformed proofs cannot make us index out of bounds
+    let DecryptionProof { a, z, z_rho, z_u } = &proof;
+    if a.len() != rows || z.len() != rows || z_rho.len() != rows || z_u.len() != rows {
+        return Err(ProofError::WrongLength);
+    }
+    let n = params.n;
+    if z_rho.iter().flatten().any(|z_rho_k| z_rho_k.len() != n) || z_u.iter().any(|z| z.len() != n)
+    {
+        return Err(ProofError::WrongLength);
+    }
+
+    let e = hash(params, statement, a);
+    let [[k_11, k_12], [k_21, k_22]] = statement.k;
+    let images_e = [k_11, k_12, k_21, k_22, statement.d];
+
+    // Check images(z) = a_k + e_k * (K_1, K_2, d) for every row
+    for (k, (((a_k, z_k), z_rho_k), z_u_k)) in a.iter().zip(z).zip(z_rho).zip(z_u).enumerate() {
+        let lhs = images(params, statement, [&z_k[0], &z_k[1]], z_rho_k, z_u_k);
+        for (j, (lhs_j, a_kj)) in lhs.iter().zip(a_k).enumerate() {
+            let rhs = if e[k] == 0 {
+                rq.add(a_kj, &polynomial![0])
+            } else {
+                rq.add(a_kj, &monomial_multiple(params, images_e[j], e[k]))
+            };
+            if *lhs_j != rhs {
+                return Err(ProofError::ShareMismatch(k));
+            }
+        }
+    }
+
+    if let Some(k) = first_long_response(&proof, &bounds) {
+        return Err(ProofError::NoiseTooBig(k));
+    }
+
+    Ok(())
+}
+
+/// The images (g_1 * s_1 + rho_11, g_2 * s_1 + rho_12, g_1 * s_2 + rho_21, g_2 * s_2 + rho_22,
+/// weight * (c_1 * s_1 + c_2 * s_2) + p * u) of the linear map the proof is about
+fn images(
+    params: &Parameters,
+    statement: &Statement,
+    [s_1, s_2]: [&Polynomial; 2],
+    [rho_11, rho_12, rho_21, rho_22]: &[Vec<Integer>; 4],
+    u: &[Integer],
+) -> [Polynomial; 5] {
+    let rq = &params.quotient_ring;
+    let Statement { g, c, weight, .. } = statement;
+
+    let commit = |g_l: &Polynomial, s: &Polynomial, rho: &Vec<Integer>| {
+        rq.add(&rq.mul(g_l, s), &Polynomial::new(rho.clone()))
+    };
+    let sum = rq.add(&rq.mul(&c[1], s_1), &rq.mul(&c[2], s_2));
+    let u = rq.times(&Polynomial::new(u.to_vec()), &params.p);
+    let d = rq.add(&rq.times(&sum, *weight), &u);
+    [
+        commit(&g[0], s_1, rho_11),
+        commit(&g[1], s_1, rho_12),
+        commit(&g[0], s_2, rho_21),
+        commit(&g[1], s_2, rho_22),
+        d,
+    ]
+}
+
+/// Hash the statement and the first messages to get the challenges, which are 0 or j > 0 for the
+/// monomial X^(j - 1)
+fn hash(params: &Parameters, statement: &Statement, a: &[[Polynomial; 5]]) -> Vec<usize> {
+    let mut hasher = Shake256::default();
+
+    let Statement {
+        g, k, c, weight, d, ..
+    } = statement;
+    let statement_pols = g.iter().chain(k.iter().flatten()).chain([*d]);
+    for pol in statement_pols.chain(c.iter()) {
+        hasher.update(&polynomial_to_bytes(params, pol));
+    }
+    hasher.update(&weight.to_digits::<u8>(rug::integer::Order::Lsf));
+    for pol in a.iter().flatten() {
+        hasher.update(&polynomial_to_bytes(params, pol));
+    }
+
+    let mut seed = [0_u8; 32];
+    hasher.finalize_xof().read(&mut seed);
+
+    let choices = Integer::from(2 * params.n + 1);
+    let rows = challenge_rows(params, statement.sec, false);
+    Prg::new(&seed)
+        .sample_n_below(&choices, rows)
+        .iter()
+        .map(|e_k| e_k.to_usize().unwrap())
+        .collect()
+}
+
+/// Bounds on the masks and the accepted responses of the short parts of the witness
+struct Bounds {
+    y_rho: Integer,
+    y_u: Integer,
+    z_rho: Integer,
+    z_u: Integer,
+}
+
+impl Bounds {
+    fn new(params: &Parameters, sec: Sec, u_bound: &Integer, rows: usize) -> Self {
+        let rho_bound = gaussian_bound(params);
+
+        // Each coefficient is rejected with probability at most 1 / (sec * coefficients), so a
+        // proof is rejected with probability about 2 / sec
+        let sec = sec.bits();
+        let y_rho = Integer::from(&rho_bound * (sec * 4 * params.n * rows) as u64);
+        let y_u = Integer::from(u_bound * (sec * params.n * rows) as u64);
+        let z_rho = Integer::from(&y_rho - &rho_bound);
+        let z_u = Integer::from(&y_u - u_bound);
+
+        Bounds {
+            y_rho,
+            y_u,
+            z_rho,
+            z_u,
+        }
+    }
+}
+
+fn receive_poly<F: Facilitator>(
+    state: &PlayerState<F>,
+    player: usize,
+) -> Result<Polynomial, Error> {
+    match state.facilitator.receive(player) {
+        OnlineMessage::SharePoly(pol) => Ok(pol),
+        msg => Err(Error::unexpected_message(player, "SharePoly", msg.name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{
+        encryption::{encrypt, generate_key_pair, mul, params_8degree},
+        mpc::{
+            ddec, ddec_among, ddec_noise_bound, smudging_bound,
+            testing::{additive_key_materials, channel_facilitators},
+            threshold::threshold_key_materials,
+        },
+        protocol::KeyMaterial,
+    };
+
+    use super::*;
+
+    /// An encryption of 6 * 7 under `pk`, with 3 elements so the shares of sk^2 are used as well
+    fn product_ciphertext(params: &Parameters, key_material: &KeyMaterial) -> Ciphertext {
+        let c_1 = encrypt(params, polynomial![6], &key_material.pk);
+        let c_2 = encrypt(params, polynomial![7], &key_material.pk);
+        mul(params, &c_1, &c_2)
+    }
+
+    #[test]
+    fn ddec_verifies_shares_of_committed_keys() {
+        let params = params_8degree();
+        let key_materials = additive_key_materials(&params, 3);
+        let c = product_ciphertext(&params, &key_materials[0]);
+
+        let results: Vec<Integer> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let (params, c) = (&params, c.clone());
+                    s.spawn(move || {
+                        let mut state = PlayerState::new(facilitator, key_material);
+                        commit_key_shares(params, &mut state).unwrap();
+                        ddec(params, &state, c).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results, vec![Integer::from(42); 3]);
+    }
+
+    #[test]
+    fn ddec_among_verifies_weighted_shares() {
+        let params = params_8degree();
+        let (pk, sk) = generate_key_pair(&params);
+        let key_materials = threshold_key_materials(&params, &pk, &sk, 1, 3).unwrap();
+        let c = product_ciphertext(&params, &key_materials[0]);
+
+        let results: Vec<Option<Integer>> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let (params, c) = (&params, c.clone());
+                    s.spawn(move || {
+                        let mut state = PlayerState::new_threshold(facilitator, key_material, 1);
+                        commit_key_shares(params, &mut state).unwrap();
+
+                        // Player 1 does not take part in the decryption
+                        let players = [0, 2];
+                        if players.contains(&state.facilitator.player_number()) {
+                            Some(ddec_among(params, &state, c, &players).unwrap())
+                        } else {
+                            None
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(
+            results,
+            vec![Some(Integer::from(42)), None, Some(Integer::from(42))]
+        );
+    }
+
+    #[test]
+    fn ddec_identifies_player_using_other_key_shares() {
+        let params = params_8degree();
+        let rq = &params.quotient_ring;
+        let key_materials = additive_key_materials(&params, 3);
+        let c = product_ciphertext(&params, &key_materials[0]);
+
+        let results: Vec<Result<Integer, Error>> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let (params, c) = (&params, c.clone());
+                    s.spawn(move || {
+                        let mut state = PlayerState::new(facilitator, key_material);
+                        commit_key_shares(params, &mut state).unwrap();
+
+                        // Player 1 shifts its share of sk after committing to it
+                        if state.facilitator.player_number() == 1 {
+                            state.sk_i1 = rq.add(&state.sk_i1, &polynomial![1]);
+                        }
+                        ddec(params, &state, c)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        for res in results {
+            assert!(matches!(
+                res,
+                Err(Error::ProofFailed {
+                    player: 1,
+                    reason: ProofError::ShareMismatch(_)
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn proof_rejects_equivocated_key_share() {
+        let params = params_8degree();
+        let rq = &params.quotient_ring;
+        let key_material = additive_key_materials(&params, 1).remove(0);
+        let c = product_ciphertext(&params, &key_material);
+        let (sk_1, sk_2) = (key_material.sk_i1, key_material.sk_i2);
+
+        // With g_1 = X, whose inverse is -X^(N - 1), s' = s + X^(N - 1) * delta and
+        // rho_1 + delta open g_1 * s + rho_1 for a short delta
+        let mut x = vec![Integer::ZERO; params.n];
+        x[1] = Integer::from(1);
+        let g = [Polynomial::new(x), sample_from_uniform(&rq.q, params.n)];
+        let rho = [(); 2].map(|_| [(); 2].map(|_| sample_from_gaussian(params.r, params.n)));
+        let commit = |s: &Polynomial, rho: &[Polynomial; 2]| {
+            [0, 1].map(|l| rq.add(&rq.mul(&g[l], s), &rho[l]))
+        };
+        let key_commitments = KeyCommitments {
+            commitments: vec![[commit(&sk_1, &rho[0]), commit(&sk_2, &rho[1])]],
+            g: g.clone(),
+            rho_i: rho.clone(),
+            sec: Sec::default(),
+        };
+
+        let delta = polynomial![1, -1, 1];
+        let mut x_n_minus_1 = vec![Integer::ZERO; params.n];
+        x_n_minus_1[params.n - 1] = Integer::from(1);
+        let sk_1_prime = rq.add(&sk_1, &rq.mul(&Polynomial::new(x_n_minus_1), &delta));
+        let rho_11_prime = rho[0][0].clone() + delta;
+        let [k_11, k_12] = &key_commitments.commitments[0][0];
+        assert_eq!(rq.add(&rq.mul(&g[0], &sk_1_prime), &rho_11_prime), *k_11);
+
+        // The element for g_2 can then only be opened with long randomness
+        let rho_12_prime = rq
+            .sub(k_12, &rq.mul(&g[1], &sk_1_prime))
+            .normalized_coefficients(&rq.q);
+        let rho_prime = [[rho_11_prime, rho_12_prime], rho[1].clone()];
+
+        // A wrong share computed from s' passes every check but the bounds
+        let weight = Integer::from(1);
+        let u_bound = Integer::from(1000);
+        let u = polynomial![0];
+        let sum = rq.add(&rq.mul(&c[1], &sk_1_prime), &rq.mul(&c[2], &sk_2));
+        let statement = Statement {
+            g: &key_commitments.g,
+            k: &key_commitments.commitments[0],
+            c: &c,
+            weight: &weight,
+            d: &sum,
+            sec: key_commitments.sec,
+        };
+        let rows = challenge_rows(&params, statement.sec, false);
+        let bounds = Bounds::new(&params, statement.sec, &u_bound, rows);
+        let proof = respond(
+            &params,
+            &statement,
+            [&sk_1_prime, &sk_2],
+            &rho_prime,
+            &u,
+            &bounds,
+        );
+
+        assert!(matches!(
+            verify_decryption_share(
+                &params,
+                &key_commitments,
+                0,
+                &c,
+                (&weight, &sum),
+                &u_bound,
+                proof
+            ),
+            Err(ProofError::NoiseTooBig(_))
+        ));
+    }
+
+    #[test]
+    fn ddec_refuses_q_too_small_for_verified_noise() {
+        // q fits the noise of honest shares, but not the larger noise a verified share may have
+        let base = params_8degree();
+        let noise_bound = ddec_noise_bound(&base, 1);
+        let u_bound = smudging_bound(&base, Sec::default(), &noise_bound, 1);
+        let q = (Integer::from(&base.p * &u_bound) + &noise_bound) * 4_i32 + 1_i32;
+        let params = Parameters::new(q, base.r, base.r_prime, base.n, base.p);
+
+        let key_material = additive_key_materials(&params, 1).remove(0);
+        let c = encrypt(&params, polynomial![5], &key_material.pk);
+        let facilitator = channel_facilitators(1).remove(0);
+        let mut state = PlayerState::new(facilitator, key_material);
+        assert_eq!(ddec(&params, &state, c.clone()).unwrap(), Integer::from(5));
+
+        commit_key_shares(&params, &mut state).unwrap();
+        assert!(matches!(
+            ddec(&params, &state, c),
+            Err(Error::NoiseOverflow { .. })
+        ));
+    }
+}
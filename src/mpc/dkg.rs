@@ -0,0 +1,250 @@
+//! Dealer-free key generation, replacing the dealer for Fkey_gen (fig. 2).
+//!
+//! The players jointly sample a0, and each player i samples s_i and e_i and publishes
+//! b_i = a0 * s_i + p * e_i, so that pk = (a0, sum_i b_i) is a public key for sk = sum_i s_i.
+//!
+//! Additive shares of sk^2 = sum_{i,j} s_i * s_j are obtained with an oblivious product for every
+//! pair of players: player i encrypts s_i under a key of its own whose plaintext modulus is q,
+//! player j multiplies the ciphertext by s_j, masks it with a random r_ij and floods the noise with
+//! `rerandomize`, and player i decrypts s_i * s_j - r_ij while player j keeps r_ij.
+//!
+//! The protocol is secure against passive adversaries only, as the players do not prove that they
+//! follow it.
+
+use rug::{ops::DivRounding, Integer};
+
+use crate::{
+    encryption::*,
+    error::Error,
+    mpc::{commitment::common_seed, gaussian_bound, Sec},
+    poly::Polynomial,
+    polynomial,
+    prob::{sample_from_gaussian, sample_from_uniform, Prg},
+    protocol::{Facilitator, KeyMaterial, OnlineMessage},
+    serialization::{
+        ciphertext_from_bytes, ciphertext_to_bytes, public_key_from_bytes, public_key_to_bytes,
+    },
+};
+
+/// Runs the key generation with all players, returning the public key and our shares of sk and sk^2.
+/// The noise of the oblivious products is flooded with statistical security `sec`.
+pub fn generate_keys<F: Facilitator>(
+    params: &Parameters,
+    sec: Sec,
+    facilitator: &F,
+) -> Result<KeyMaterial, Error> {
+    let rq = &params.quotient_ring;
+
+    let seed = common_seed(facilitator)?;
+    let a0 = Polynomial::new(Prg::new(&seed).sample_n_below(&rq.q, params.n));
+
+    let s_i = sample_from_gaussian(params.r, params.n);
+    let e_i = sample_from_gaussian(params.r, params.n);
+    let b_i = rq.add(&rq.mul(&a0, &s_i), &rq.times(&e_i, &params.p));
+
+    facilitator.broadcast(&OnlineMessage::SharePoly(b_i));
+    let mut b = polynomial![0];
+    for (j, msg) in facilitator.receive_from_all().into_iter().enumerate() {
+        match msg {
+            OnlineMessage::SharePoly(b_j) => b = rq.add(&b, &b_j),
+            _ => return Err(Error::unexpected_message(j, "SharePoly", msg.name())),
+        }
+    }
+
+    let sk_i2 = square_share(params, sec, facilitator, &s_i)?;
+
+    Ok(KeyMaterial {
+        pk: (a0, b),
+        sk_i1: s_i,
+        sk_i2,
+    })
+}
+
+/// Returns an additive share of (sum_i s_i)^2, given our summand `s_i`
+fn square_share<F: Facilitator>(
+    params: &Parameters,
+    sec: Sec,
+    facilitator: &F,
+    s_i: &Polynomial,
+) -> Result<Polynomial, Error> {
+    let rq = &params.quotient_ring;
+    let player_number = facilitator.player_number();
+    let others: Vec<usize> = (0..facilitator.player_count())
+        .filter(|&j| j != player_number)
+        .collect();
+
+    // Send a key of our own and the encryption of s_i under it to everybody
+    let product_params = product_params(params, sec);
+    let (pk_i, sk_i) = generate_key_pair(&product_params);
+    let c_i = encrypt(&product_params, s_i.modulo(&rq.q), &pk_i);
+    facilitator.broadcast(&OnlineMessage::SharePublicKey(public_key_to_bytes(
+        &product_params,
+        &pk_i,
+    )));
+    facilitator.broadcast(&OnlineMessage::ShareCiphertext(ciphertext_to_bytes(
+        &product_params,
+        &c_i,
+    )));
+
+    let mut pks = Vec::with_capacity(others.len() + 1);
+    for (j, msg) in facilitator.receive_from_all().into_iter().enumerate() {
+        match msg {
+            OnlineMessage::SharePublicKey(bytes) => pks.push(
+                public_key_from_bytes(&product_params, &bytes)
+                    .map_err(|e| Error::malformed(j, e))?,
+            ),
+            _ => return Err(Error::unexpected_message(j, "SharePublicKey", msg.name())),
+        }
+    }
+    let mut cs = Vec::with_capacity(others.len() + 1);
+    for (j, msg) in facilitator.receive_from_all().into_iter().enumerate() {
+        cs.push(receive_ciphertext(&product_params, j, msg)?);
+    }
+
+    let mut share = rq.mul(s_i, s_i);
+
+    // Send s_j * s_i - r_ji to every other player j, and keep r_ji
+    let noise_bound = product_noise_bound(params);
+    for &j in &others {
+        let r_ji = sample_from_uniform(&rq.q, params.n);
+        let product = mul(&product_params, &cs[j], &vec![s_i.clone()]);
+        let e_minus_r_ji = encrypt(&product_params, rq.neg(&r_ji), &pks[j]);
+        let masked = add(&product_params, &product, &e_minus_r_ji);
+        let flooded = rerandomize(
+            &product_params,
+            &masked,
+            &pks[j],
+            &noise_bound,
+            sec.bits() as u32,
+        )?;
+
+        let msg = OnlineMessage::ShareCiphertext(ciphertext_to_bytes(&product_params, &flooded));
+        facilitator.send(j, &msg);
+        share = rq.add(&share, &r_ji);
+    }
+
+    // Receive s_i * s_j - r_ij from every other player j
+    for &j in &others {
+        let c = receive_ciphertext(&product_params, j, facilitator.receive(j))?;
+        let product = decrypt(&product_params, c, &sk_i)?;
+        share = rq.add(&share, &product);
+    }
+
+    Ok(share)
+}
+
+fn receive_ciphertext(
+    params: &Parameters,
+    player: usize,
+    msg: OnlineMessage,
+) -> Result<Ciphertext, Error> {
+    match msg {
+        OnlineMessage::ShareCiphertext(bytes) => {
+            ciphertext_from_bytes(params, &bytes).map_err(|e| Error::malformed(player, e))
+        }
+        _ => Err(Error::unexpected_message(
+            player,
+            "ShareCiphertext",
+            msg.name(),
+        )),
+    }
+}
+
+/// Bound on the noise of Enc(s_i) * s_j + Enc(-r_ij) under `product_params`, assuming that all
+/// Gaussian samples are within `gaussian_bound`
+fn product_noise_bound(params: &Parameters) -> Integer {
+    let q = &params.quotient_ring.q;
+    let beta = gaussian_bound(params);
+    let n = Integer::from(params.n);
+
+    // A fresh encryption has noise m + q * (e0 * v + e'' - e' * sk)
+    let fresh = Integer::from(&n * &beta) * &beta * 2_i32 + &beta + 1_i32;
+    let fresh = fresh * q;
+
+    // Multiplying by s_j multiplies the noise by at most N * beta
+    Integer::from(&fresh * &n) * beta + fresh
+}
+
+/// Parameters with plaintext modulus q, and a ciphertext modulus large enough to flood the noise
+/// of the products with statistical security `sec`
+fn product_params(params: &Parameters, sec: Sec) -> Parameters {
+    let q = &params.quotient_ring.q;
+    let e_bound = product_noise_bound(params).div_ceil(q);
+    let k = (e_bound * Integer::from(8 * params.n)) << sec.bits() as u32;
+
+    // q * k + 1 is coprime to q
+    Parameters::new(
+        Integer::from(q * &k) + 1_i32,
+        params.r,
+        params.r_prime,
+        params.n,
+        q.clone(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::mpc::{ddec, testing::channel_facilitators, PlayerState};
+
+    use super::*;
+
+    #[test]
+    fn generated_keys_are_shares_of_key_pair() {
+        let params = params_8degree();
+        let rq = &params.quotient_ring;
+        let key_materials: Vec<KeyMaterial> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .map(|facilitator| {
+                    let params = &params;
+                    s.spawn(move || generate_keys(params, Sec::default(), &facilitator).unwrap())
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let pk = key_materials[0].pk.clone();
+        let mut sk = polynomial![0];
+        let mut sk_squared = polynomial![0];
+        for key_material in &key_materials {
+            assert_eq!(key_material.pk, pk);
+            sk = rq.add(&sk, &key_material.sk_i1);
+            sk_squared = rq.add(&sk_squared, &key_material.sk_i2);
+        }
+        assert_eq!(sk_squared, rq.mul(&sk, &sk));
+
+        let c = encrypt(&params, polynomial![3, 1], &pk);
+        assert_eq!(decrypt(&params, c, &sk).unwrap(), polynomial![3, 1]);
+    }
+
+    #[test]
+    fn ddec_works_with_generated_keys() {
+        let params = params_8degree();
+        let results: Vec<Integer> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .map(|facilitator| {
+                    let params = &params;
+                    s.spawn(move || {
+                        let key_material =
+                            generate_keys(params, Sec::default(), &facilitator).unwrap();
+
+                        // Every player needs the same ciphertext, so we encrypt deterministically
+                        let r = (polynomial![1], polynomial![1], polynomial![1]);
+                        let c_1 = encrypt_det(params, polynomial![6], &key_material.pk, r.clone());
+                        let c_2 = encrypt_det(params, polynomial![7], &key_material.pk, r);
+                        let c = mul(params, &c_1, &c_2);
+
+                        let state = PlayerState::new(facilitator, key_material);
+                        ddec(params, &state, c).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results, vec![Integer::from(42); 3]);
+    }
+}
@@ -1,22 +1,74 @@
 use rug::{ops::RemRounding, Integer};
 // use std::num::Float;
 
-use crate::{encryption::*, polynomial, protocol::KeyMaterial};
+use crate::{encryption::*, error::Error, polynomial, protocol::KeyMaterial};
 use crate::{poly::*, protocol::Facilitator};
 use crate::{prob::sample_from_uniform, protocol::OnlineMessage};
-use rug::ops::Pow;
+use decryption_proof::{DecryptionProof, KeyCommitments};
+use serde::{Deserialize, Serialize};
+use zk::ChallengeMode;
 
 pub mod commitment;
+pub mod decryption_proof;
+pub mod dkg;
 pub mod online;
 pub mod prep;
+pub mod refresh;
+pub mod store;
+#[cfg(test)]
+mod testing;
+pub mod threshold;
 pub mod zk;
 
 pub type Angle = Vec<Integer>;
 pub type AngleShare = (Integer, Integer);
 pub type MulTriple = (AngleShare, AngleShare, AngleShare);
+/// Shares of a random a and of a^2
+pub type SquarePair = (AngleShare, AngleShare);
+
+/// The statistical security parameter sec of a session, which all players must agree on.
+///
+/// A cheating prover passes a ZKPoPK or a decryption proof, a wrong opening passes MACCheck, or
+/// decryption shares reveal something about the key shares with probability about 2^-sec.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "usize", into = "usize")]
+pub struct Sec(usize);
+
+impl Sec {
+    /// The supported values of sec
+    pub const SUPPORTED: [usize; 4] = [40, 64, 80, 128];
 
-pub const SEC: usize = 40;
-pub const V: usize = 2 * SEC - 1;
+    pub fn new(sec: usize) -> Result<Sec, Error> {
+        if !Self::SUPPORTED.contains(&sec) {
+            return Err(Error::UnsupportedSecurityParameter(sec));
+        }
+        Ok(Sec(sec))
+    }
+
+    pub fn bits(self) -> usize {
+        self.0
+    }
+}
+
+impl Default for Sec {
+    fn default() -> Self {
+        Sec(40)
+    }
+}
+
+impl TryFrom<usize> for Sec {
+    type Error = Error;
+
+    fn try_from(sec: usize) -> Result<Sec, Error> {
+        Sec::new(sec)
+    }
+}
+
+impl From<Sec> for usize {
+    fn from(sec: Sec) -> usize {
+        sec.0
+    }
+}
 
 #[derive(Clone, Debug)]
 pub struct PlayerState<F: Facilitator> {
@@ -26,6 +78,11 @@ pub struct PlayerState<F: Facilitator> {
     alpha_i: Integer,    // global key share
     e_alpha: Ciphertext, // Encrypted global key
     opened: Vec<(Integer, Integer)>,
+    threshold: Option<usize>, // Set if the key shares are Shamir shares, see `threshold`
+    challenge_mode: ChallengeMode,
+    sec: Sec,
+    // Set once the players committed to their key shares, see `decryption_proof`
+    key_commitments: Option<KeyCommitments>,
     pub facilitator: F,
 }
 
@@ -38,10 +95,43 @@ impl<F: Facilitator> PlayerState<F> {
             alpha_i: Integer::ZERO,
             e_alpha: vec![],
             opened: vec![],
+            threshold: None,
+            challenge_mode: ChallengeMode::default(),
+            sec: Sec::default(),
+            key_commitments: None,
             facilitator,
         }
     }
 
+    /// Creates the state of a player holding Shamir shares of the secret key, such that any
+    /// `threshold + 1` players can decrypt
+    pub fn new_threshold(facilitator: F, key_material: KeyMaterial, threshold: usize) -> Self {
+        Self {
+            threshold: Some(threshold),
+            ..Self::new(facilitator, key_material)
+        }
+    }
+
+    /// Sets how the challenges of the ZKPoPKs in the preprocessing are chosen, which must be the
+    /// same for all players
+    pub fn set_challenge_mode(&mut self, challenge_mode: ChallengeMode) {
+        self.challenge_mode = challenge_mode;
+    }
+
+    /// Sets the statistical security parameter, which must be the same for all players
+    pub fn set_sec(&mut self, sec: Sec) {
+        self.sec = sec;
+    }
+
+    /// Returns our current key material, e.g. to store it after `refresh::refresh_key_shares`
+    pub fn key_material(&self) -> KeyMaterial {
+        KeyMaterial {
+            pk: self.pk.clone(),
+            sk_i1: self.sk_i1.clone(),
+            sk_i2: self.sk_i2.clone(),
+        }
+    }
+
     pub fn stop(self) {
         self.facilitator.stop()
     }
@@ -51,61 +141,303 @@ impl<F: Facilitator> PlayerState<F> {
 pub fn ddec<F: Facilitator>(
     params: &Parameters,
     state: &PlayerState<F>,
-    mut c: Ciphertext,
-) -> Integer {
-    let rq = &params.quotient_ring;
+    c: Ciphertext,
+) -> Result<Integer, Error> {
+    let players: Vec<usize> = (0..state.facilitator.player_count()).collect();
+    ddec_among(params, state, c, &players)
+}
+
+/// Distributed decryption run by `players`, who must all call this with the same set.
+///
+/// With additive key shares every player has to take part, with Shamir shares any `threshold + 1`
+/// players can decrypt by weighting their shares with their Lagrange coefficients. If the players
+/// committed to their key shares, every share comes with a proof that it is correct.
+pub fn ddec_among<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    c: Ciphertext,
+    players: &[usize],
+) -> Result<Integer, Error> {
+    let c = padded_ciphertext(c)?;
+    let (t_i, proof) = decryption_share(params, state, &c, players)?;
 
-    // Need to ensure that there are 3 elements (there can never be < 2)
-    if c.len() == 2 {
-        c.push(polynomial![0])
+    // Public decryption, every player in the set receives all shares
+    for &j in players {
+        send_decryption_share(state, j, &t_i, &proof);
     }
 
+    combine_decryption_shares(params, state, &c, players)
+}
+
+/// Distributed decryption where only `receiver` learns the plaintext, the other players get `None`.
+///
+/// Every player has to take part, also with Shamir shares.
+pub fn ddec_to<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    c: Ciphertext,
+    receiver: usize,
+) -> Result<Option<Integer>, Error> {
+    let player_count = state.facilitator.player_count();
+    if receiver >= player_count {
+        return Err(Error::InvalidPlayers(format!(
+            "receiver {} is not one of the {} players",
+            receiver, player_count
+        )));
+    }
+    let players: Vec<usize> = (0..player_count).collect();
+
+    let c = padded_ciphertext(c)?;
+    let (t_i, proof) = decryption_share(params, state, &c, &players)?;
+    send_decryption_share(state, receiver, &t_i, &proof);
+
+    if state.facilitator.player_number() != receiver {
+        return Ok(None);
+    }
+    combine_decryption_shares(params, state, &c, &players).map(Some)
+}
+
+/// Ensures that there are 3 elements, since we only have shares of sk and sk^2
+fn padded_ciphertext(mut c: Ciphertext) -> Result<Ciphertext, Error> {
+    match c.len() {
+        2 => c.push(polynomial![0]),
+        3 => {}
+        found => {
+            return Err(Error::CiphertextLengthMismatch { expected: 3, found });
+        }
+    }
+    Ok(c)
+}
+
+/// Computes our share t_i of the decryption of `c` by `players`, with noise to hide our key share,
+/// and the proof that it is correct if the players committed to their key shares
+fn decryption_share<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    c: &Ciphertext,
+    players: &[usize],
+) -> Result<(Polynomial, Option<DecryptionProof>), Error> {
+    let rq = &params.quotient_ring;
+    let player_number = state.facilitator.player_number();
+    let weight = share_weight(params, state, players, player_number)?;
+
+    // Flood our share with noise that hides sk_i, while the noise of all shares fits below q/2
+    let u_bound = decryption_smudging_bound(params, state, players)?;
+    let u = sample_from_uniform(&(Integer::from(2_i32 * &u_bound) + 1_i32), params.n)
+        - Polynomial::new(vec![u_bound.clone(); params.n]);
+
     let si1_ci1 = rq.mul(&state.sk_i1, &c[1]);
     let si2_ci2 = rq.mul(&state.sk_i2, &c[2]);
-    let sum = rq.add(&si1_ci1, &si2_ci2);
+    let sum = rq.times(&rq.add(&si1_ci1, &si2_ci2), &weight);
+    let d_i = rq.add(&sum, &rq.times(&u, &params.p));
 
-    let v_i = if state.facilitator.player_number() == 0 {
-        rq.add(&c[0], &sum)
+    let proof = match &state.key_commitments {
+        Some(key_commitments) => Some(decryption_proof::prove_decryption_share(
+            params,
+            state,
+            key_commitments,
+            c,
+            &weight,
+            (&d_i, &u),
+            &u_bound,
+        )?),
+        None => None,
+    };
+
+    let t_i = if player_number == players[0] {
+        rq.add(&c[0], &d_i)
     } else {
-        sum
+        d_i
+    };
+
+    Ok((t_i, proof))
+}
+
+/// The weight of the key shares of `player` in the decryption by `players`
+fn share_weight<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    players: &[usize],
+    player: usize,
+) -> Result<Integer, Error> {
+    match state.threshold {
+        Some(threshold) => {
+            if players.len() <= threshold {
+                return Err(Error::NotEnoughPlayers {
+                    required: threshold + 1,
+                    found: players.len(),
+                });
+            }
+            threshold::lagrange_coefficient(params, players, player)
+        }
+        None => {
+            let player_count = state.facilitator.player_count();
+            if players.len() < player_count {
+                return Err(Error::NotEnoughPlayers {
+                    required: player_count,
+                    found: players.len(),
+                });
+            }
+            Ok(Integer::from(1))
+        }
+    }
+}
+
+/// The bound on the smudging noise of every share in the decryption by `players`, failing if the
+/// noise of all shares would not fit below q/2.
+///
+/// If the players committed to their key shares, the shares are only known to have the larger
+/// noise that their proofs allow, so that noise has to fit instead.
+fn decryption_smudging_bound<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    players: &[usize],
+) -> Result<Integer, Error> {
+    let noise_bound = ddec_noise_bound(params, state.facilitator.player_count());
+    let u_bound = smudging_bound(params, state.sec, &noise_bound, players.len());
+    let share_bound = match &state.key_commitments {
+        Some(key_commitments) => {
+            decryption_proof::verified_u_bound(params, key_commitments, &u_bound)
+        }
+        None => u_bound.clone(),
+    };
+    let max_noise = Integer::from(&params.p * &share_bound) * players.len() as u64 + &noise_bound;
+    let q_half: Integer = (&params.quotient_ring.q / 2_i32).into();
+    if max_noise >= q_half {
+        return Err(Error::NoiseOverflow { norm: max_noise });
+    }
+    Ok(u_bound)
+}
+
+fn send_decryption_share<F: Facilitator>(
+    state: &PlayerState<F>,
+    player: usize,
+    t_i: &Polynomial,
+    proof: &Option<DecryptionProof>,
+) {
+    state
+        .facilitator
+        .send(player, &OnlineMessage::SharePoly(t_i.clone()));
+    if let Some(proof) = proof {
+        state
+            .facilitator
+            .send(player, &OnlineMessage::ShareDecryptionProof(proof.clone()));
+    }
+}
+
+/// Receives the decryption shares of `players`, verifies their proofs if the players committed to
+/// their key shares, and decodes the plaintext
+fn combine_decryption_shares<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    c: &Ciphertext,
+    players: &[usize],
+) -> Result<Integer, Error> {
+    let rq = &params.quotient_ring;
+
+    // The weights and the bound the proofs are checked with only depend on the set of players
+    let proof_checks = match &state.key_commitments {
+        Some(key_commitments) => {
+            let weights = players
+                .iter()
+                .map(|&j| share_weight(params, state, players, j))
+                .collect::<Result<Vec<_>, _>>()?;
+            let u_bound = decryption_smudging_bound(params, state, players)?;
+            Some((key_commitments, weights, u_bound))
+        }
+        None => None,
     };
 
-    let bound_c_m = 8.6;
-    let r_squared = params.r * params.r;
-    let n_squared = params.n * params.n;
-    let bound_b = &params.p / Integer::from(2_i32)
-        + &params.p
-            * Integer::from(
-                (4_f64 * bound_c_m * r_squared * (n_squared as f64)
-                    + 2_f64 * (params.n as f64).sqrt() * params.r
-                    + 4_f64 * bound_c_m * r_squared * (n_squared as f64)) as i64,
-            );
-    let two_exp_sec = Integer::from(2_i32).pow(SEC as u32);
-
-    let norm_bound =
-        two_exp_sec * bound_b / (Integer::from(state.facilitator.player_count()) * &params.p);
-
-    let t_i = rq.add(
-        &v_i,
-        &rq.times(&sample_from_uniform(&norm_bound, params.n), &params.p), // norm_bound is placeholder, since q needs to be a lot higher for this to work properly
-    );
-
-    // Assume public decryption
-    let msg = OnlineMessage::SharePoly(t_i);
-    state.facilitator.broadcast(&msg);
-
-    let messages = state.facilitator.receive_from_all();
-    let t_prime = messages
-        .into_iter()
-        .map(|msg| match msg {
+    let mut t_prime = polynomial![0];
+    for (i, &j) in players.iter().enumerate() {
+        let t_j = match state.facilitator.receive(j) {
             OnlineMessage::SharePoly(t_j) => t_j,
-            _ => panic!("expected SharePoly message, got {:?}", msg),
-        })
-        .fold(polynomial![0], |acc, elem| rq.add(&acc, &elem));
+            msg => return Err(Error::unexpected_message(j, "SharePoly", msg.name())),
+        };
+
+        if let Some((key_commitments, weights, u_bound)) = &proof_checks {
+            let proof = match state.facilitator.receive(j) {
+                OnlineMessage::ShareDecryptionProof(proof) => proof,
+                msg => {
+                    return Err(Error::unexpected_message(
+                        j,
+                        "ShareDecryptionProof",
+                        msg.name(),
+                    ))
+                }
+            };
+            let d_j = if j == players[0] {
+                rq.sub(&t_j, &c[0])
+            } else {
+                t_j.clone()
+            };
+            decryption_proof::verify_decryption_share(
+                params,
+                key_commitments,
+                j,
+                c,
+                (&weights[i], &d_j),
+                u_bound,
+                proof,
+            )
+            .map_err(|reason| Error::ProofFailed { player: j, reason })?;
+        }
+
+        t_prime = rq.add(&t_prime, &t_j);
+    }
 
     let msg_minus_q = t_prime.normalized_coefficients(&rq.q);
 
-    decode(msg_minus_q.modulo(&params.p))
+    Ok(decode(msg_minus_q.modulo(&params.p)))
+}
+
+/// Bound B on the noise of the ciphertexts decrypted by `ddec` in the preprocessing, with
+/// `player_count` players, assuming that all Gaussian samples are within `gaussian_bound`.
+///
+/// These are at most the product of two sums of `player_count` fresh ciphertexts (or of a fresh
+/// sum and a Reshare output), plus a sum of `player_count` fresh ciphertexts for the mask.
+pub fn ddec_noise_bound(params: &Parameters, player_count: usize) -> Integer {
+    let n = Integer::from(params.n);
+    let players = Integer::from(player_count);
+
+    // The Reshare output has noise of at most p plus that of the fresh sum
+    let sum = fresh_noise_bound(params, player_count) * &players + &params.p;
+
+    // Multiplying in Rq multiplies the norms and N
+    let product = Integer::from(&sum * &sum) * &n;
+    product + sum
+}
+
+/// Bound on the noise of a fresh encryption under a key shared by `player_count` players,
+/// assuming that all Gaussian samples are within `gaussian_bound`
+pub fn fresh_noise_bound(params: &Parameters, player_count: usize) -> Integer {
+    let beta = gaussian_bound(params);
+    let n = Integer::from(params.n);
+
+    // A fresh encryption has noise m + p * (e * v + e'' - e' * sk), where sk and e are sums of
+    // up to `player_count` Gaussians when the keys are generated without a dealer
+    let key_bound = Integer::from(player_count) * &beta;
+    let e_bound = Integer::from(&n * &key_bound) * &beta * 2_i32 + &beta;
+    Integer::from(&params.p / 2_i32) + e_bound * &params.p
+}
+
+/// Bound on the smudging noise each of `decrypting_players` adds to its decryption share, so that
+/// the shares of a ciphertext with noise at most `noise_bound` reveal nothing else than the
+/// plaintext (SPDZ fig. 3: ||r_i|| <= 2^sec * B / (n * p)).
+pub fn smudging_bound(
+    params: &Parameters,
+    sec: Sec,
+    noise_bound: &Integer,
+    decrypting_players: usize,
+) -> Integer {
+    let denominator = Integer::from(&params.p * decrypting_players as u64);
+    Integer::from(noise_bound << sec.bits() as u32) / denominator
+}
+
+/// Bound on Gaussian samples with the standard deviations of `params`, which holds except with
+/// negligible probability
+pub(crate) fn gaussian_bound(params: &Parameters) -> Integer {
+    Integer::from((10.0 * params.r.max(params.r_prime)).ceil() as u64)
 }
 
 pub fn open_shares(params: &Parameters, shares: Vec<Integer>) -> Integer {
@@ -129,6 +461,112 @@ pub fn diag(_params: &Parameters, a: Integer) -> Integer {
     a
 }
 
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::{testing::*, *};
+
+    #[test]
+    fn ddec_to_only_reveals_to_receiver() {
+        let params = params_8degree();
+        let key_materials = additive_key_materials(&params, 3);
+        let pk = key_materials[0].pk.clone();
+
+        let c_1 = encrypt(&params, polynomial![6], &pk);
+        let c_2 = encrypt(&params, polynomial![7], &pk);
+        let c = mul(&params, &c_1, &c_2);
+
+        let results: Vec<Option<Integer>> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let (params, c) = (&params, c.clone());
+                    s.spawn(move || {
+                        let state = PlayerState::new(facilitator, key_material);
+                        ddec_to(params, &state, c, 2).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results, vec![None, None, Some(Integer::from(42))]);
+    }
+
+    #[test]
+    fn ddec_to_rejects_unknown_receiver() {
+        let params = params_8degree();
+        let key_material = additive_key_materials(&params, 1).remove(0);
+        let c = encrypt(&params, polynomial![1], &key_material.pk);
+        let facilitator = channel_facilitators(1).remove(0);
+        let state = PlayerState::new(facilitator, key_material);
+
+        assert!(matches!(
+            ddec_to(&params, &state, c, 1),
+            Err(Error::InvalidPlayers(_))
+        ));
+    }
+
+    #[test]
+    fn ddec_noise_bound_covers_product_of_sums() {
+        let params = params_8degree();
+        let (pk, sk) = generate_key_pair(&params);
+
+        let sum = add_encrypted_shares(
+            &params,
+            (0..3)
+                .map(|_| encrypt(&params, sample_from_uniform(&params.p, params.n), &pk))
+                .collect(),
+        );
+        let c = mul(&params, &sum, &sum);
+
+        assert!(noise(&params, &c, &sk) <= ddec_noise_bound(&params, 3));
+    }
+
+    #[test]
+    fn ddec_refuses_too_small_q() {
+        let params = Parameters::default();
+        let key_material = additive_key_materials(&params, 1).remove(0);
+        let c = encrypt(&params, polynomial![1], &key_material.pk);
+        let facilitator = channel_facilitators(1).remove(0);
+        let state = PlayerState::new(facilitator, key_material);
+
+        assert!(matches!(
+            ddec(&params, &state, c),
+            Err(Error::NoiseOverflow { .. })
+        ));
+    }
+
+    #[test]
+    fn sec_rejects_unsupported_values() {
+        assert_eq!(Sec::new(64).unwrap().bits(), 64);
+        assert!(matches!(
+            Sec::new(41),
+            Err(Error::UnsupportedSecurityParameter(41))
+        ));
+
+        // Values received from other processes are validated as well
+        assert_eq!(serde_json::to_string(&Sec::default()).unwrap(), "40");
+        assert_eq!(
+            serde_json::from_str::<Sec>("80").unwrap(),
+            Sec::new(80).unwrap()
+        );
+        assert!(serde_json::from_str::<Sec>("48").is_err());
+    }
+
+    #[test]
+    fn smudging_bound_grows_with_sec() {
+        let params = params_8degree();
+        let noise_bound = ddec_noise_bound(&params, 3);
+        let sec_40 = smudging_bound(&params, Sec::default(), &noise_bound, 3);
+        let sec_80 = smudging_bound(&params, Sec::new(80).unwrap(), &noise_bound, 3);
+
+        assert!(sec_80 >= sec_40 << 40);
+    }
+}
+
 /*
 #[cfg(test)]
 mod tests {
@@ -2,15 +2,16 @@
 
 use crate::{
     encryption::Parameters,
+    error::Error,
     mpc::{
-        commitment::{commit, open},
-        open_shares, MulTriple,
+        commitment::{commit_and_open, common_seed},
+        open_shares, MulTriple, Sec, SquarePair,
     },
+    prob::Prg,
     protocol::{Facilitator, OnlineMessage},
 };
 
-use rand::Rng;
-use rug::{integer::Order, ops::RemRounding, rand::RandState, Integer};
+use rug::{integer::Order, ops::RemRounding, Integer};
 
 use super::{AngleShare, PlayerState};
 
@@ -28,7 +29,7 @@ pub mod protocol {
         x_i: Integer,
         r_pair: (Integer, AngleShare),
         state: &PlayerState<F>,
-    ) -> AngleShare {
+    ) -> Result<AngleShare, Error> {
         let player_number = state.facilitator.player_number();
 
         // Send intent to share x_i
@@ -36,7 +37,13 @@ pub mod protocol {
         state.facilitator.broadcast(&msg);
         match state.facilitator.receive(player_number) {
             OnlineMessage::BeginInput => {}
-            m => panic!("expected BeginInput message from myself, received {:?}", m),
+            m => {
+                return Err(Error::unexpected_message(
+                    player_number,
+                    "BeginInput",
+                    m.name(),
+                ))
+            }
         }
 
         // Send our share to ourselves
@@ -45,14 +52,7 @@ pub mod protocol {
         state.facilitator.send(player_number, &msg);
 
         // [[r]] is opened to P_i
-        let messages = state.facilitator.receive_from_all();
-        let r_shares = messages
-            .into_iter()
-            .map(|msg| match msg {
-                OnlineMessage::ShareInteger(r_share) => r_share,
-                _ => panic!("expected ShareInteger message, got {:?}", msg),
-            })
-            .collect();
+        let r_shares = receive_integers(state)?;
 
         let r = open_shares(params, r_shares);
 
@@ -64,23 +64,27 @@ pub mod protocol {
         let _ = state.facilitator.receive(player_number);
 
         if state.facilitator.player_number() == 0 {
-            return (
+            return Ok((
                 r_angle.0 + eps.clone(),
                 r_angle.1 + eps * state.alpha_i.clone(),
-            );
+            ));
         }
-        (r_angle.0, r_angle.1 + eps * state.alpha_i.clone())
+        Ok((r_angle.0, r_angle.1 + eps * state.alpha_i.clone()))
     }
 
     pub fn receive_input<F: Facilitator>(
         r_pair: (Integer, AngleShare),
         sharing_player: usize,
         state: &PlayerState<F>,
-    ) -> AngleShare {
+    ) -> Result<AngleShare, Error> {
         // Wait for sharing player to send BeginInput
         let msg = state.facilitator.receive(sharing_player);
         if !matches!(msg, OnlineMessage::BeginInput) {
-            panic!("expected BeginInput, got {:?}", msg)
+            return Err(Error::unexpected_message(
+                sharing_player,
+                "BeginInput",
+                msg.name(),
+            ));
         }
 
         // Share r with P_i
@@ -92,47 +96,47 @@ pub mod protocol {
         let msg = state.facilitator.receive(sharing_player);
         let eps = match msg {
             OnlineMessage::ShareInteger(eps) => eps,
-            _ => panic!("expected ShareInteger, received {:?}", msg),
+            _ => {
+                return Err(Error::unexpected_message(
+                    sharing_player,
+                    "ShareInteger",
+                    msg.name(),
+                ))
+            }
         };
 
         if state.facilitator.player_number() == 0 {
-            return (
+            return Ok((
                 r_angle.0 + eps.clone(),
                 r_angle.1 + eps * state.alpha_i.clone(),
-            );
+            ));
         }
-        (r_angle.0, r_angle.1 + eps * state.alpha_i.clone())
+        Ok((r_angle.0, r_angle.1 + eps * state.alpha_i.clone()))
     }
 
     pub fn add(x: &AngleShare, y: &AngleShare) -> AngleShare {
         ((&x.0 + &y.0).into(), (&x.1 + &y.1).into())
     }
 
+    /// Multiplies `x` and `y` with a triple from `prep::protocol::triples`, which has already been
+    /// checked, so only epsilon and delta are opened
     pub fn multiply<F: Facilitator>(
         params: &Parameters,
         x: AngleShare,
         y: AngleShare,
-        abc_triple: MulTriple,
-        fgh_triple: MulTriple,
-        t_share: Integer,
+        triple: MulTriple,
         state: &mut PlayerState<F>,
-    ) -> AngleShare {
-        let (a_angle, b_angle, c_angle) = abc_triple.clone();
-
-        // Check if ab = c in first triple by using the second triple
-        match triple_check(params, abc_triple, fgh_triple, t_share, state) {
-            Ok(()) => {}
-            Err(e) => panic!("Triple check did not succeed: {:?}", e),
-        }
+    ) -> Result<AngleShare, Error> {
+        let (a_angle, b_angle, c_angle) = triple;
 
         // Compute epsilon
         let epsilon_share = (x.0 - a_angle.clone().0, x.1 - a_angle.clone().1);
-        let epsilon = partial_opening(params, epsilon_share.0, state);
+        let epsilon = partial_opening(params, epsilon_share.0, state)?;
         state.opened.push((epsilon.clone(), epsilon_share.1));
 
         // Compute delta
         let delta_share = (y.0 - b_angle.clone().0, y.1 - b_angle.clone().1);
-        let delta = partial_opening(params, delta_share.0, state);
+        let delta = partial_opening(params, delta_share.0, state)?;
         state.opened.push((delta.clone(), delta_share.1));
 
         // Compute shares of result
@@ -147,17 +151,46 @@ pub mod protocol {
         }
         z_share.1 += epsilon * delta * state.alpha_i.clone();
 
-        z_share
+        Ok(z_share)
+    }
+
+    /// Squares `x` with a square pair from `prep::protocol::squares`, opening only epsilon
+    pub fn square<F: Facilitator>(
+        params: &Parameters,
+        x: AngleShare,
+        square_pair: SquarePair,
+        state: &mut PlayerState<F>,
+    ) -> Result<AngleShare, Error> {
+        let (a_angle, b_angle) = square_pair;
+
+        // Compute epsilon
+        let epsilon_share = (x.0 - a_angle.clone().0, x.1 - a_angle.clone().1);
+        let epsilon = partial_opening(params, epsilon_share.0, state)?;
+        state.opened.push((epsilon.clone(), epsilon_share.1));
+
+        // x^2 = (a + epsilon)^2 = b + 2 * epsilon * a + epsilon^2
+        let two_epsilon = epsilon.clone() * 2_i32;
+        let mut z_share = (
+            b_angle.0 + two_epsilon.clone() * a_angle.0,
+            b_angle.1 + two_epsilon * a_angle.1,
+        );
+
+        // Adding epsilon^2
+        let epsilon_squared = epsilon.square();
+        if state.facilitator.player_number() == 0 {
+            z_share.0 += epsilon_squared.clone();
+        }
+        z_share.1 += epsilon_squared * state.alpha_i.clone();
+
+        Ok(z_share)
     }
 
     pub fn output<F: Facilitator>(
         params: &Parameters,
         y_angle: AngleShare,
         state: &PlayerState<F>,
-    ) -> Integer {
-        if !maccheck(params, state.opened.clone(), state) {
-            panic!("MACCheck did not succeed!")
-        }
+    ) -> Result<Integer, Error> {
+        maccheck(params, state.opened.clone(), state)?;
 
         let (y_share, _) = y_angle;
         state
@@ -165,47 +198,117 @@ pub mod protocol {
             .broadcast(&OnlineMessage::ShareInteger(y_share));
 
         // Receive all broadcasted y shares
-        let messages = state.facilitator.receive_from_all();
-        let y_shares = messages
-            .into_iter()
-            .map(|msg| match msg {
-                OnlineMessage::ShareInteger(y_share) => y_share,
-                _ => panic!("expected ShareInteger message, got {:?}", msg),
-            })
-            .collect();
+        let y_shares = receive_integers(state)?;
 
         let y = open_shares(params, y_shares);
 
-        if !maccheck(params, vec![(y.clone(), y_angle.1)], state) {
-            panic!("MACCheck did not succeed!")
-        }
+        maccheck(params, vec![(y.clone(), y_angle.1)], state)?;
 
         // Broadcast my y_angle share
-        y
+        Ok(y)
     }
-}
 
-fn xor(x: &[u8], y: &[u8]) -> Vec<u8> {
-    x.iter().zip(y.iter()).map(|(&x, &y)| x ^ y).collect()
+    /// Number of pairs that `output_to` uses
+    pub fn output_to_pairs(params: &Parameters, sec: Sec) -> usize {
+        1 + maccheck_repetitions(params, sec)
+    }
+
+    /// Opens `y_angle` to `player` only, the other players get `None`.
+    ///
+    /// This is the input step in reverse: [[r]] of the first pair is opened to `player`, and the
+    /// players open eps = y - r, which is MAC checked together with the values opened so far. Then
+    /// `player` computes y = eps + r. The shares of r sent to `player` carry no MACs, so the
+    /// remaining pairs are opened to `player` as well, and the combinations r + t_k * s_k for
+    /// random t_k != 0 are opened to everyone and MAC checked. `player` fails with
+    /// `Error::MacCheckFailed` if they do not match the values it received, which catches a
+    /// wrong share of r except with probability 2^-sec.
+    ///
+    /// `r_pairs` must be `output_to_pairs` pairs that have not been used before.
+    pub fn output_to<F: Facilitator>(
+        params: &Parameters,
+        player: usize,
+        y_angle: AngleShare,
+        r_pairs: Vec<(Integer, AngleShare)>,
+        state: &PlayerState<F>,
+    ) -> Result<Option<Integer>, Error> {
+        let player_count = state.facilitator.player_count();
+        if player >= player_count {
+            return Err(Error::InvalidPlayers(format!(
+                "receiver {} is not one of the {} players",
+                player, player_count
+            )));
+        }
+        let pair_count = output_to_pairs(params, state.sec);
+        if r_pairs.len() != pair_count {
+            return Err(Error::NotEnoughPreprocessing {
+                kind: "pairs",
+                requested: pair_count,
+                available: r_pairs.len(),
+            });
+        }
+        let is_receiver = state.facilitator.player_number() == player;
+
+        // [[r]] and the [[s_k]] are opened to the receiving player, before the t_k are known
+        for (share, _) in &r_pairs {
+            state
+                .facilitator
+                .send(player, &OnlineMessage::ShareInteger(share.clone()));
+        }
+        let mut received = Vec::with_capacity(pair_count);
+        if is_receiver {
+            for _ in 0..pair_count {
+                received.push(open_shares(params, receive_integers(state)?));
+            }
+        }
+
+        let seed = common_seed(&state.facilitator)?;
+        let t: Vec<Integer> = Prg::new(&seed)
+            .sample_n_below(&Integer::from(&params.p - 1_i32), pair_count - 1)
+            .into_iter()
+            .map(|t_k| t_k + 1_i32)
+            .collect();
+
+        let mut r_angles = r_pairs.into_iter().map(|(_, angle)| angle);
+        let r_angle = r_angles.next().unwrap();
+        let mut to_check = state.opened.clone();
+
+        let eps_share = (y_angle.0 - &r_angle.0, y_angle.1 - &r_angle.1);
+        let eps = partial_opening(params, eps_share.0, state)?;
+        to_check.push((eps.clone(), eps_share.1));
+
+        let mut combinations = Vec::with_capacity(t.len());
+        for (t_k, s_angle) in t.iter().zip(r_angles) {
+            let u_share = (&r_angle.0 + t_k * s_angle.0, &r_angle.1 + t_k * s_angle.1);
+            let u = partial_opening(params, u_share.0, state)?;
+            combinations.push(u.clone());
+            to_check.push((u, u_share.1));
+        }
+        maccheck(params, to_check, state)?;
+
+        if !is_receiver {
+            return Ok(None);
+        }
+        let r = &received[0];
+        for ((t_k, s), u) in t.iter().zip(&received[1..]).zip(combinations) {
+            if Integer::from(r + t_k * s).rem_euc(&params.p) != u {
+                return Err(Error::MacCheckFailed);
+            }
+        }
+
+        Ok(Some((eps + r).rem_euc(&params.p)))
+    }
 }
 
-fn partial_opening<F: Facilitator>(
+pub(super) fn partial_opening<F: Facilitator>(
     params: &Parameters,
     to_share: Integer,
     state: &PlayerState<F>,
-) -> Integer {
+) -> Result<Integer, Error> {
     let msg = OnlineMessage::ShareInteger(to_share);
     // Need to send to a designated player, here we choose player 1, which has index 0
     state.facilitator.send(0, &msg);
     if state.facilitator.player_number() == 0 {
-        let messages = state.facilitator.receive_from_all();
-        let shares = messages
-            .into_iter()
-            .map(|msg| match msg {
-                OnlineMessage::ShareInteger(share) => share,
-                _ => panic!("expected ShareInteger message, got {:?}", msg),
-            })
-            .collect();
+        let shares = receive_integers(state)?;
 
         let result = open_shares(params, shares);
         let result_msg = OnlineMessage::ShareInteger(result);
@@ -214,202 +317,258 @@ fn partial_opening<F: Facilitator>(
 
     let msg = state.facilitator.receive(0);
     match msg {
-        OnlineMessage::ShareInteger(received) => received,
-        _ => panic!("expected ShareInteger message, got {:?}", msg),
+        OnlineMessage::ShareInteger(received) => Ok(received),
+        _ => Err(Error::unexpected_message(0, "ShareInteger", msg.name())),
     }
 }
 
-fn maccheck<F: Facilitator>(
+/// Receive an integer from every player
+fn receive_integers<F: Facilitator>(state: &PlayerState<F>) -> Result<Vec<Integer>, Error> {
+    let messages = state.facilitator.receive_from_all();
+    messages
+        .into_iter()
+        .enumerate()
+        .map(|(j, msg)| match msg {
+            OnlineMessage::ShareInteger(share) => Ok(share),
+            _ => Err(Error::unexpected_message(j, "ShareInteger", msg.name())),
+        })
+        .collect()
+}
+
+/// Checks the MACs of the opened values `to_check` with a random linear combination of them.
+///
+/// A wrong MAC passes one combination with probability about 2 / p, so the check is repeated with
+/// independent combinations until it fails with probability at least 1 - 2^-sec.
+pub(super) fn maccheck<F: Facilitator>(
     params: &Parameters,
     to_check: Vec<(Integer, Integer)>,
     state: &PlayerState<F>,
-) -> bool {
-    let amount_of_players = state.facilitator.player_count();
+) -> Result<(), Error> {
     let t = to_check.len();
+    let repetitions = maccheck_repetitions(params, state.sec);
+    let width = params.p.significant_digits::<u8>();
+
+    // Players jointly sample a seed s, and sample random vectors r using it (vectors of length t with elements generated uniformly modulo p)
+    let rng_seed = common_seed(&state.facilitator)?;
+    let mut prg = Prg::new(&rng_seed);
+
+    let mut sigma_i_bytes = Vec::with_capacity(repetitions * width);
+    for _ in 0..repetitions {
+        let r = prg.sample_n_below(&params.p, t);
+
+        // Each player computes a
+        let mut a = Integer::ZERO;
+        for j in 0..t {
+            let a_j = to_check[j].clone().0;
+            a = (a + r[j].clone() * a_j).rem_euc(&params.p);
+        }
 
-    // Sample seed, randomness, and commit to seed
-    let s_i = rand::thread_rng().gen::<[u8; 32]>().to_vec();
-    let r = rand::thread_rng().gen::<[u8; 32]>().to_vec(); // Hvor mange bytes?
-    commit(s_i.clone(), r.clone(), state);
-
-    // Store commitments
-    let messages = state.facilitator.receive_from_all();
-    let commitments: Vec<Vec<u8>> = messages
-        .into_iter()
-        .map(|msg| match msg {
-            OnlineMessage::ShareCommitment(commitment) => commitment,
-            _ => panic!("expected ShareCommitment message, got {:?}", msg),
-        })
-        .collect();
+        // Player i computes gamma_i and sigma_i
+        let mut gamma_i = Integer::ZERO;
+        for j in 0..t {
+            gamma_i = (gamma_i + r[j].clone() * to_check[j].clone().1).rem_euc(&params.p);
+        }
+        let sigma_i = (gamma_i - state.alpha_i.clone() * a).rem_euc(&params.p);
 
-    // This should actually have been a part of the commitment.rs module
-    let mut o = vec![];
-    o.extend(s_i);
-    o.extend(r);
-    state
-        .facilitator
-        .broadcast(&OnlineMessage::ShareCommitOpen(o));
+        // Every sigma_i takes `width` bytes, so that they can be split after opening
+        let digits = sigma_i.to_digits::<u8>(Order::MsfBe);
+        sigma_i_bytes.resize(sigma_i_bytes.len() + width - digits.len(), 0);
+        sigma_i_bytes.extend(digits);
+    }
 
-    // This should actually have been a part of the commitment.rs module
-    let messages = state.facilitator.receive_from_all();
-    let seeds: Vec<Vec<u8>> = messages
-        .into_iter()
-        .enumerate()
-        .map(|(i, msg)| match msg {
-            OnlineMessage::ShareCommitOpen(o_i) => {
-                let opened = open(commitments[i].clone(), o_i).unwrap();
-                opened.iter().take(32).cloned().collect()
-            }
-            _ => panic!("expected ShareCommitOpen message, got {:?}", msg),
-        })
-        .collect();
+    // Commit to all sigma_i and open them
+    let openings = commit_and_open(&state.facilitator, sigma_i_bytes)?;
+
+    // Sum the sigma_i's of every repetition and check that they all equal 0
+    let mut sigma_sums = vec![Integer::ZERO; repetitions];
+    for (j, opened) in openings.iter().enumerate() {
+        if opened.len() != repetitions * width {
+            return Err(Error::MalformedMessage {
+                player: j,
+                reason: format!(
+                    "expected {} bytes of sigma, got {}",
+                    repetitions * width,
+                    opened.len()
+                ),
+            });
+        }
+        for (sigma_sum, digits) in sigma_sums.iter_mut().zip(opened.chunks(width)) {
+            let sigma_j = Integer::from_digits(digits, Order::MsfBe);
+            *sigma_sum = (sigma_sum.clone() + sigma_j).rem_euc(&params.p);
+        }
+    }
 
-    // XOR seeds to get s
-    let mut s: Vec<u8> = seeds[0].clone();
-    for seed in seeds.iter().skip(1) {
-        s = xor(&s, seed);
+    if sigma_sums
+        .iter()
+        .any(|sigma_sum| *sigma_sum != Integer::ZERO)
+    {
+        return Err(Error::MacCheckFailed);
     }
+    Ok(())
+}
 
-    // Players sample random vector r using seed s (a vector of length n with elements generated uniformly modulo q)
-    let rng_seed: [u8; 32] = s
-        .as_slice()
-        .try_into()
-        .unwrap_or_else(|_| panic!("Expected seed length {}, got {}!", 32, s.len()));
+/// Number of independent linear combinations that MACCheck needs for statistical security `sec`
+fn maccheck_repetitions(params: &Parameters, sec: Sec) -> usize {
+    // Every combination fails with probability at least 1 - 2 / p >= 1 - 2^-(log2(p) - 2)
+    let bits_per_check = (params.p.significant_bits() as usize)
+        .saturating_sub(2)
+        .max(1);
+    sec.bits().div_ceil(bits_per_check)
+}
 
-    let rng_seed = Integer::from_digits(&rng_seed, Order::MsfBe);
+#[cfg(test)]
+mod tests {
+    use std::thread;
 
-    // TODO: This is not cryptographically secure, should make a custom RandGen
-    let mut rand_state = RandState::new();
-    rand_state.seed(&rng_seed);
+    use crate::{
+        encryption::params_8degree,
+        mpc::{
+            prep,
+            testing::{additive_key_materials, channel_facilitators},
+        },
+    };
 
-    let mut r = Vec::with_capacity(t);
-    for _ in 0..t {
-        let r_i = params.p.clone().random_below(&mut rand_state);
-        r.push(r_i);
-    }
+    use super::*;
 
-    // Each player computes a
-    let mut a = Integer::ZERO;
-    for j in 0..t {
-        let a_j = to_check[j].clone().0;
-        a = (a + r[j].clone() * a_j).rem_euc(&params.p);
+    /// Player 0 inputs 42 and the result is output to player 1. Player 2 may change its share of
+    /// the result and its pairs for the output with `tamper` first.
+    fn input_and_output_to(
+        params: &Parameters,
+        tamper: impl Fn(&mut AngleShare, &mut [(Integer, AngleShare)]) + Sync,
+    ) -> Vec<Result<Option<Integer>, Error>> {
+        let key_materials = additive_key_materials(params, 3);
+
+        thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let tamper = &tamper;
+                    s.spawn(move || {
+                        let mut state = PlayerState::new(facilitator, key_material);
+                        prep::protocol::initialize(params, &mut state).unwrap();
+                        let x_pair = prep::protocol::pair(params, &state).unwrap();
+                        let pair_count = protocol::output_to_pairs(params, state.sec);
+                        let mut r_pairs =
+                            prep::protocol::pairs(params, &state, pair_count).unwrap();
+
+                        let player_number = state.facilitator.player_number();
+                        let mut x_angle = if player_number == 0 {
+                            protocol::give_input(params, Integer::from(42), x_pair, &state)
+                        } else {
+                            protocol::receive_input(x_pair, 0, &state)
+                        }
+                        .unwrap();
+                        if player_number == 2 {
+                            tamper(&mut x_angle, &mut r_pairs);
+                        }
+                        protocol::output_to(params, 1, x_angle, r_pairs, &state)
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
     }
 
-    // Player i computes gamma_i and sigma_i
-    let mut gamma_i = Integer::ZERO;
-    for j in 0..t {
-        gamma_i = (gamma_i + r[j].clone() * to_check[j].clone().1).rem_euc(&params.p);
+    #[test]
+    fn square_of_input_is_output() {
+        let params = params_8degree();
+        let key_materials = additive_key_materials(&params, 3);
+
+        let results: Vec<Integer> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let params = &params;
+                    s.spawn(move || {
+                        let mut state = PlayerState::new(facilitator, key_material);
+                        prep::protocol::initialize(params, &mut state).unwrap();
+                        let x_pair = prep::protocol::pair(params, &state).unwrap();
+                        let square_pair = prep::protocol::square(params, &state).unwrap();
+
+                        let x_angle = if state.facilitator.player_number() == 0 {
+                            protocol::give_input(params, Integer::from(12), x_pair, &state)
+                        } else {
+                            protocol::receive_input(x_pair, 0, &state)
+                        }
+                        .unwrap();
+                        let y_angle =
+                            protocol::square(params, x_angle, square_pair, &mut state).unwrap();
+                        protocol::output(params, y_angle, &state).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        // 12^2 = 144 = 17 mod 127
+        assert_eq!(results, vec![Integer::from(17); 3]);
     }
-    let sigma_i = (gamma_i - state.alpha_i.clone() * a).rem_euc(&params.p);
 
-    // Convert sigma_i to bytes, sample randomness, and commit to sigma_i
-    let sigma_i_bytes = sigma_i.to_digits(Order::MsfBe);
-    let r = rand::thread_rng().gen::<[u8; 32]>().to_vec(); // Hvor mange bytes?
-    commit(sigma_i_bytes.clone(), r.clone(), state);
-
-    // Store commitments
-    let messages = state.facilitator.receive_from_all();
-    let sigma_commitments: Vec<Vec<u8>> = messages
-        .into_iter()
-        .map(|msg| match msg {
-            OnlineMessage::ShareCommitment(commitment) => commitment,
-            _ => panic!("expected ShareCommitment message, got {:?}", msg),
-        })
-        .collect();
-
-    // This should actually have been a part of the commitment.rs module
-    let mut o = vec![];
-    o.extend(sigma_i_bytes);
-    o.extend(r);
-    state
-        .facilitator
-        .broadcast(&OnlineMessage::ShareCommitOpen(o));
+    #[test]
+    fn output_to_only_reveals_to_receiver() {
+        let results: Vec<Option<Integer>> = input_and_output_to(&params_8degree(), |_, _| {})
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(results, vec![None, Some(Integer::from(42)), None]);
+    }
 
-    // This should actually have been a part of the commitment.rs module
-    let messages = state.facilitator.receive_from_all();
-    let sigma_is: Vec<Integer> = messages
-        .into_iter()
-        .enumerate()
-        .map(|(i, msg)| match msg {
-            OnlineMessage::ShareCommitOpen(o_i) => {
-                let opened = open(sigma_commitments[i].clone(), o_i).unwrap();
-                let digits: Vec<u8> = opened.iter().take(opened.len() - 32).cloned().collect();
-                Integer::from_digits(&digits, Order::MsfBe)
-            }
-            _ => panic!("expected ShareCommitOpen message, got {:?}", msg),
-        })
-        .collect();
+    #[test]
+    fn output_to_detects_tampered_share() {
+        // MACCheck misses an error with probability about 2 / p, so we use a large p
+        let base = params_8degree();
+        let params = Parameters::new(
+            base.quotient_ring.q,
+            base.r,
+            base.r_prime,
+            base.n,
+            Integer::from(2_305_843_009_213_693_951_u64), // 2^61 - 1
+        );
 
-    // Sum sigma_i's and check that this equals 0
-    let mut sigma_sum = Integer::ZERO;
-    for sigma_i in sigma_is.iter().take(amount_of_players) {
-        sigma_sum = (sigma_sum + sigma_i).rem_euc(&params.p);
+        for res in input_and_output_to(&params, |y_angle, _| y_angle.0 += 1) {
+            assert!(matches!(res, Err(Error::MacCheckFailed)));
+        }
     }
 
-    sigma_sum == Integer::ZERO
-}
+    #[test]
+    fn output_to_detects_tampered_share_with_small_p() {
+        // With p = 127 a single combination misses the error too often, so MACCheck repeats it
+        let params = params_8degree();
+        assert!(maccheck_repetitions(&params, Sec::default()) > 1);
 
-#[allow(dead_code)]
-#[derive(Debug)]
-enum TripleCheckErr {
-    ResultNotZero { result: Integer },
-}
+        for res in input_and_output_to(&params, |y_angle, _| y_angle.0 += 1) {
+            assert!(matches!(res, Err(Error::MacCheckFailed)));
+        }
+    }
 
-fn triple_check<F: Facilitator>(
-    params: &Parameters,
-    abc_triple: MulTriple,
-    fgh_triple: MulTriple,
-    t_share: Integer,
-    state: &mut PlayerState<F>,
-) -> Result<(), TripleCheckErr> {
-    let (a_angle, b_angle, c_angle) = abc_triple;
-    let (f_angle, g_angle, h_angle) = fgh_triple;
-
-    // Open t_bracket
-    let msg = OnlineMessage::ShareInteger(t_share);
-    state.facilitator.broadcast(&msg);
+    #[test]
+    fn output_to_detects_tampered_mask() {
+        // The receiver gets a wrong r, which the MACs of the opened values cannot show
+        let results = input_and_output_to(&params_8degree(), |_, r_pairs| r_pairs[0].0 += 1);
 
-    let messages = state.facilitator.receive_from_all();
-    let t_shares = messages
-        .into_iter()
-        .map(|msg| match msg {
-            OnlineMessage::ShareInteger(t_share) => t_share,
-            _ => panic!("expected ShareInteger message, got {:?}", msg),
-        })
-        .collect();
-    let t = open_shares(params, t_shares);
-
-    // Compute rho
-    let rho_share = (
-        t.clone() * a_angle.0 - f_angle.clone().0,
-        t.clone() * a_angle.1 - f_angle.clone().1,
-    );
-    let rho = partial_opening(params, rho_share.0, state);
-    state.opened.push((rho.clone(), rho_share.1));
-
-    // Compute sigma
-    let sigma_share = (b_angle.0 - g_angle.clone().0, b_angle.1 - g_angle.clone().1);
-    let sigma = partial_opening(params, sigma_share.0, state);
-    state.opened.push((sigma.clone(), sigma_share.1));
-
-    // Evaluate formula and check if zero as expected. If zero, then ab = c.
-    let mut zero_share = (
-        t.clone() * c_angle.0 - h_angle.0 - sigma.clone() * f_angle.0 - rho.clone() * g_angle.0,
-        t * c_angle.1 - h_angle.1 - sigma.clone() * f_angle.1 - rho.clone() * g_angle.1,
-    );
-
-    // Subtracting sigma * rho
-    if state.facilitator.player_number() == 0 {
-        zero_share.0 -= sigma.clone() * rho.clone();
+        assert!(matches!(results[0], Ok(None)));
+        assert!(matches!(results[1], Err(Error::MacCheckFailed)));
+        assert!(matches!(results[2], Ok(None)));
     }
-    zero_share.1 -= sigma * rho * state.alpha_i.clone();
 
-    let zero = partial_opening(params, zero_share.0, state);
-    state.opened.push((zero.clone(), zero_share.1));
-
-    //Check for 0
-    match zero == Integer::ZERO {
-        true => Ok(()),
-        false => Err(TripleCheckErr::ResultNotZero { result: zero }),
+    #[test]
+    fn maccheck_repetitions_grow_with_sec() {
+        let params = params_8degree();
+        let sec_40 = maccheck_repetitions(&params, Sec::default());
+        let sec_128 = maccheck_repetitions(&params, Sec::new(128).unwrap());
+        assert_eq!(sec_40, 8);
+        assert_eq!(sec_128, 26);
+
+        // One combination is enough when p alone exceeds 2^sec
+        let large_p = Parameters::new(
+            params.quotient_ring.q.clone(),
+            params.r,
+            params.r_prime,
+            params.n,
+            Integer::from(2_305_843_009_213_693_951_u64), // 2^61 - 1
+        );
+        assert_eq!(maccheck_repetitions(&large_p, Sec::default()), 1);
     }
-}
\ No newline at end of file
+}
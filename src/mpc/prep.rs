@@ -2,20 +2,27 @@
 
 use super::{
     add_encrypted_shares,
-    zk::{make_zkpopk, verify_zkpopk},
-    AngleShare, PlayerState, SEC,
+    commitment::common_seed,
+    online::{maccheck, partial_opening},
+    zk::{
+        challenge_from_seed, commit_zkpopk, respond_zkpopk, ChallengeMode, PlaintextKnowledgeProof,
+        ProofError, MAX_ATTEMPTS,
+    },
+    AngleShare, MulTriple, PlayerState, Sec, SquarePair,
 };
 
 use crate::{
     encryption::*,
+    error::Error,
     mpc::{ddec, diag},
     poly::Polynomial,
-    polynomial,
     prob::*,
     protocol::{Facilitator, OnlineMessage},
+    serialization::{ciphertext_from_bytes, ciphertext_to_bytes},
 };
 
-use rug::{ops::RemRounding, Integer};
+use rug::{integer::IsPrime, ops::RemRounding, Integer};
+use std::slice;
 
 pub enum Enc {
     NewCiphertext,
@@ -23,146 +30,405 @@ pub enum Enc {
 }
 
 /// Represents the preprocessing protocol (fig. 7)
+///
+/// The fresh ciphertexts of every step are proven in batches of sec ciphertexts with one ZKPoPK,
+/// so `pairs`, `triples`, `squares` and `bits` should be used to produce many at once. Triples and square
+/// pairs are checked by sacrificing others before they are returned, so the online phase can use
+/// them right away.
 pub mod protocol {
     use super::*;
 
     /// Implements the Initialize step
-    pub fn initialize<F: Facilitator>(params: &Parameters, state: &mut PlayerState<F>) {
+    pub fn initialize<F: Facilitator>(
+        params: &Parameters,
+        state: &mut PlayerState<F>,
+    ) -> Result<(), Error> {
         state.alpha_i = sample_single(&params.p);
-        let alpha_i_polynomial = encode(diag(params, state.alpha_i.clone()));
-        let (e_alpha_i, r_i) = encrypt_with_rand(params, alpha_i_polynomial.clone(), &state.pk);
-
-        let msg = OnlineMessage::ShareCiphertext(e_alpha_i.clone());
-        state.facilitator.broadcast(&msg);
-
-        let messages = state.facilitator.receive_from_all();
-        let e_alpha_is = messages
-            .into_iter()
-            .map(|msg| match msg {
-                OnlineMessage::ShareCiphertext(e_i) => e_i,
-                _ => panic!("expected ShareCiphertext message, got {:?}", msg),
-            })
-            .collect();
 
-        state.e_alpha = add_encrypted_shares(params, e_alpha_is);
+        let mut e_alpha_is =
+            encrypt_and_prove(params, state, slice::from_ref(&state.alpha_i), true)?;
+        state.e_alpha = add_encrypted_shares(params, e_alpha_is.remove(0));
 
-        run_zkpopk_for_single(params, state, alpha_i_polynomial, r_i, e_alpha_i);
+        Ok(())
     }
 
     /// Implements the Pair step
     pub fn pair<F: Facilitator>(
         params: &Parameters,
         state: &PlayerState<F>,
-    ) -> (Integer, AngleShare) {
-        let r_i = sample_single(&params.p);
-        let r_i_polynomial = encode(r_i.clone());
-        let (e_r_i, r_r_i) = encrypt_with_rand(params, r_i_polynomial.clone(), &state.pk);
+    ) -> Result<(Integer, AngleShare), Error> {
+        Ok(pairs(params, state, 1)?.remove(0))
+    }
+
+    /// Implements the Pair step `count` times, proving the ciphertexts of all of them together
+    pub fn pairs<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+        count: usize,
+    ) -> Result<Vec<(Integer, AngleShare)>, Error> {
+        let r_is = sample_values(params, count);
 
-        let msg = OnlineMessage::ShareCiphertext(e_r_i.clone());
-        state.facilitator.broadcast(&msg);
+        // One Reshare for each pair, in PAngle
+        let (e_r_iss, masks) = encrypt_with_masks(params, state, &r_is, count)?;
 
-        let messages = state.facilitator.receive_from_all();
-        let e_r_is: Vec<Vec<Polynomial>> = messages
-            .into_iter()
-            .map(|msg| match msg {
-                OnlineMessage::ShareCiphertext(e_i) => e_i,
-                _ => panic!("expected ShareCiphertext message, got {:?}", msg),
+        r_is.into_iter()
+            .zip(e_r_iss)
+            .zip(masks)
+            .map(|((r_i, e_r_is), mask)| {
+                let e_r = add_encrypted_shares(params, e_r_is);
+                let r_angle = p_angle(params, r_i.clone(), e_r, mask, state)?;
+                Ok((r_i, r_angle))
             })
-            .collect();
+            .collect()
+    }
+
+    /// Implements the Triple step
+    pub fn triple<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+    ) -> Result<(AngleShare, AngleShare, AngleShare), Error> {
+        Ok(triples(params, state, 1)?.remove(0))
+    }
+
+    /// Implements the Triple step `count` times, proving the ciphertexts of all of them together,
+    /// and checks the triples with the Sacrifice step
+    pub fn triples<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+        count: usize,
+    ) -> Result<Vec<MulTriple>, Error> {
+        let checks = sacrifices(params, state.sec, 1);
+        let mut triples = unchecked_triples(params, state, count * (1 + checks))?;
+        let spares = triples.split_off(count);
+        sacrifice(params, state, &triples, spares)?;
+        Ok(triples)
+    }
+
+    /// Implements the Triple step `count` times without checking the triples
+    pub(super) fn unchecked_triples<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+        count: usize,
+    ) -> Result<Vec<MulTriple>, Error> {
+        let a_is = sample_values(params, count);
+        let b_is = sample_values(params, count);
+        let a_b_is = [a_is.clone(), b_is.clone()].concat();
 
-        let e_r = add_encrypted_shares(params, e_r_is);
+        // Four Reshares for each triple, one for c and one in each PAngle
+        let (mut e_a_iss, masks) = encrypt_with_masks(params, state, &a_b_is, 4 * count)?;
+        let e_b_iss = e_a_iss.split_off(count);
+        let mut masks = masks.into_iter();
 
-        run_zkpopk_for_single(params, state, r_i_polynomial, r_r_i, e_r_i);
+        let mut triples = Vec::with_capacity(count);
+        for (((a_i, b_i), e_a_is), e_b_is) in a_is.into_iter().zip(b_is).zip(e_a_iss).zip(e_b_iss) {
+            let e_a = add_encrypted_shares(params, e_a_is);
+            let e_b = add_encrypted_shares(params, e_b_is);
+            let mut next_mask = || masks.next().unwrap();
 
-        let r_angle = p_angle(params, r_i.clone(), e_r, state);
-        (r_i, r_angle)
+            let a_angle = p_angle(params, a_i, e_a.clone(), next_mask(), state)?;
+            let b_angle = p_angle(params, b_i, e_b.clone(), next_mask(), state)?;
+            let e_c = mul(params, &e_a, &e_b);
+
+            let (e_c_prime_opt, c_i) =
+                reshare(params, &e_c, next_mask(), state, Enc::NewCiphertext)?;
+            let e_c_prime: Ciphertext = e_c_prime_opt.unwrap();
+
+            let c_angle = p_angle(params, c_i, e_c_prime, next_mask(), state)?;
+
+            triples.push((a_angle, b_angle, c_angle));
+        }
+
+        Ok(triples)
     }
 
-    /// Implements the Triple step
-    pub fn triple<F: Facilitator>(
+    /// Produces a square pair (<a>, <a^2>)
+    pub fn square<F: Facilitator>(
         params: &Parameters,
         state: &PlayerState<F>,
-    ) -> (AngleShare, AngleShare, AngleShare) {
-        let a_i = sample_single(&params.p);
-        let b_i = sample_single(&params.p);
-        let a_i_polynomial = encode(a_i.clone());
-        let (e_a_i, r_a_i) = encrypt_with_rand(params, a_i_polynomial.clone(), &state.pk);
-        let b_i_polynomial = encode(b_i.clone());
-        let (e_b_i, r_b_i) = encrypt_with_rand(params, b_i_polynomial.clone(), &state.pk);
-
-        let msg = OnlineMessage::ShareCiphertext(e_a_i.clone());
-        state.facilitator.broadcast(&msg);
+    ) -> Result<SquarePair, Error> {
+        Ok(squares(params, state, 1)?.remove(0))
+    }
 
-        let messages = state.facilitator.receive_from_all();
-        let e_a_is = messages
-            .into_iter()
-            .map(|msg| match msg {
-                OnlineMessage::ShareCiphertext(e_i) => e_i,
-                _ => panic!("expected ShareCiphertext message, got {:?}", msg),
-            })
-            .collect();
+    /// Produces `count` square pairs, proving the ciphertexts of all of them together, and checks
+    /// them by sacrificing others
+    pub fn squares<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+        count: usize,
+    ) -> Result<Vec<SquarePair>, Error> {
+        let checks = sacrifices(params, state.sec, 2);
+        let mut squares = unchecked_squares(params, state, count * (1 + checks))?;
+        let spares = squares.split_off(count);
+        sacrifice_squares(params, state, &squares, spares)?;
+        Ok(squares)
+    }
 
-        let msg = OnlineMessage::ShareCiphertext(e_b_i.clone());
-        state.facilitator.broadcast(&msg);
+    /// Produces `count` square pairs like the Triple step with b = a, without checking them
+    pub(super) fn unchecked_squares<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+        count: usize,
+    ) -> Result<Vec<SquarePair>, Error> {
+        let a_is = sample_values(params, count);
 
-        let messages = state.facilitator.receive_from_all();
-        let e_b_is = messages
-            .into_iter()
-            .map(|msg| match msg {
-                OnlineMessage::ShareCiphertext(e_i) => e_i,
-                _ => panic!("expected ShareCiphertext message, got {:?}", msg),
-            })
-            .collect();
+        // Three Reshares for each pair, one for a^2 and one in each PAngle
+        let (e_a_iss, masks) = encrypt_with_masks(params, state, &a_is, 3 * count)?;
+        let mut masks = masks.into_iter();
+
+        let mut squares = Vec::with_capacity(count);
+        for (a_i, e_a_is) in a_is.into_iter().zip(e_a_iss) {
+            let e_a = add_encrypted_shares(params, e_a_is);
+            let mut next_mask = || masks.next().unwrap();
 
-        let e_a = add_encrypted_shares(params, e_a_is);
-        let e_b = add_encrypted_shares(params, e_b_is);
+            let a_angle = p_angle(params, a_i, e_a.clone(), next_mask(), state)?;
+            let e_b = mul(params, &e_a, &e_a);
 
-        run_zkpopk_for_single(params, state, a_i_polynomial, r_a_i, e_a_i);
-        run_zkpopk_for_single(params, state, b_i_polynomial, r_b_i, e_b_i);
+            let (e_b_prime_opt, b_i) =
+                reshare(params, &e_b, next_mask(), state, Enc::NewCiphertext)?;
+            let e_b_prime: Ciphertext = e_b_prime_opt.unwrap();
 
-        let a_angle = p_angle(params, a_i, e_a.clone(), state);
-        let b_angle = p_angle(params, b_i, e_b.clone(), state);
-        let e_c = mul(params, &e_a, &e_b);
+            let b_angle = p_angle(params, b_i, e_b_prime, next_mask(), state)?;
 
-        let (e_c_prime_opt, c_i) = reshare(params, &e_c, state, Enc::NewCiphertext);
-        let e_c_prime: Ciphertext = e_c_prime_opt.unwrap();
+            squares.push((a_angle, b_angle));
+        }
 
-        let c_angle = p_angle(params, c_i, e_c_prime, state);
+        Ok(squares)
+    }
 
-        (a_angle, b_angle, c_angle)
+    /// Produces a shared random bit <b>
+    pub fn bit<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+    ) -> Result<AngleShare, Error> {
+        Ok(bits(params, state, 1)?.remove(0))
+    }
+
+    /// Produces `count` shared random bits, which requires p to be an odd prime.
+    ///
+    /// For every square pair (<a>, <a^2>), a^2 is opened and the MACs are checked. Then for a
+    /// square root r of a^2, a / r is 1 or -1 with probability 1/2, and b = (a / r + 1) / 2 is a
+    /// random bit, unknown to the players. Pairs with a = 0 are discarded.
+    pub fn bits<F: Facilitator>(
+        params: &Parameters,
+        state: &PlayerState<F>,
+        count: usize,
+    ) -> Result<Vec<AngleShare>, Error> {
+        let p = &params.p;
+        if p.is_even() || p.is_probably_prime(30) == IsPrime::No {
+            return Err(Error::PlaintextModulusNotOddPrime(p.clone()));
+        }
+        let half = Integer::from(p + 1) >> 1_u32;
+        let is_first = state.facilitator.player_number() == 0;
+
+        let mut bits = Vec::with_capacity(count);
+        while bits.len() < count {
+            let squares = squares(params, state, count - bits.len())?;
+
+            let mut opened = Vec::with_capacity(squares.len());
+            for (_, b_angle) in &squares {
+                let s = partial_opening(params, b_angle.0.clone(), state)?;
+                opened.push((s, b_angle.1.clone()));
+            }
+            maccheck(params, opened.clone(), state)?;
+
+            for ((a_angle, _), (s, _)) in squares.into_iter().zip(opened) {
+                let s = s.rem_euc(p);
+                if s == Integer::ZERO {
+                    continue;
+                }
+                let r = sqrt_mod(&s, p).ok_or(Error::SquareCheckFailed { result: s })?;
+                let r_inverse = r.invert(p).unwrap();
+
+                // <b> = (r^-1 * <a> + 1) / 2
+                let mut b_angle = (r_inverse.clone() * a_angle.0, r_inverse * a_angle.1);
+                if is_first {
+                    b_angle.0 += 1;
+                }
+                b_angle.1 += &state.alpha_i;
+                bits.push((
+                    (b_angle.0 * &half).rem_euc(p),
+                    (b_angle.1 * &half).rem_euc(p),
+                ));
+            }
+        }
+
+        Ok(bits)
     }
 }
 
-/// Implements Protocol Reshare (fig. 4)
-fn reshare<F: Facilitator>(
+/// Implements the Sacrifice step: checks that a * b = c for every triple in `triples`, using the
+/// same number of `spares` for each of them, which must not be used afterwards.
+///
+/// For a jointly random t and a spare (f, g, h), the players open rho = t * a - f and sigma = b - g
+/// and check that t * c - h - sigma * f - rho * g - sigma * rho opens to 0, which happens with
+/// probability at most 1 / p if either triple is wrong. The MACs of all opened values are checked
+/// as well.
+fn sacrifice<F: Facilitator>(
     params: &Parameters,
-    e_m: &Ciphertext,
     state: &PlayerState<F>,
-    enc: Enc,
-) -> (Option<Ciphertext>, Integer) {
-    let f_i = sample_single(&params.p);
-    let f_i_polynomial = encode(f_i.clone());
-    let (e_f_i, r_f_i) = encrypt_with_rand(params, f_i_polynomial.clone(), &state.pk);
+    triples: &[MulTriple],
+    spares: Vec<MulTriple>,
+) -> Result<(), Error> {
+    let checks = spares.len() / triples.len().max(1);
+    let t = Prg::new(&common_seed(&state.facilitator)?).sample_n_below(&params.p, spares.len());
+    let is_first = state.facilitator.player_number() == 0;
 
-    let msg = OnlineMessage::ShareCiphertext(e_f_i.clone());
-    state.facilitator.broadcast(&msg);
+    let mut opened = Vec::with_capacity(3 * spares.len());
+    for (k, ((f_angle, g_angle, h_angle), t)) in spares.into_iter().zip(t).enumerate() {
+        let (a_angle, b_angle, c_angle) = &triples[k / checks];
 
-    let messages = state.facilitator.receive_from_all();
-    let e_f_is: Vec<Vec<Polynomial>> = messages
-        .into_iter()
-        .map(|msg| match msg {
-            OnlineMessage::ShareCiphertext(e_i) => e_i,
-            _ => panic!("expected ShareCiphertext message, got {:?}", msg),
-        })
-        .collect();
+        let rho_share = (
+            t.clone() * &a_angle.0 - &f_angle.0,
+            t.clone() * &a_angle.1 - &f_angle.1,
+        );
+        let rho = partial_opening(params, rho_share.0, state)?;
+        opened.push((rho.clone(), rho_share.1));
 
-    run_zkpopk_for_single(params, state, f_i_polynomial, r_f_i, e_f_i);
+        let sigma_share = (
+            Integer::from(&b_angle.0 - &g_angle.0),
+            Integer::from(&b_angle.1 - &g_angle.1),
+        );
+        let sigma = partial_opening(params, sigma_share.0, state)?;
+        opened.push((sigma.clone(), sigma_share.1));
 
-    let e_f = add_encrypted_shares(params, e_f_is.clone());
-    let e_m_plus_f = add(params, e_m, &e_f);
+        let mut zero_share = (
+            t.clone() * &c_angle.0
+                - h_angle.0
+                - sigma.clone() * f_angle.0
+                - rho.clone() * g_angle.0,
+            t * &c_angle.1 - h_angle.1 - sigma.clone() * f_angle.1 - rho.clone() * g_angle.1,
+        );
+        if is_first {
+            zero_share.0 -= sigma.clone() * &rho;
+        }
+        zero_share.1 -= sigma * rho * &state.alpha_i;
+
+        let zero = partial_opening(params, zero_share.0, state)?;
+        if zero != Integer::ZERO {
+            return Err(Error::TripleCheckFailed { result: zero });
+        }
+        opened.push((zero, zero_share.1));
+    }
+
+    maccheck(params, opened, state)
+}
+
+/// Checks that b = a^2 for every square pair (a, b) in `squares` like `sacrifice`, using the same
+/// number of `spares` for each of them, which must not be used afterwards.
+///
+/// For a jointly random t and a spare (f, h), the players open rho = t * a - f and check that
+/// t^2 * b - h - rho * (t * a + f) opens to 0, which happens with probability at most 2 / p if
+/// either pair is wrong.
+fn sacrifice_squares<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    squares: &[SquarePair],
+    spares: Vec<SquarePair>,
+) -> Result<(), Error> {
+    let checks = spares.len() / squares.len().max(1);
+    let t = Prg::new(&common_seed(&state.facilitator)?).sample_n_below(&params.p, spares.len());
+
+    let mut opened = Vec::with_capacity(2 * spares.len());
+    for (k, ((f_angle, h_angle), t)) in spares.into_iter().zip(t).enumerate() {
+        let (a_angle, b_angle) = &squares[k / checks];
+
+        let rho_share = (
+            t.clone() * &a_angle.0 - &f_angle.0,
+            t.clone() * &a_angle.1 - &f_angle.1,
+        );
+        let rho = partial_opening(params, rho_share.0, state)?;
+        opened.push((rho.clone(), rho_share.1));
+
+        // (t * a)^2 - f^2 = rho * (t * a + f)
+        let t_squared = t.clone().square();
+        let zero_share = (
+            t_squared.clone() * &b_angle.0
+                - h_angle.0
+                - rho.clone() * (t.clone() * &a_angle.0 + f_angle.0),
+            t_squared * &b_angle.1 - h_angle.1 - rho * (t * &a_angle.1 + f_angle.1),
+        );
+
+        let zero = partial_opening(params, zero_share.0, state)?;
+        if zero != Integer::ZERO {
+            return Err(Error::SquareCheckFailed { result: zero });
+        }
+        opened.push((zero, zero_share.1));
+    }
+
+    maccheck(params, opened, state)
+}
+
+/// Number of spares sacrificed for each triple or square pair, so that a wrong one passes with
+/// probability at most 2^-sec, when one sacrifice lets it pass with probability at most
+/// `degree / p`
+fn sacrifices(params: &Parameters, sec: Sec, degree: usize) -> usize {
+    // p >= 2^(bits - 1), so degree / p <= 2^-(bits - 1 - ceil(log2(degree)))
+    let degree_bits = degree.next_power_of_two().trailing_zeros() + 1;
+    let bits_per_check = params.p.significant_bits().saturating_sub(degree_bits) as usize;
+    sec.bits().div_ceil(bits_per_check.max(1))
+}
 
-    let m_plus_f = ddec(params, state, e_m_plus_f);
+/// Returns a square root of `s` modulo the odd prime `p`, if `s` is a square (Tonelli-Shanks)
+fn sqrt_mod(s: &Integer, p: &Integer) -> Option<Integer> {
+    let s = s.clone().rem_euc(p);
+    if s == Integer::ZERO {
+        return Some(s);
+    }
+    if s.legendre(p) != 1 {
+        return None;
+    }
+
+    // p - 1 = q * 2^e with q odd
+    let p_minus_one = Integer::from(p - 1);
+    let e = p_minus_one.find_one(0).unwrap();
+    let q = p_minus_one >> e;
+
+    let mut z = Integer::from(2);
+    while z.legendre(p) != -1 {
+        z += 1;
+    }
+
+    let mut m = e;
+    let mut c = z.pow_mod(&q, p).unwrap();
+    let mut t = s.clone().pow_mod(&q, p).unwrap();
+    let mut r = s.pow_mod(&(Integer::from(&q + 1) >> 1), p).unwrap();
+    while t != 1 {
+        // The least i with t^(2^i) = 1, which is below m
+        let mut i = 0;
+        let mut t_power = t.clone();
+        while t_power != 1 {
+            t_power = t_power.square().rem_euc(p);
+            i += 1;
+        }
 
+        let b = c.pow_mod(&(Integer::from(1) << (m - i - 1)), p).unwrap();
+        m = i;
+        c = b.clone().square().rem_euc(p);
+        t = (t * &c).rem_euc(p);
+        r = (r * b).rem_euc(p);
+    }
+    Some(r)
+}
+
+/// A random f, of which we know the share `f_i`, and its encryption `e_f`, used by Reshare to
+/// mask the ciphertext it decrypts
+struct Mask {
+    f_i: Integer,
+    e_f: Ciphertext,
+}
+
+/// Implements Protocol Reshare (fig. 4)
+fn reshare<F: Facilitator>(
+    params: &Parameters,
+    e_m: &Ciphertext,
+    mask: Mask,
+    state: &PlayerState<F>,
+    enc: Enc,
+) -> Result<(Option<Ciphertext>, Integer), Error> {
+    let Mask { f_i, e_f } = mask;
+    let e_m_plus_f = add(params, e_m, &e_f);
+
+    let m_plus_f = ddec(params, state, e_m_plus_f)?;
     let m_i = if state.facilitator.player_number() == 0 {
         (m_plus_f.clone() - f_i).rem_euc(&params.p)
     } else {
@@ -170,24 +436,16 @@ fn reshare<F: Facilitator>(
     };
 
     if matches!(enc, Enc::NewCiphertext) {
-        let mut e_m_prime = encrypt_det(
-            params,
-            encode(m_plus_f),
-            &state.pk,
-            (polynomial![1], polynomial![1], polynomial![1]),
-        );
-        for e_f_i in e_f_is {
-            e_m_prime = add(
-                params,
-                &e_m_prime,
-                &(e_f_i.iter().map(|e| -(e.clone())).collect()),
-            );
-        }
-        return (Some(e_m_prime), m_i);
+        // Enc(m + f) - Enc(f), so e_m_prime only has the noise of the fresh encryptions of f_i
+        let rq = &params.quotient_ring;
+        let e_m_plus_f_trivial = encrypt_trivial(params, encode(m_plus_f));
+        let neg_e_f: Ciphertext = e_f.iter().map(|e| rq.neg(e)).collect();
+        let e_m_prime = add(params, &e_m_plus_f_trivial, &neg_e_f);
+        return Ok((Some(e_m_prime), m_i));
     }
 
     // Player P_i is supposed to get m_is[i]
-    (None, m_i)
+    Ok((None, m_i))
 }
 
 /// Implements Protocol PAngle (fig. 6)
@@ -195,52 +453,500 @@ fn p_angle<F: Facilitator>(
     params: &Parameters,
     v_i: Integer,
     e_v: Ciphertext,
+    mask: Mask,
     player_state: &PlayerState<F>,
-) -> AngleShare {
+) -> Result<AngleShare, Error> {
     // Each player does the following:
     let e_v_mul_alpha = mul(params, &e_v, &player_state.e_alpha);
-    let (_, gamma_i) = reshare(params, &e_v_mul_alpha, player_state, Enc::NoNewCiphertext); // each player Pi gets a share γi of α·v
+    // each player Pi gets a share γi of α·v
+    let (_, gamma_i) = reshare(
+        params,
+        &e_v_mul_alpha,
+        mask,
+        player_state,
+        Enc::NoNewCiphertext,
+    )?;
     let v_angle: AngleShare = (v_i, gamma_i);
-    v_angle
+    Ok(v_angle)
 }
 
-fn run_zkpopk_for_single<F: Facilitator>(
+/// Samples `count` values modulo p
+fn sample_values(params: &Parameters, count: usize) -> Vec<Integer> {
+    (0..count).map(|_| sample_single(&params.p)).collect()
+}
+
+/// Encrypts and proves our `values` together with the shares of `mask_count` masks for Reshare
+///
+/// Returns the ciphertexts of all players for each value, and the masks.
+fn encrypt_with_masks<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    values: &[Integer],
+    mask_count: usize,
+) -> Result<(Vec<Vec<Ciphertext>>, Vec<Mask>), Error> {
+    let f_is = sample_values(params, mask_count);
+
+    let mut e_iss = encrypt_and_prove(params, state, &[values, &f_is].concat(), false)?;
+    let e_f_iss = e_iss.split_off(values.len());
+
+    let masks = f_is
+        .into_iter()
+        .zip(e_f_iss)
+        .map(|(f_i, e_f_is)| Mask {
+            f_i,
+            e_f: add_encrypted_shares(params, e_f_is),
+        })
+        .collect();
+    Ok((e_iss, masks))
+}
+
+/// Encrypts our `values`, and sends the ciphertexts to all players with a ZKPoPK for each batch
+/// of sec of them. The last batch is padded with encryptions of 0.
+///
+/// Returns the ciphertexts of all players for each value, once all proofs are verified.
+fn encrypt_and_prove<F: Facilitator>(
     params: &Parameters,
     state: &PlayerState<F>,
-    x_i: Polynomial,
-    r_i: (Polynomial, Polynomial, Polynomial),
-    c_i: Ciphertext,
-) {
-    // Create own ZKPoPK
-    // We are running the protocol on (x_i, ..., x_i) (sec times)
-    let x = vec![x_i; SEC];
-    let r = vec![r_i; SEC];
-    let c = vec![c_i; SEC];
-
-    let (a, z, t);
-    loop {
-        if let Ok(res) = make_zkpopk(params, x.clone(), r.clone(), c.clone(), true, &state.pk) {
-            a = res.0;
-            z = res.1;
-            t = res.2;
-            break;
+    values: &[Integer],
+    diagonal: bool,
+) -> Result<Vec<Vec<Ciphertext>>, Error> {
+    let sec = state.sec.bits();
+    let mut ciphertexts = Vec::with_capacity(values.len());
+
+    for batch in values.chunks(sec) {
+        let mut x = Vec::with_capacity(sec);
+        let mut r = Vec::with_capacity(sec);
+        let mut c = Vec::with_capacity(sec);
+        for k in 0..sec {
+            let value = batch.get(k).cloned().unwrap_or_default();
+            let x_k = if diagonal {
+                encode(diag(params, value))
+            } else {
+                encode(value)
+            };
+            let (c_k, r_k) = encrypt_with_rand(params, x_k.clone(), &state.pk);
+            x.push(x_k);
+            r.push(r_k);
+            c.push(c_k);
+        }
+
+        let player_cs = match state.challenge_mode {
+            ChallengeMode::FiatShamir => prove_fiat_shamir(params, state, x, r, c, diagonal)?,
+            ChallengeMode::Interactive => prove_interactive(params, state, x, r, c, diagonal)?,
+        };
+
+        let mut batch_ciphertexts = vec![Vec::with_capacity(player_cs.len()); batch.len()];
+        for c_j in player_cs {
+            for (e_is, c_k) in batch_ciphertexts.iter_mut().zip(c_j) {
+                e_is.push(c_k);
+            }
         }
+        ciphertexts.extend(batch_ciphertexts);
     }
 
-    // Broadcast ZKPoPK to all players
-    let message = OnlineMessage::ShareZKPoPK { a, z, t, c };
-    state.facilitator.broadcast(&message);
+    Ok(ciphertexts)
+}
+
+/// Sends our ciphertexts `c` with a non-interactive ZKPoPK, and returns the ciphertexts of every
+/// player once their proofs are verified
+fn prove_fiat_shamir<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    x: Vec<Polynomial>,
+    r: Vec<(Polynomial, Polynomial, Polynomial)>,
+    c: Vec<Ciphertext>,
+    diagonal: bool,
+) -> Result<Vec<Vec<Ciphertext>>, Error> {
+    // Create own ZKPoPK, and broadcast it to all players along with the ciphertexts
+    let proof = PlaintextKnowledgeProof::prove(params, state.sec, x, r, c, diagonal, &state.pk)
+        .map_err(|_| Error::ProofAborted {
+            attempts: MAX_ATTEMPTS,
+        })?;
+    state
+        .facilitator
+        .broadcast(&OnlineMessage::ShareZKPoPK(proof));
 
     // Verify all received ZKPoPK
     let messages = state.facilitator.receive_from_all();
-    for (i, msg) in messages.into_iter().enumerate() {
-        match msg {
-            OnlineMessage::ShareZKPoPK { a, z, t, c } => {
-                if !verify_zkpopk(params, a, z, t, c, &state.pk) {
-                    panic!("ZKPoPK for player {} failed", i)
+    messages
+        .into_iter()
+        .enumerate()
+        .map(|(i, msg)| match msg {
+            OnlineMessage::ShareZKPoPK(proof) => {
+                let res = if proof.diagonal == diagonal {
+                    proof.verify(params, state.sec, &state.pk)
+                } else {
+                    Err(ProofError::WrongStatement)
+                };
+                res.map_err(|reason| Error::ProofFailed { player: i, reason })?;
+                Ok(proof.c)
+            }
+            _ => Err(Error::unexpected_message(i, "ShareZKPoPK", msg.name())),
+        })
+        .collect()
+}
+
+/// Like `prove_fiat_shamir`, but the challenge is sampled jointly once every player has sent its
+/// first message. If any prover aborts, all players start over with new first messages, at most
+/// `MAX_ATTEMPTS` times.
+fn prove_interactive<F: Facilitator>(
+    params: &Parameters,
+    state: &PlayerState<F>,
+    x: Vec<Polynomial>,
+    r: Vec<(Polynomial, Polynomial, Polynomial)>,
+    c: Vec<Ciphertext>,
+    diagonal: bool,
+) -> Result<Vec<Vec<Ciphertext>>, Error> {
+    for _ in 0..MAX_ATTEMPTS {
+        let (a, prover_state) = commit_zkpopk(params, state.sec, diagonal, &state.pk);
+        let to_bytes = |ciphertexts: &[Ciphertext]| {
+            ciphertexts
+                .iter()
+                .map(|c| ciphertext_to_bytes(params, c))
+                .collect()
+        };
+        state
+            .facilitator
+            .broadcast(&OnlineMessage::ShareZKPoPKCommitment {
+                a: to_bytes(&a),
+                c: to_bytes(&c),
+            });
+
+        let mut statements = Vec::with_capacity(state.facilitator.player_count());
+        for (i, msg) in state.facilitator.receive_from_all().into_iter().enumerate() {
+            match msg {
+                OnlineMessage::ShareZKPoPKCommitment { a, c } => {
+                    let from_bytes = |ciphertexts: Vec<Vec<u8>>| {
+                        ciphertexts
+                            .iter()
+                            .map(|bytes| ciphertext_from_bytes(params, bytes))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|e| Error::malformed(i, e))
+                    };
+                    statements.push((from_bytes(a)?, from_bytes(c)?))
+                }
+                _ => {
+                    return Err(Error::unexpected_message(
+                        i,
+                        "ShareZKPoPKCommitment",
+                        msg.name(),
+                    ))
                 }
             }
-            _ => panic!("expected ShareZKPoPK, got {:?}", msg),
+        }
+
+        // The challenge is only fixed after all first messages are sent
+        let e = challenge_from_seed(
+            params,
+            state.sec,
+            &common_seed(&state.facilitator)?,
+            diagonal,
+        );
+
+        let msg = match respond_zkpopk(params, x.clone(), r.clone(), prover_state, &e) {
+            Ok((z, t)) => OnlineMessage::ShareZKPoPKResponse { z, t },
+            Err(_) => OnlineMessage::AbortZKPoPK,
+        };
+        state.facilitator.broadcast(&msg);
+
+        let mut responses = Vec::with_capacity(statements.len());
+        for (i, msg) in state.facilitator.receive_from_all().into_iter().enumerate() {
+            match msg {
+                OnlineMessage::ShareZKPoPKResponse { z, t } => responses.push(Some((z, t))),
+                OnlineMessage::AbortZKPoPK => responses.push(None),
+                _ => {
+                    return Err(Error::unexpected_message(
+                        i,
+                        "ShareZKPoPKResponse",
+                        msg.name(),
+                    ))
+                }
+            }
+        }
+        if responses.iter().any(Option::is_none) {
+            continue;
+        }
+
+        return statements
+            .into_iter()
+            .zip(responses.into_iter().flatten())
+            .enumerate()
+            .map(|(i, ((a, c), response))| {
+                let proof = PlaintextKnowledgeProof::from_transcript(
+                    params, state.sec, c, a, response, diagonal, &state.pk,
+                );
+                proof
+                    .verify_with_challenge(params, state.sec, &e, &state.pk)
+                    .map_err(|reason| Error::ProofFailed { player: i, reason })?;
+                Ok(proof.c)
+            })
+            .collect();
+    }
+
+    Err(Error::ProofAborted {
+        attempts: MAX_ATTEMPTS,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{
+        mpc::{
+            fresh_noise_bound, open_shares,
+            testing::{additive_key_materials, channel_facilitators, ChannelFacilitator},
+            Sec,
+        },
+        poly::Polynomial,
+        polynomial,
+    };
+
+    use super::*;
+
+    #[test]
+    fn reshare_creates_fresh_ciphertext() {
+        let params = params_8degree();
+        let rq = &params.quotient_ring;
+        let key_materials = additive_key_materials(&params, 3);
+        let sk = key_materials
+            .iter()
+            .fold(polynomial![0], |sk, km| rq.add(&sk, &km.sk_i1));
+
+        // A product, as in the Triple step
+        let c_1 = encrypt(&params, polynomial![6], &key_materials[0].pk);
+        let c_2 = encrypt(&params, polynomial![7], &key_materials[0].pk);
+        let e_m = mul(&params, &c_1, &c_2);
+
+        let results: Vec<(Option<Ciphertext>, Integer)> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(3)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let (params, e_m) = (&params, &e_m);
+                    s.spawn(move || {
+                        let state = PlayerState::new(facilitator, key_material);
+                        let (_, mut masks) = encrypt_with_masks(params, &state, &[], 1).unwrap();
+                        reshare(params, e_m, masks.remove(0), &state, Enc::NewCiphertext).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        let (e_m_primes, m_is): (Vec<_>, Vec<_>) = results.into_iter().unzip();
+        assert_eq!(open_shares(&params, m_is), Integer::from(42));
+
+        let e_m_prime = e_m_primes[0].clone().unwrap();
+        for other in &e_m_primes[1..] {
+            assert_eq!(other.as_ref(), Some(&e_m_prime));
+        }
+        assert_eq!(e_m_prime.len(), 2);
+        assert_eq!(
+            decrypt(&params, e_m_prime.clone(), &sk).unwrap(),
+            polynomial![42]
+        );
+
+        // The noise is that of a sum of fresh encryptions, and not of the product
+        let bound = fresh_noise_bound(&params, 3) * 3_i32 + &params.p;
+        assert!(noise(&params, &e_m_prime, &sk) <= bound);
+    }
+
+    /// Runs `f` for two players after Initialize, returning their alpha_i and results
+    fn run_prep<T: Send>(
+        challenge_mode: ChallengeMode,
+        f: impl Fn(&Parameters, &PlayerState<ChannelFacilitator>) -> T + Sync,
+    ) -> Vec<(Integer, T)> {
+        let params = params_8degree();
+        let key_materials = additive_key_materials(&params, 2);
+
+        thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(2)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let (params, f) = (&params, &f);
+                    s.spawn(move || {
+                        let mut state = PlayerState::new(facilitator, key_material);
+                        state.set_challenge_mode(challenge_mode);
+                        protocol::initialize(params, &mut state).unwrap();
+                        (state.alpha_i.clone(), f(params, &state))
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    /// Opens the value and MAC of the angle shares of all players
+    fn open_angle(params: &Parameters, shares: &[AngleShare]) -> (Integer, Integer) {
+        let values = shares.iter().map(|share| share.0.clone()).collect();
+        let macs = shares.iter().map(|share| share.1.clone()).collect();
+        (open_shares(params, values), open_shares(params, macs))
+    }
+
+    /// Checks that the k-th pairs of all players are shares of the same r with correct MACs
+    fn check_pairs(params: &Parameters, results: &[(Integer, Vec<(Integer, AngleShare)>)]) {
+        let alpha = open_shares(params, results.iter().map(|(a, _)| a.clone()).collect());
+
+        for k in 0..results[0].1.len() {
+            let r_shares: Vec<Integer> = results.iter().map(|(_, p)| p[k].0.clone()).collect();
+            let angle_shares: Vec<AngleShare> =
+                results.iter().map(|(_, p)| p[k].1.clone()).collect();
+            let (r_angle, gamma) = open_angle(params, &angle_shares);
+
+            assert_eq!(open_shares(params, r_shares), r_angle);
+            assert_eq!(gamma, (alpha.clone() * r_angle).rem_euc(&params.p));
+        }
+    }
+
+    #[test]
+    fn pairs_spanning_several_batches_are_consistent() {
+        let results = run_prep(ChallengeMode::FiatShamir, |params, state| {
+            protocol::pairs(params, state, Sec::default().bits() + 1).unwrap()
+        });
+        check_pairs(&params_8degree(), &results);
+    }
+
+    #[test]
+    fn pairs_with_interactive_proofs_are_consistent() {
+        let results = run_prep(ChallengeMode::Interactive, |params, state| {
+            protocol::pairs(params, state, 2).unwrap()
+        });
+        check_pairs(&params_8degree(), &results);
+    }
+
+    #[test]
+    fn triples_are_multiplicative() {
+        let params = params_8degree();
+        let results = run_prep(ChallengeMode::FiatShamir, |params, state| {
+            protocol::triples(params, state, 2).unwrap()
+        });
+        let alpha = open_shares(&params, results.iter().map(|(a, _)| a.clone()).collect());
+
+        for k in 0..2 {
+            let open = |select: fn(&MulTriple) -> AngleShare| {
+                let shares: Vec<AngleShare> = results.iter().map(|(_, t)| select(&t[k])).collect();
+                open_angle(&params, &shares)
+            };
+            let (a, a_mac) = open(|t| t.0.clone());
+            let (b, b_mac) = open(|t| t.1.clone());
+            let (c, c_mac) = open(|t| t.2.clone());
+
+            assert_eq!(c, (a.clone() * &b).rem_euc(&params.p));
+            for (v, mac) in [(a, a_mac), (b, b_mac), (c, c_mac)] {
+                assert_eq!(mac, (alpha.clone() * v).rem_euc(&params.p));
+            }
+        }
+    }
+
+    #[test]
+    fn sacrifice_detects_wrong_triple() {
+        let results = run_prep(ChallengeMode::FiatShamir, |params, state| {
+            let checks = sacrifices(params, state.sec, 1);
+            let mut triples = protocol::unchecked_triples(params, state, 1 + checks).unwrap();
+            let spares = triples.split_off(1);
+            if state.facilitator.player_number() == 1 {
+                triples[0].2 .0 += 1;
+            }
+            sacrifice(params, state, &triples, spares)
+        });
+
+        for (_, res) in results {
+            assert!(matches!(res, Err(Error::TripleCheckFailed { .. })));
+        }
+    }
+
+    #[test]
+    fn sacrifices_grow_with_sec() {
+        let params = params_8degree();
+        assert_eq!(sacrifices(&params, Sec::default(), 1), 7);
+        assert_eq!(sacrifices(&params, Sec::new(128).unwrap(), 1), 22);
+
+        // A wrong square pair passes a sacrifice twice as often
+        assert_eq!(sacrifices(&params, Sec::default(), 2), 8);
+    }
+
+    #[test]
+    fn squares_are_squares() {
+        let params = params_8degree();
+        let results = run_prep(ChallengeMode::FiatShamir, |params, state| {
+            protocol::squares(params, state, 2).unwrap()
+        });
+        let alpha = open_shares(&params, results.iter().map(|(a, _)| a.clone()).collect());
+
+        for k in 0..2 {
+            let a_shares: Vec<AngleShare> = results.iter().map(|(_, s)| s[k].0.clone()).collect();
+            let b_shares: Vec<AngleShare> = results.iter().map(|(_, s)| s[k].1.clone()).collect();
+            let (a, a_mac) = open_angle(&params, &a_shares);
+            let (b, b_mac) = open_angle(&params, &b_shares);
+
+            assert_eq!(b, a.clone().square().rem_euc(&params.p));
+            for (v, mac) in [(a, a_mac), (b, b_mac)] {
+                assert_eq!(mac, (alpha.clone() * v).rem_euc(&params.p));
+            }
+        }
+    }
+
+    #[test]
+    fn bits_are_bits() {
+        let params = params_8degree();
+        let results = run_prep(ChallengeMode::FiatShamir, |params, state| {
+            protocol::bits(params, state, 4).unwrap()
+        });
+        let alpha = open_shares(&params, results.iter().map(|(a, _)| a.clone()).collect());
+
+        for k in 0..4 {
+            let shares: Vec<AngleShare> = results.iter().map(|(_, b)| b[k].clone()).collect();
+            let (b, b_mac) = open_angle(&params, &shares);
+
+            assert!(b == 0 || b == 1);
+            assert_eq!(b_mac, (alpha.clone() * b).rem_euc(&params.p));
+        }
+    }
+
+    #[test]
+    fn bits_require_odd_prime_p() {
+        let params = secure_params();
+        let key_material = additive_key_materials(&params, 1).remove(0);
+        let state = PlayerState::new(channel_facilitators(1).remove(0), key_material);
+
+        assert!(matches!(
+            protocol::bits(&params, &state, 1),
+            Err(Error::PlaintextModulusNotOddPrime(_))
+        ));
+    }
+
+    #[test]
+    fn sqrt_mod_finds_roots_of_squares() {
+        // 127 = 3 mod 4, and 97 - 1 = 3 * 2^5 needs several Tonelli-Shanks steps
+        for p in [Integer::from(127), Integer::from(97)] {
+            for a in 0..p.to_u32().unwrap() {
+                let s = Integer::from(a * a).rem_euc(&p);
+                let r = sqrt_mod(&s, &p).unwrap();
+                assert_eq!(r.square().rem_euc(&p), s);
+            }
+            let non_residues = (1..p.to_u32().unwrap())
+                .filter(|&s| sqrt_mod(&Integer::from(s), &p).is_none())
+                .count();
+            assert_eq!(non_residues, p.to_usize().unwrap() / 2);
+        }
+    }
+
+    #[test]
+    fn sacrifice_detects_wrong_square() {
+        let results = run_prep(ChallengeMode::FiatShamir, |params, state| {
+            let checks = sacrifices(params, state.sec, 2);
+            let mut squares = protocol::unchecked_squares(params, state, 1 + checks).unwrap();
+            let spares = squares.split_off(1);
+            if state.facilitator.player_number() == 1 {
+                squares[0].1 .0 += 1;
+            }
+            sacrifice_squares(params, state, &squares, spares)
+        });
+
+        for (_, res) in results {
+            assert!(matches!(res, Err(Error::SquareCheckFailed { .. })));
         }
     }
 }
@@ -0,0 +1,188 @@
+//! Proactive refresh of the secret-key shares.
+//!
+//! Every player deals a fresh sharing of zero (additive, or Shamir with the same threshold as the
+//! key shares), and every player adds the shares it receives to its shares of sk and sk^2. The
+//! shared values and `pk` are unchanged, but shares from before the refresh cannot be combined
+//! with shares from after it, so an adversary has to corrupt enough players within one period.
+//! If the players committed to their key shares, they commit to the new shares as well.
+
+use crate::{
+    encryption::Parameters,
+    error::Error,
+    mpc::{decryption_proof::commit_key_shares, threshold, PlayerState},
+    poly::Polynomial,
+    polynomial,
+    prob::sample_from_uniform,
+    protocol::{Facilitator, OnlineMessage},
+};
+
+/// Refreshes our shares of sk and sk^2 and the commitments to them; all players must take part
+pub fn refresh_key_shares<F: Facilitator>(
+    params: &Parameters,
+    state: &mut PlayerState<F>,
+) -> Result<(), Error> {
+    let rq = &params.quotient_ring;
+    let player_count = state.facilitator.player_count();
+
+    let zero_shares_1 = zero_shares(params, state.threshold, player_count);
+    let zero_shares_2 = zero_shares(params, state.threshold, player_count);
+    for (j, (z_j1, z_j2)) in zero_shares_1.into_iter().zip(zero_shares_2).enumerate() {
+        state.facilitator.send(j, &OnlineMessage::SharePoly(z_j1));
+        state.facilitator.send(j, &OnlineMessage::SharePoly(z_j2));
+    }
+
+    let mut sk_i1 = state.sk_i1.clone();
+    let mut sk_i2 = state.sk_i2.clone();
+    for j in 0..player_count {
+        sk_i1 = rq.add(&sk_i1, &receive_poly(state, j)?);
+        sk_i2 = rq.add(&sk_i2, &receive_poly(state, j)?);
+    }
+    state.sk_i1 = sk_i1;
+    state.sk_i2 = sk_i2;
+
+    if state.key_commitments.is_some() {
+        commit_key_shares(params, state)?;
+    }
+    Ok(())
+}
+
+/// Shares of zero for every player, in the same sharing as the key shares
+fn zero_shares(
+    params: &Parameters,
+    threshold: Option<usize>,
+    player_count: usize,
+) -> Vec<Polynomial> {
+    let rq = &params.quotient_ring;
+    match threshold {
+        Some(threshold) => threshold::share(params, &polynomial![0], threshold, player_count),
+        None => {
+            let mut shares: Vec<Polynomial> = (0..player_count - 1)
+                .map(|_| sample_from_uniform(&rq.q, params.n))
+                .collect();
+            let sum = shares
+                .iter()
+                .fold(polynomial![0], |sum, share| rq.add(&sum, share));
+            shares.push(rq.neg(&sum));
+            shares
+        }
+    }
+}
+
+fn receive_poly<F: Facilitator>(
+    state: &PlayerState<F>,
+    player: usize,
+) -> Result<Polynomial, Error> {
+    match state.facilitator.receive(player) {
+        OnlineMessage::SharePoly(pol) => Ok(pol),
+        msg => Err(Error::unexpected_message(player, "SharePoly", msg.name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use rug::Integer;
+
+    use crate::{
+        encryption::{encrypt_det, generate_key_pair, mul, params_8degree},
+        mpc::{
+            ddec,
+            testing::{additive_key_materials, channel_facilitators},
+            threshold::threshold_key_materials,
+        },
+        protocol::KeyMaterial,
+    };
+
+    use super::*;
+
+    /// Refreshes the key shares of all players, decrypts an encryption of 6 * 7 with the new
+    /// shares and returns them
+    fn refresh_and_decrypt(
+        params: &Parameters,
+        key_materials: &[KeyMaterial],
+        threshold: Option<usize>,
+    ) -> Vec<KeyMaterial> {
+        let results: Vec<(Integer, KeyMaterial)> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(key_materials.len())
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    s.spawn(move || {
+                        let mut state = match threshold {
+                            Some(t) => {
+                                PlayerState::new_threshold(facilitator, key_material.clone(), t)
+                            }
+                            None => PlayerState::new(facilitator, key_material.clone()),
+                        };
+                        refresh_key_shares(params, &mut state).unwrap();
+
+                        let r = (polynomial![1], polynomial![1], polynomial![1]);
+                        let c_1 = encrypt_det(params, polynomial![6], &state.pk, r.clone());
+                        let c_2 = encrypt_det(params, polynomial![7], &state.pk, r);
+                        let c = mul(params, &c_1, &c_2);
+                        (ddec(params, &state, c).unwrap(), state.key_material())
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        results
+            .into_iter()
+            .map(|(m, key_material)| {
+                assert_eq!(m, Integer::from(42));
+                key_material
+            })
+            .collect()
+    }
+
+    fn sum_shares(params: &Parameters, shares: &[&Polynomial]) -> Polynomial {
+        let rq = &params.quotient_ring;
+        shares
+            .iter()
+            .fold(polynomial![0], |sum, share| rq.add(&sum, share))
+    }
+
+    #[test]
+    fn refreshed_additive_shares_decrypt() {
+        let params = params_8degree();
+        let old = additive_key_materials(&params, 3);
+        let new = refresh_and_decrypt(&params, &old, None);
+
+        for (old, new) in old.iter().zip(&new) {
+            assert_eq!(old.pk, new.pk);
+            assert_ne!(old.sk_i1, new.sk_i1);
+            assert_ne!(old.sk_i2, new.sk_i2);
+        }
+
+        // The new shares are shares of the same key, but old and new shares do not combine
+        let sk = sum_shares(&params, &[&old[0].sk_i1, &old[1].sk_i1, &old[2].sk_i1]);
+        let new_sk = sum_shares(&params, &[&new[0].sk_i1, &new[1].sk_i1, &new[2].sk_i1]);
+        let mixed_sk = sum_shares(&params, &[&old[0].sk_i1, &new[1].sk_i1, &new[2].sk_i1]);
+        assert_eq!(new_sk, sk);
+        assert_ne!(mixed_sk, sk);
+    }
+
+    #[test]
+    fn refreshed_threshold_shares_decrypt() {
+        let params = params_8degree();
+        let (pk, sk) = generate_key_pair(&params);
+        let old = threshold_key_materials(&params, &pk, &sk, 1, 3).unwrap();
+        let new = refresh_and_decrypt(&params, &old, Some(1));
+
+        let sk = sk.modulo(&params.quotient_ring.q);
+        let new_sk = threshold::reconstruct(
+            &params,
+            &[(0, new[0].sk_i1.clone()), (2, new[2].sk_i1.clone())],
+        )
+        .unwrap();
+        let mixed_sk = threshold::reconstruct(
+            &params,
+            &[(0, old[0].sk_i1.clone()), (2, new[2].sk_i1.clone())],
+        )
+        .unwrap();
+        assert_eq!(new_sk, sk);
+        assert_ne!(mixed_sk, sk);
+    }
+}
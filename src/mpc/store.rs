@@ -0,0 +1,244 @@
+//! Preprocessed material of one player, produced ahead of the online phase.
+//!
+//! A store holds the MAC key share of the player and the pairs, triples, square pairs and bits
+//! produced under it, together with the session they were produced in: the number of players, the
+//! number of the player, sec and the public key. The online phase takes material out of the store,
+//! and the caller must save the store again before using it, so that no material is used twice.
+//! Stores are saved with `storage::save_preprocessing_store`.
+
+use rug::Integer;
+
+use crate::{encryption::*, error::Error, protocol::Facilitator};
+
+use super::{
+    prep, zk::public_key_fingerprint, AngleShare, MulTriple, PlayerState, Sec, SquarePair,
+};
+
+/// Amounts of each kind of preprocessed material
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Amounts {
+    pub pairs: usize,
+    pub triples: usize,
+    pub squares: usize,
+    pub bits: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreprocessingStore {
+    pub(crate) player_count: usize,
+    pub(crate) player_number: usize,
+    pub(crate) sec: Sec,
+    pub(crate) pk_fingerprint: [u8; 32],
+    pub(crate) alpha_i: Integer,
+    pub(crate) e_alpha: Ciphertext,
+    pub(crate) pairs: Vec<(Integer, AngleShare)>,
+    pub(crate) triples: Vec<MulTriple>,
+    pub(crate) squares: Vec<SquarePair>,
+    pub(crate) bits: Vec<AngleShare>,
+}
+
+impl PreprocessingStore {
+    /// Runs Initialize, replacing the MAC key of `state`, and produces `amounts` of material under
+    /// the new key. All players must take part with the same amounts.
+    ///
+    /// Kinds with an amount of 0 are skipped, so bits are only required to be possible if some are
+    /// requested.
+    pub fn generate<F: Facilitator>(
+        params: &Parameters,
+        state: &mut PlayerState<F>,
+        amounts: Amounts,
+    ) -> Result<Self, Error> {
+        prep::protocol::initialize(params, state)?;
+
+        let mut store = Self {
+            player_count: state.facilitator.player_count(),
+            player_number: state.facilitator.player_number(),
+            sec: state.sec,
+            pk_fingerprint: public_key_fingerprint(params, &state.pk),
+            alpha_i: state.alpha_i.clone(),
+            e_alpha: state.e_alpha.clone(),
+            pairs: vec![],
+            triples: vec![],
+            squares: vec![],
+            bits: vec![],
+        };
+        if amounts.pairs > 0 {
+            store.pairs = prep::protocol::pairs(params, state, amounts.pairs)?;
+        }
+        if amounts.triples > 0 {
+            store.triples = prep::protocol::triples(params, state, amounts.triples)?;
+        }
+        if amounts.squares > 0 {
+            store.squares = prep::protocol::squares(params, state, amounts.squares)?;
+        }
+        if amounts.bits > 0 {
+            store.bits = prep::protocol::bits(params, state, amounts.bits)?;
+        }
+        Ok(store)
+    }
+
+    /// The amounts of material left in the store
+    pub fn amounts(&self) -> Amounts {
+        Amounts {
+            pairs: self.pairs.len(),
+            triples: self.triples.len(),
+            squares: self.squares.len(),
+            bits: self.bits.len(),
+        }
+    }
+
+    /// Sets the MAC key of `state` to the one the material was produced under, after checking that
+    /// `state` belongs to the same session
+    pub fn restore_mac_key<F: Facilitator>(
+        &self,
+        params: &Parameters,
+        state: &mut PlayerState<F>,
+    ) -> Result<(), Error> {
+        let mismatch = if self.player_count != state.facilitator.player_count() {
+            Some("number of players")
+        } else if self.player_number != state.facilitator.player_number() {
+            Some("player")
+        } else if self.sec != state.sec {
+            Some("statistical security parameter")
+        } else if self.pk_fingerprint != public_key_fingerprint(params, &state.pk) {
+            Some("public key")
+        } else {
+            None
+        };
+        if let Some(what) = mismatch {
+            return Err(Error::PreprocessingMismatch(what));
+        }
+
+        state.alpha_i = self.alpha_i.clone();
+        state.e_alpha = self.e_alpha.clone();
+        Ok(())
+    }
+
+    pub fn take_pairs(&mut self, count: usize) -> Result<Vec<(Integer, AngleShare)>, Error> {
+        take(&mut self.pairs, count, "pairs")
+    }
+
+    pub fn take_triples(&mut self, count: usize) -> Result<Vec<MulTriple>, Error> {
+        take(&mut self.triples, count, "triples")
+    }
+
+    pub fn take_squares(&mut self, count: usize) -> Result<Vec<SquarePair>, Error> {
+        take(&mut self.squares, count, "square pairs")
+    }
+
+    pub fn take_bits(&mut self, count: usize) -> Result<Vec<AngleShare>, Error> {
+        take(&mut self.bits, count, "bits")
+    }
+}
+
+/// Removes the first `count` items, or none if there are fewer
+fn take<T>(items: &mut Vec<T>, count: usize, kind: &'static str) -> Result<Vec<T>, Error> {
+    if items.len() < count {
+        return Err(Error::NotEnoughPreprocessing {
+            kind,
+            requested: count,
+            available: items.len(),
+        });
+    }
+    Ok(items.drain(..count).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::mpc::{
+        online,
+        testing::{additive_key_materials, channel_facilitators},
+    };
+
+    use super::*;
+
+    #[test]
+    fn stored_material_is_used_once() {
+        let params = params_8degree();
+        let key_materials = additive_key_materials(&params, 2);
+        let amounts = Amounts {
+            pairs: 2,
+            triples: 1,
+            ..Amounts::default()
+        };
+
+        let results: Vec<Integer> = thread::scope(|s| {
+            let handles: Vec<_> = channel_facilitators(2)
+                .into_iter()
+                .zip(key_materials)
+                .map(|(facilitator, key_material)| {
+                    let params = &params;
+                    s.spawn(move || {
+                        let mut state = PlayerState::new(facilitator, key_material.clone());
+                        let mut store =
+                            PreprocessingStore::generate(params, &mut state, amounts).unwrap();
+                        assert_eq!(store.amounts(), amounts);
+
+                        // The online phase can run in a state without the MAC key
+                        let mut state = PlayerState::new(state.facilitator, key_material);
+                        store.restore_mac_key(params, &mut state).unwrap();
+                        let pairs = store.take_pairs(2).unwrap();
+                        let triple = store.take_triples(1).unwrap().remove(0);
+                        assert!(matches!(
+                            store.take_pairs(1),
+                            Err(Error::NotEnoughPreprocessing {
+                                requested: 1,
+                                available: 0,
+                                ..
+                            })
+                        ));
+
+                        let mut inputs = vec![];
+                        for (i, pair) in pairs.into_iter().enumerate() {
+                            inputs.push(if i == state.facilitator.player_number() {
+                                let x = Integer::from(5 + i);
+                                online::protocol::give_input(params, x, pair, &state).unwrap()
+                            } else {
+                                online::protocol::receive_input(pair, i, &state).unwrap()
+                            });
+                        }
+                        let (x, y) = (inputs.remove(0), inputs.remove(0));
+                        let z =
+                            online::protocol::multiply(params, x, y, triple, &mut state).unwrap();
+                        online::protocol::output(params, z, &state).unwrap()
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        assert_eq!(results, vec![Integer::from(30); 2]);
+    }
+
+    #[test]
+    fn store_of_other_session_is_rejected() {
+        let params = params_8degree();
+        let mut key_materials = additive_key_materials(&params, 1);
+        let facilitator = channel_facilitators(1).remove(0);
+        let mut state = PlayerState::new(facilitator, key_materials[0].clone());
+        let amounts = Amounts {
+            pairs: 1,
+            ..Amounts::default()
+        };
+        let store = PreprocessingStore::generate(&params, &mut state, amounts).unwrap();
+        assert!(store.restore_mac_key(&params, &mut state).is_ok());
+
+        state.set_sec(Sec::new(64).unwrap());
+        assert!(matches!(
+            store.restore_mac_key(&params, &mut state),
+            Err(Error::PreprocessingMismatch(
+                "statistical security parameter"
+            ))
+        ));
+
+        // The same player under a freshly generated key
+        key_materials = additive_key_materials(&params, 1);
+        let mut state = PlayerState::new(state.facilitator, key_materials.remove(0));
+        assert!(matches!(
+            store.restore_mac_key(&params, &mut state),
+            Err(Error::PreprocessingMismatch("public key"))
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+//! An in-memory `Facilitator` for running protocols with several players in tests.
+
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use crate::{
+    encryption::{generate_key_pair, Parameters},
+    poly::Polynomial,
+    polynomial,
+    prob::sample_from_uniform,
+    protocol::{Facilitator, KeyMaterial, OnlineMessage},
+};
+
+pub struct ChannelFacilitator {
+    player_number: usize,
+    senders: Vec<Sender<OnlineMessage>>,
+    receivers: Vec<Receiver<OnlineMessage>>,
+}
+
+/// Creates facilitators for `player_count` players, connected by channels
+pub fn channel_facilitators(player_count: usize) -> Vec<ChannelFacilitator> {
+    // channels[i][j] carries messages from player i to player j
+    let mut senders: Vec<Vec<Sender<OnlineMessage>>> = vec![vec![]; player_count];
+    let mut receivers: Vec<Vec<Receiver<OnlineMessage>>> =
+        (0..player_count).map(|_| vec![]).collect();
+    for sender in senders.iter_mut() {
+        for receiver in receivers.iter_mut() {
+            let (tx, rx) = mpsc::channel();
+            sender.push(tx);
+            receiver.push(rx);
+        }
+    }
+
+    senders
+        .into_iter()
+        .zip(receivers)
+        .enumerate()
+        .map(|(player_number, (senders, receivers))| ChannelFacilitator {
+            player_number,
+            senders,
+            receivers,
+        })
+        .collect()
+}
+
+/// Generates a key pair and additive shares of sk and sk^2 for `player_count` players
+pub fn additive_key_materials(params: &Parameters, player_count: usize) -> Vec<KeyMaterial> {
+    let rq = &params.quotient_ring;
+    let (pk, sk) = generate_key_pair(params);
+    let sk_squared = rq.mul(&sk, &sk);
+
+    let mut key_materials: Vec<KeyMaterial> = (0..player_count - 1)
+        .map(|_| KeyMaterial {
+            pk: pk.clone(),
+            sk_i1: sample_from_uniform(&rq.q, params.n),
+            sk_i2: sample_from_uniform(&rq.q, params.n),
+        })
+        .collect();
+    let (sk_1_sum, sk_2_sum) = key_materials.iter().fold(
+        (polynomial![0], polynomial![0]),
+        |(sk_1_sum, sk_2_sum): (Polynomial, Polynomial), km| {
+            (rq.add(&sk_1_sum, &km.sk_i1), rq.add(&sk_2_sum, &km.sk_i2))
+        },
+    );
+    key_materials.push(KeyMaterial {
+        pk,
+        sk_i1: rq.sub(&sk, &sk_1_sum),
+        sk_i2: rq.sub(&sk_squared, &sk_2_sum),
+    });
+    key_materials
+}
+
+impl Facilitator for ChannelFacilitator {
+    fn player_count(&self) -> usize {
+        self.senders.len()
+    }
+
+    fn player_number(&self) -> usize {
+        self.player_number
+    }
+
+    fn send(&self, player: usize, msg: &OnlineMessage) {
+        self.senders[player].send(msg.clone()).unwrap();
+    }
+
+    fn broadcast(&self, msg: &OnlineMessage) {
+        for player in 0..self.player_count() {
+            self.send(player, msg);
+        }
+    }
+
+    fn receive(&self, player: usize) -> OnlineMessage {
+        self.receivers[player].recv().unwrap()
+    }
+
+    fn receive_from_all(&self) -> Vec<OnlineMessage> {
+        (0..self.player_count())
+            .map(|player| self.receive(player))
+            .collect()
+    }
+
+    fn stop(self) {}
+}
@@ -0,0 +1,261 @@
+//! Shamir (t-of-n) sharing of the secret key.
+//!
+//! Player i holds the evaluation at x = i + 1 of random polynomials of degree t over Rq whose
+//! constant terms are `sk` and `sk^2`. Any t + 1 players can decrypt with `ddec_among` by weighting
+//! their shares with the Lagrange coefficients of the set of decrypting players.
+
+use rug::{ops::RemRounding, Integer};
+
+use crate::{
+    encryption::{Parameters, PublicKey, SecretKey},
+    error::Error,
+    poly::Polynomial,
+    polynomial,
+    prob::sample_from_uniform,
+    protocol::KeyMaterial,
+};
+
+/// Shares `secret` among `player_count` players such that any `threshold + 1` of them can
+/// reconstruct it, and `threshold` of them learn nothing
+pub fn share(
+    params: &Parameters,
+    secret: &Polynomial,
+    threshold: usize,
+    player_count: usize,
+) -> Vec<Polynomial> {
+    let rq = &params.quotient_ring;
+
+    let mut coefficients = vec![secret.modulo(&rq.q)];
+    for _ in 0..threshold {
+        coefficients.push(sample_from_uniform(&rq.q, params.n));
+    }
+
+    (0..player_count)
+        .map(|i| {
+            // Horner's method in x = i + 1
+            let x = Integer::from(i + 1);
+            let mut share = polynomial![0];
+            for coefficient in coefficients.iter().rev() {
+                share = rq.add(&rq.times(&share, &x), coefficient);
+            }
+            share
+        })
+        .collect()
+}
+
+/// Returns the Lagrange coefficient of `player` for interpolating at 0 from the shares of `players`
+pub fn lagrange_coefficient(
+    params: &Parameters,
+    players: &[usize],
+    player: usize,
+) -> Result<Integer, Error> {
+    check_players(players)?;
+    if !players.contains(&player) {
+        return Err(Error::InvalidPlayers(format!(
+            "player {} is not one of {:?}",
+            player, players
+        )));
+    }
+
+    let q = &params.quotient_ring.q;
+    let x_i = Integer::from(player + 1);
+    let mut numerator = Integer::from(1);
+    let mut denominator = Integer::from(1);
+    for &j in players.iter().filter(|&&j| j != player) {
+        let x_j = Integer::from(j + 1);
+        numerator *= &x_j;
+        denominator *= x_j - &x_i;
+    }
+
+    let denominator_inverse = denominator.invert(q).map_err(|_| {
+        Error::InvalidPlayers(format!(
+            "Lagrange coefficients of {:?} are not defined modulo q",
+            players
+        ))
+    })?;
+    Ok((numerator * denominator_inverse).rem_euc(q))
+}
+
+/// Reconstructs a secret from the shares `(player, share)` of at least `threshold + 1` players
+pub fn reconstruct(
+    params: &Parameters,
+    shares: &[(usize, Polynomial)],
+) -> Result<Polynomial, Error> {
+    let rq = &params.quotient_ring;
+    let players: Vec<usize> = shares.iter().map(|(i, _)| *i).collect();
+
+    let mut secret = polynomial![0];
+    for (i, share) in shares {
+        let lambda_i = lagrange_coefficient(params, &players, *i)?;
+        secret = rq.add(&secret, &rq.times(share, &lambda_i));
+    }
+    Ok(secret)
+}
+
+/// Creates the key material of `player_count` players with Shamir shares of `sk` and `sk^2`
+pub fn threshold_key_materials(
+    params: &Parameters,
+    pk: &PublicKey,
+    sk: &SecretKey,
+    threshold: usize,
+    player_count: usize,
+) -> Result<Vec<KeyMaterial>, Error> {
+    if threshold >= player_count {
+        return Err(Error::NotEnoughPlayers {
+            required: threshold + 1,
+            found: player_count,
+        });
+    }
+
+    let sk_squared = params.quotient_ring.mul(sk, sk);
+    let sk_i1s = share(params, sk, threshold, player_count);
+    let sk_i2s = share(params, &sk_squared, threshold, player_count);
+
+    Ok(sk_i1s
+        .into_iter()
+        .zip(sk_i2s)
+        .map(|(sk_i1, sk_i2)| KeyMaterial {
+            pk: pk.clone(),
+            sk_i1,
+            sk_i2,
+        })
+        .collect())
+}
+
+/// Checks that no player appears twice
+fn check_players(players: &[usize]) -> Result<(), Error> {
+    for (k, i) in players.iter().enumerate() {
+        if players[..k].contains(i) {
+            return Err(Error::InvalidPlayers(format!(
+                "player {} appears more than once",
+                i
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use crate::{
+        encryption::{encrypt, generate_key_pair, mul, params_8degree, Ciphertext},
+        mpc::{ddec_among, testing::channel_facilitators, PlayerState},
+    };
+
+    use super::*;
+
+    /// All subsets of `0..player_count`, as sorted vectors
+    fn subsets(player_count: usize) -> Vec<Vec<usize>> {
+        (0..1_usize << player_count)
+            .map(|mask| (0..player_count).filter(|i| mask >> i & 1 == 1).collect())
+            .collect()
+    }
+
+    /// Runs `ddec_among` for every player in `players` and returns their results
+    fn run_ddec(
+        params: &Parameters,
+        key_materials: &[KeyMaterial],
+        threshold: usize,
+        players: &[usize],
+        c: &Ciphertext,
+    ) -> Vec<Result<Integer, Error>> {
+        let facilitators = channel_facilitators(key_materials.len());
+        thread::scope(|s| {
+            let handles: Vec<_> = facilitators
+                .into_iter()
+                .zip(key_materials)
+                .enumerate()
+                .filter(|(i, _)| players.contains(i))
+                .map(|(_, (facilitator, key_material))| {
+                    let state =
+                        PlayerState::new_threshold(facilitator, key_material.clone(), threshold);
+                    s.spawn(move || ddec_among(params, &state, c.clone(), players))
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        })
+    }
+
+    #[test]
+    fn every_qualifying_subset_reconstructs() {
+        let params = params_8degree();
+        let (_, sk) = generate_key_pair(&params);
+        let (threshold, player_count) = (2, 5);
+        let shares = share(&params, &sk, threshold, player_count);
+
+        for players in subsets(player_count) {
+            let subset_shares: Vec<(usize, Polynomial)> =
+                players.iter().map(|&i| (i, shares[i].clone())).collect();
+            let res = reconstruct(&params, &subset_shares).unwrap();
+
+            if players.len() > threshold {
+                assert_eq!(res, sk.modulo(&params.quotient_ring.q), "{:?}", players);
+            } else if !players.is_empty() {
+                assert_ne!(res, sk.modulo(&params.quotient_ring.q), "{:?}", players);
+            }
+        }
+    }
+
+    #[test]
+    fn every_qualifying_subset_decrypts() {
+        let params = params_8degree();
+        let (pk, sk) = generate_key_pair(&params);
+        let (threshold, player_count) = (1, 4);
+        let key_materials =
+            threshold_key_materials(&params, &pk, &sk, threshold, player_count).unwrap();
+
+        // A product, so the shares of sk^2 are used as well
+        let c_1 = encrypt(&params, polynomial![6], &pk);
+        let c_2 = encrypt(&params, polynomial![7], &pk);
+        let c = mul(&params, &c_1, &c_2);
+
+        for players in subsets(player_count) {
+            if players.is_empty() {
+                continue;
+            }
+            let results = run_ddec(&params, &key_materials, threshold, &players, &c);
+
+            for res in results {
+                if players.len() > threshold {
+                    assert_eq!(res.unwrap(), Integer::from(42), "{:?}", players);
+                } else {
+                    assert!(matches!(
+                        res,
+                        Err(Error::NotEnoughPlayers {
+                            required: 2,
+                            found: 1
+                        })
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lagrange_coefficient_rejects_invalid_players() {
+        let params = params_8degree();
+        assert!(matches!(
+            lagrange_coefficient(&params, &[0, 1, 1], 0),
+            Err(Error::InvalidPlayers(_))
+        ));
+        assert!(matches!(
+            lagrange_coefficient(&params, &[0, 1], 2),
+            Err(Error::InvalidPlayers(_))
+        ));
+    }
+
+    #[test]
+    fn threshold_must_be_below_player_count() {
+        let params = params_8degree();
+        let (pk, sk) = generate_key_pair(&params);
+        assert!(matches!(
+            threshold_key_materials(&params, &pk, &sk, 3, 3),
+            Err(Error::NotEnoughPlayers {
+                required: 4,
+                found: 3
+            })
+        ));
+    }
+}
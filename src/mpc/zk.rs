@@ -1,334 +1,539 @@
-use rug::{ops::Pow, Integer};
-use sha2::digest::{ExtendableOutput, Update, XofReader};
-use sha3::Shake256;
+//! Zero-knowledge proofs of plaintext knowledge (ZKPoPK) for batches of sec ciphertexts, where sec
+//! is the statistical security parameter of the session.
+//!
+//! The proof follows the "high gear" proof of TopGear (Baum, Cozzo, Smart): the challenge is a
+//! V x sec matrix W whose entries are 0 or monomials X^j in R = Z[X]/(X^N + 1), so every row
+//! contributes log2(2N + 1) bits of soundness and far fewer rows are needed than with bits. As
+//! multiplying by a monomial does not change the infinity norm, the prover can use masks that are
+//! only a factor sec * N * V * sec larger than the plaintexts and randomness, and rejection
+//! sampling aborts with probability about 2 / sec, so a bounded number of retries is enough.
+//!
+//! Diagonal plaintexts have to stay diagonal under the challenge, so their proofs use challenges
+//! in {0, 1} and need more rows.
+
+use rug::Integer;
+use serde::{Deserialize, Serialize};
+use sha2::{digest::Update, Digest, Sha256};
+use sha3::{
+    digest::{ExtendableOutput, XofReader},
+    Shake256,
+};
 
 use crate::{
     encryption::{add, PublicKey},
-    mpc::{decode, diag, encode, encrypt_det, Ciphertext, Parameters},
+    mpc::{encrypt_det, gaussian_bound, Ciphertext, Parameters, Sec},
     poly::Polynomial,
-    prob::{sample_from_uniform, sample_single},
+    prob::{sample_from_uniform, Prg},
+    serialization::{
+        ciphertext_to_bytes, plaintext_knowledge_proof_from_bytes,
+        plaintext_knowledge_proof_to_bytes, public_key_to_bytes, SerializationError,
+    },
 };
 
-use super::{SEC, V};
+/// How many times a prover starts over after aborting before giving up. Every attempt succeeds
+/// with probability about 1 - 2 / sec, so giving up is negligible.
+pub const MAX_ATTEMPTS: usize = 16;
 
 #[derive(Debug)]
 pub enum MakeZKPoPKError {
     TLInfNormTooBig,
     ZLInfNormTooBig,
+    /// The prover aborted in all of the `MAX_ATTEMPTS` attempts
+    TooManyAborts,
 }
 
-/// Make a zero-knowledge proof of plaintext knowledge
-#[allow(clippy::needless_range_loop, clippy::type_complexity)]
-pub fn make_zkpopk(
-    params: &Parameters,
-    x: Vec<Polynomial>,
-    r: Vec<(Polynomial, Polynomial, Polynomial)>,
-    c: Vec<Ciphertext>,
-    diagonal: bool,
-    pk: &PublicKey,
-) -> Result<(Vec<Vec<Polynomial>>, Vec<Vec<Integer>>, Vec<Vec<Integer>>), MakeZKPoPKError> {
-    let tau = &params.p / Integer::from(2_i32);
-    let rho = Integer::from(2_i32)
-        * Integer::from(params.r as i64)
-        * Integer::sqrt(Integer::from(params.n));
-    let d = params.n * 3;
+/// Reasons for rejecting a zero-knowledge proof of plaintext knowledge
+#[derive(Debug, PartialEq, Eq)]
+pub enum ProofError {
+    /// The proof or the statement does not have the expected dimensions
+    WrongLength,
+    /// The check d_i = a_i + (W * c)_i failed for the given index
+    EncryptionMismatch(usize),
+    /// The given z_i has too large infinity norm
+    ZLInfNormTooBig(usize),
+    /// The given t_i has too large infinity norm
+    TLInfNormTooBig(usize),
+    /// The given z_i does not decode to a diagonal plaintext, although one was required
+    NotDiagonal(usize),
+    /// The proof is about another public key, security parameter or kind of plaintexts
+    WrongStatement,
+    /// A decryption share or key commitment does not match the response for the given index
+    ShareMismatch(usize),
+    /// The response for the noise or commitment randomness for the given index is too large
+    NoiseTooBig(usize),
+}
 
-    let y_i_bound =
-        Integer::from(128_i32) * Integer::from(params.n) * tau.clone() * Integer::from(SEC).pow(2);
-    let s_i_bound =
-        Integer::from(128_i32) * Integer::from(d) * rho.clone() * Integer::from(SEC).pow(2);
+/// How the challenge of a ZKPoPK is chosen, which all players of a session must agree on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChallengeMode {
+    /// The challenge is a hash of the statement and the first message (Fiat-Shamir), so the
+    /// proof is non-interactive but only secure in the random oracle model
+    #[default]
+    FiatShamir,
+    /// The players jointly sample the challenge with a commit-and-open coin toss after all first
+    /// messages are sent, which costs two more rounds but needs no random oracle
+    Interactive,
+}
 
-    let mut y = Vec::with_capacity(V);
-    let mut s = Vec::with_capacity(V);
+/// The challenge matrix W with V rows and sec columns, where 0 stands for 0 and j > 0 for the
+/// monomial X^(j - 1)
+pub type Challenge = Vec<Vec<usize>>;
 
-    let mut a = Vec::with_capacity(V);
-    for i in 0..V {
-        let mut m_i = sample_single(&params.p);
-        if diagonal {
-            m_i = diag(params, m_i)
-        }
-        let encoded_m_i = encode(m_i);
-        let u_i = sample_from_uniform(&((y_i_bound.clone() / params.p.clone()) - 1_i32), params.n)
-            * params.p.clone();
-        y.push(encoded_m_i + u_i);
-
-        s.push((
-            sample_from_uniform(&s_i_bound, params.n),
-            sample_from_uniform(&s_i_bound, params.n),
-            sample_from_uniform(&s_i_bound, params.n),
-        ));
+/// The randomness of the prover's first message, which is needed for the response
+pub struct ProverState {
+    y: Vec<Vec<Integer>>,
+    s: Vec<Vec<Integer>>,
+    diagonal: bool,
+    sec: Sec,
+}
 
-        a.push(encrypt_det(params, y[i].clone(), pk, s[i].clone()))
-    }
+/// A zero-knowledge proof of plaintext knowledge for sec ciphertexts, along with its statement
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaintextKnowledgeProof {
+    /// The ciphertexts whose plaintexts and randomness the prover knows
+    pub c: Vec<Ciphertext>,
+    /// Fingerprint of the public key the ciphertexts are encrypted under
+    pub pk_fingerprint: [u8; 32],
+    /// Whether the plaintexts are proven to be diagonal
+    pub diagonal: bool,
+    /// The statistical security parameter the proof was made for
+    pub sec: usize,
+    pub(crate) a: Vec<Ciphertext>,
+    pub(crate) z: Vec<Vec<Integer>>,
+    pub(crate) t: Vec<Vec<Integer>>,
+}
 
-    // Create a SEC-bit bitstring (each bit represented by u8)
-    let e = hash(&a, &c);
-    let m_e = create_m_e_from_e(e);
-
-    // Calculate z (such that z^T = y^T + M_e * x^T)
-    // z : Z^{n x V}
-    // Note that we are creating a vector of columns, not a vector of rows.
-    let mut z = Vec::with_capacity(V);
-    for i in 0..V {
-        let mut z_col = Vec::with_capacity(V);
-
-        for j in 0..params.n {
-            // Get y^T values
-            let y_col = &y[i];
-            let mut val = y_col.coefficient(j).to_owned();
-
-            // Multiply M_e * x^T and add it
-            for k in 0..SEC {
-                let x_k = &x[k];
-                val += m_e[k][i] * x_k.coefficient(j).to_owned();
+impl PlaintextKnowledgeProof {
+    /// Proves that we know the plaintexts `x` and randomness `r` of the ciphertexts `c`, with the
+    /// challenge derived by Fiat-Shamir
+    ///
+    /// Starts over with a new first message whenever the prover aborts, at most `MAX_ATTEMPTS`
+    /// times.
+    pub fn prove(
+        params: &Parameters,
+        sec: Sec,
+        x: Vec<Polynomial>,
+        r: Vec<(Polynomial, Polynomial, Polynomial)>,
+        c: Vec<Ciphertext>,
+        diagonal: bool,
+        pk: &PublicKey,
+    ) -> Result<Self, MakeZKPoPKError> {
+        for _ in 0..MAX_ATTEMPTS {
+            let (a, prover_state) = commit_zkpopk(params, sec, diagonal, pk);
+            let mut proof =
+                Self::from_transcript(params, sec, c.clone(), a, (vec![], vec![]), diagonal, pk);
+            let e = proof.hash(params, sec);
+
+            if let Ok((z, t)) = respond_zkpopk(params, x.clone(), r.clone(), prover_state, &e) {
+                proof.z = z;
+                proof.t = t;
+                return Ok(proof);
             }
+        }
+        Err(MakeZKPoPKError::TooManyAborts)
+    }
 
-            z_col.push(val);
+    /// Puts together the proof of an interactive run, see `ChallengeMode::Interactive`
+    pub fn from_transcript(
+        params: &Parameters,
+        sec: Sec,
+        c: Vec<Ciphertext>,
+        a: Vec<Ciphertext>,
+        (z, t): (Vec<Vec<Integer>>, Vec<Vec<Integer>>),
+        diagonal: bool,
+        pk: &PublicKey,
+    ) -> Self {
+        Self {
+            c,
+            pk_fingerprint: public_key_fingerprint(params, pk),
+            diagonal,
+            sec: sec.bits(),
+            a,
+            z,
+            t,
         }
+    }
 
-        z.push(z_col);
+    /// Verifies the proof for the ciphertexts `self.c` under `pk`, which must have been made for
+    /// the statistical security parameter `sec`
+    ///
+    /// If `self.diagonal` is set, the plaintexts must also be diagonal, i.e. encode the same value
+    /// in every slot, which is a constant polynomial mod p.
+    pub fn verify(&self, params: &Parameters, sec: Sec, pk: &PublicKey) -> Result<(), ProofError> {
+        self.verify_with_challenge(params, sec, &self.hash(params, sec), pk)
     }
 
-    // Create R matrix
-    // This part makes me sad - the default state of any programmer.
-    let mut r_mat = Vec::with_capacity(SEC);
-    for (r1, r2, r3) in r.iter() {
-        let mut row = Vec::with_capacity(d);
+    /// Verifies a proof whose challenge `e` was chosen by the players, see
+    /// `ChallengeMode::Interactive`
+    pub fn verify_with_challenge(
+        &self,
+        params: &Parameters,
+        sec: Sec,
+        e: &Challenge,
+        pk: &PublicKey,
+    ) -> Result<(), ProofError> {
+        if self.sec != sec.bits() || self.pk_fingerprint != public_key_fingerprint(params, pk) {
+            return Err(ProofError::WrongStatement);
+        }
 
-        for c in r1.coefficients() {
-            row.push(c.to_owned());
+        let Self {
+            c,
+            diagonal,
+            a,
+            z,
+            t,
+            ..
+        } = self;
+        let bounds = Bounds::new(params, sec, *diagonal);
+        let d = params.n * 3;
+
+        // Check the dimensions of the proof, so malformed proofs cannot make us index out of bounds
+        let rows = bounds.rows;
+        if a.len() != rows || z.len() != rows || t.len() != rows || c.len() != sec.bits() {
+            return Err(ProofError::WrongLength);
+        }
+        if z.iter().any(|z_i| z_i.len() != params.n) || t.iter().any(|t_i| t_i.len() != d) {
+            return Err(ProofError::WrongLength);
         }
-        while row.len() < params.n {
-            row.push(Integer::ZERO)
+        let max_entry = if *diagonal { 1 } else { 2 * params.n };
+        if e.len() != rows
+            || e.iter()
+                .any(|e_i| e_i.len() != sec.bits() || e_i.iter().any(|&w| w > max_entry))
+        {
+            return Err(ProofError::WrongLength);
         }
 
-        for c in r2.coefficients() {
-            row.push(c.to_owned());
+        // Check d_i = enc_pk(z_i, t_i) = a_i + (W * c)_i
+        for (i, ((a_i, e_i), (z_i, t_i))) in a.iter().zip(e).zip(z.iter().zip(t)).enumerate() {
+            let d_i = encrypt_det(
+                params,
+                Polynomial::new(z_i.clone()),
+                pk,
+                split_randomness(params, t_i),
+            );
+
+            let mut sum = a_i.clone();
+            for (&w, c_k) in e_i.iter().zip(c) {
+                if w != 0 {
+                    let w_c_k: Ciphertext = c_k
+                        .iter()
+                        .map(|c_kj| monomial_multiple(params, c_kj, w))
+                        .collect();
+                    sum = add(params, &sum, &w_c_k);
+                }
+            }
+
+            if sum != d_i {
+                return Err(ProofError::EncryptionMismatch(i));
+            }
         }
-        while row.len() < params.n * 2 {
-            row.push(Integer::ZERO)
+
+        // z_i = y_i + (W * x)_i is diagonal if the x_k and the masks y_i are
+        if *diagonal {
+            if let Some(i) = z.iter().position(|z_i| !is_diagonal(params, z_i)) {
+                return Err(ProofError::NotDiagonal(i));
+            }
         }
 
-        for c in r3.coefficients() {
-            row.push(c.to_owned());
+        if let Some(i) = z.iter().position(|z_i| !below_bound(z_i, &bounds.z)) {
+            return Err(ProofError::ZLInfNormTooBig(i));
         }
-        while row.len() < params.n * 3 {
-            row.push(Integer::ZERO)
+        if let Some(i) = t.iter().position(|t_i| !below_bound(t_i, &bounds.t)) {
+            return Err(ProofError::TLInfNormTooBig(i));
         }
 
-        r_mat.push(row);
+        Ok(())
     }
 
-    // Calculate M_e * R
-    let mut m_e_mul_r = vec![vec![Integer::ZERO; d]; V];
-    for (row_index, row) in m_e_mul_r.iter_mut().enumerate() {
-        for (col_index, val) in row.iter_mut().enumerate() {
-            for i in 0..SEC {
-                *val += m_e[i][row_index] * r_mat[i][col_index].clone()
-            }
-        }
+    /// Writes the proof with `serialization::plaintext_knowledge_proof_to_bytes`
+    pub fn to_bytes(&self, params: &Parameters) -> Vec<u8> {
+        plaintext_knowledge_proof_to_bytes(params, self)
     }
 
-    // Calculate T = S + M_e * R
-    let mut t = vec![vec![Integer::ZERO; d]; V];
-    for (t_row_index, t_row) in t.iter_mut().enumerate() {
-        let (s_row_1, s_row_2, s_row_3) = &s[t_row_index];
-        let mut s_row = s_row_1
-            .coefficients()
-            .chain(s_row_2.coefficients())
-            .chain(s_row_3.coefficients());
-
-        for (t_col_index, t_val) in t_row.iter_mut().enumerate() {
-            let s_val = s_row.next().unwrap().to_owned();
-            let m_e_mul_r_val = &m_e_mul_r[t_row_index][t_col_index];
-
-            *t_val = s_val + m_e_mul_r_val;
-        }
+    /// Reads a proof written with `to_bytes`
+    pub fn from_bytes(params: &Parameters, bytes: &[u8]) -> Result<Self, SerializationError> {
+        plaintext_knowledge_proof_from_bytes(params, bytes)
     }
 
-    // Ensure that ||z_i||_inf is at most 128 * N * tau * sec^2 - tau * sec
-    let z_i_bound = y_i_bound - (tau * Integer::from(SEC));
-    for z_i in z.iter() {
-        if !check_l_inf_norm_below_bound(&Polynomial::from(z_i.to_owned()), &z_i_bound) {
-            return Err(MakeZKPoPKError::ZLInfNormTooBig);
-        }
-    }
+    /// Hash the statement and `a` to get a random challenge `e`
+    fn hash(&self, params: &Parameters, sec: Sec) -> Challenge {
+        let mut hasher = Shake256::default();
 
-    // Ensure that ||t_i||_inf is at most 128 * d * rho * sec^2 - rho * sec
-    let t_i_bound = s_i_bound - (rho * Integer::from(SEC));
-    for t_i in t.iter() {
-        if !check_l_inf_norm_below_bound(&Polynomial::from(t_i.to_owned()), &t_i_bound) {
-            return Err(MakeZKPoPKError::TLInfNormTooBig);
+        hasher.update(&self.pk_fingerprint);
+        hasher.update(&[self.diagonal as u8]);
+        hasher.update(&(self.sec as u32).to_le_bytes());
+        for ciphertext in self.a.iter().chain(&self.c) {
+            hasher.update(&ciphertext_to_bytes(params, ciphertext));
         }
+
+        let mut seed = [0_u8; 32];
+        hasher.finalize_xof().read(&mut seed);
+        challenge_from_seed(params, sec, &seed, self.diagonal)
     }
+}
 
-    Ok((a, z, t))
+/// A short hash of `pk`, which identifies the key a proof is about
+pub fn public_key_fingerprint(params: &Parameters, pk: &PublicKey) -> [u8; 32] {
+    Sha256::digest(public_key_to_bytes(params, pk)).into()
 }
 
-/// Verify the validity of a zero-knowledge proof of plaintext knowledge
-pub fn verify_zkpopk(
+/// The first message a of a proof, and the randomness used for it
+pub fn commit_zkpopk(
     params: &Parameters,
-    a: Vec<Vec<Polynomial>>,
-    z: Vec<Vec<Integer>>,
-    t: Vec<Vec<Integer>>,
-    c: Vec<Ciphertext>,
+    sec: Sec,
+    diagonal: bool,
     pk: &PublicKey,
-) -> bool {
-    // encrypt d_i = enc_pk(z_i, t_i)
-    let mut d = Vec::with_capacity(V);
-    for i in 0..V {
-        let (t_1, t_23) = t[i].split_at(params.n);
-        let (t_2, t_3) = t_23.split_at(params.n);
-        let t = (
-            Polynomial::new(t_1.iter().map(|x| x.to_owned()).collect()),
-            Polynomial::new(t_2.iter().map(|x| x.to_owned()).collect()),
-            Polynomial::new(t_3.iter().map(|x| x.to_owned()).collect()),
-        );
+) -> (Vec<Ciphertext>, ProverState) {
+    let bounds = Bounds::new(params, sec, diagonal);
+    let d = params.n * 3;
 
-        d.push(encrypt_det(params, Polynomial::new(z[i].clone()), pk, t))
+    let mut y = Vec::with_capacity(bounds.rows);
+    let mut s = Vec::with_capacity(bounds.rows);
+    let mut a = Vec::with_capacity(bounds.rows);
+    for _ in 0..bounds.rows {
+        // A diagonal y_i is a constant, so that z_i is diagonal as well
+        let y_i = if diagonal {
+            let mut y_i = vec![Integer::ZERO; params.n];
+            y_i[0] = sample_symmetric(&bounds.y, 1).remove(0);
+            y_i
+        } else {
+            sample_symmetric(&bounds.y, params.n)
+        };
+        let s_i = sample_symmetric(&bounds.s, d);
+
+        a.push(encrypt_det(
+            params,
+            Polynomial::new(y_i.clone()),
+            pk,
+            split_randomness(params, &s_i),
+        ));
+        y.push(y_i);
+        s.push(s_i);
     }
 
-    // creates the m_e matrix
-    let e = hash(&a, &c);
-    let m_e = create_m_e_from_e(e);
-
-    // The verifier checks decode(z_i) \in f_{p_k}^s
-    let mut decoded_z_is = Vec::with_capacity(V);
-    for z_i in &z {
-        decoded_z_is.push(decode(Polynomial::new(z_i.to_owned())));
-    }
-    if decoded_z_is.len() > V {
-        println!("Length of zero knowledge proof is wrong");
-        return false;
-    }
+    (
+        a,
+        ProverState {
+            y,
+            s,
+            diagonal,
+            sec,
+        },
+    )
+}
 
-    // Check d^t = a^t |+| (m_e |*| c^t)
-    for i in 0..V {
-        let mut sum = Ciphertext::new();
-        for j in 0..SEC {
-            if m_e[j][i] == 1 {
-                sum = add(params, &sum, &c[j]);
+/// The response (z, t) of a proof for the plaintexts `x` with randomness `r` to the challenge `e`
+///
+/// Fails if the response could leak information about x and r, in which case the prover has to
+/// start over with a new first message.
+#[allow(clippy::type_complexity)]
+pub fn respond_zkpopk(
+    params: &Parameters,
+    x: Vec<Polynomial>,
+    r: Vec<(Polynomial, Polynomial, Polynomial)>,
+    prover_state: ProverState,
+    e: &Challenge,
+) -> Result<(Vec<Vec<Integer>>, Vec<Vec<Integer>>), MakeZKPoPKError> {
+    let ProverState {
+        y,
+        s,
+        diagonal,
+        sec,
+    } = prover_state;
+    let bounds = Bounds::new(params, sec, diagonal);
+
+    let x: Vec<Vec<Integer>> = x.iter().map(|x_k| coefficients(x_k, params.n)).collect();
+    let r: Vec<Vec<Integer>> = r
+        .iter()
+        .map(|(v, e_prime, e_prime_prime)| {
+            [v, e_prime, e_prime_prime]
+                .iter()
+                .flat_map(|part| coefficients(part, params.n))
+                .collect()
+        })
+        .collect();
+
+    // z_i = y_i + sum_k W_ik * x_k and t_i = s_i + sum_k W_ik * r_k
+    let mut z = Vec::with_capacity(e.len());
+    let mut t = Vec::with_capacity(e.len());
+    for ((e_i, y_i), s_i) in e.iter().zip(y).zip(s) {
+        let mut z_i = y_i;
+        let mut t_i = s_i;
+        for ((&w, x_k), r_k) in e_i.iter().zip(&x).zip(&r) {
+            add_monomial_multiple(&mut z_i, x_k, w, params.n);
+            for (t_part, r_part) in t_i.chunks_mut(params.n).zip(r_k.chunks(params.n)) {
+                add_monomial_multiple(t_part, r_part, w, params.n);
             }
         }
 
-        let test = add(params, &a[i], &sum);
-
-        if test != d[i] {
-            println!("There was a failure in the d_i test!");
-            return false;
+        // Rejection sampling: the accepted responses are uniform, whatever x and r are
+        if !below_bound(&z_i, &bounds.z) {
+            return Err(MakeZKPoPKError::ZLInfNormTooBig);
         }
-    }
-
-    // ||z_i||_{inf} <= 128 * N * t * sec^2
-    let tau = &params.p / Integer::from(2_i32);
-    for z_i in z {
-        let z_i_inf_ok = Polynomial::new(z_i).l_inf_norm()
-            <= Integer::from(128_i32) * Integer::from(params.n) * &tau * Integer::from(SEC.pow(2));
-
-        if !z_i_inf_ok {
-            println!("z_i_inf_norm was not ok!");
-            return false;
+        if !below_bound(&t_i, &bounds.t) {
+            return Err(MakeZKPoPKError::TLInfNormTooBig);
         }
+        z.push(z_i);
+        t.push(t_i);
     }
 
-    // ||t_i||_{inf} <= 128 * d * p * sec^2
-    let rho = Integer::from(2_i32)
-        * Integer::from(params.r as i64)
-        * Integer::sqrt(Integer::from(params.n));
-    let d = params.n * 3;
-    for t_i in t {
-        let t_i_inf_norm_ok = Polynomial::new(t_i).l_inf_norm()
-            <= Integer::from(128_i32)
-                * Integer::from(d)
-                * Integer::from(&rho)
-                * Integer::from(SEC.pow(2));
-        if !t_i_inf_norm_ok {
-            println!("t_i_inf_norm was not ok!");
-            return false;
-        }
-    }
-    // TODO: check if decode(z_i) is a diagonal argument if diag is set to true!
+    Ok((z, t))
+}
 
-    true
+/// Derive the challenge `e` from a seed, e.g. one the players sampled together
+pub fn challenge_from_seed(
+    params: &Parameters,
+    sec: Sec,
+    seed: &[u8; 32],
+    diagonal: bool,
+) -> Challenge {
+    let rows = challenge_rows(params, sec, diagonal);
+    let choices = Integer::from(if diagonal { 2 } else { 2 * params.n + 1 });
+
+    let mut prg = Prg::new(seed);
+    (0..rows)
+        .map(|_| {
+            prg.sample_n_below(&choices, sec.bits())
+                .iter()
+                .map(|w| w.to_usize().unwrap())
+                .collect()
+        })
+        .collect()
 }
 
-/// Hash `(a, c)` to get a random value `e`
-fn hash(a: &[Vec<Polynomial>], c: &[Vec<Polynomial>]) -> Vec<u8> {
-    let mut hasher = Shake256::default();
+/// The number of rows of the challenge and the bounds of a proof
+struct Bounds {
+    /// The number V of rows, see `challenge_rows`
+    rows: usize,
+    /// Coefficients of the masks y_i and s_i are sampled from [-y, y] and [-s, s]
+    y: Integer,
+    s: Integer,
+    /// Coefficients of accepted responses z_i and t_i are in [-z, z] and [-t, t]
+    z: Integer,
+    t: Integer,
+}
 
-    for ciphertext in a {
-        for p in ciphertext {
-            hasher.update(&polynomial_to_bytes(p));
-        }
-    }
-    for ciphertext in c {
-        for p in ciphertext {
-            hasher.update(&polynomial_to_bytes(p));
-        }
-    }
+impl Bounds {
+    fn new(params: &Parameters, sec: Sec, diagonal: bool) -> Self {
+        let rows = challenge_rows(params, sec, diagonal);
+        let sec = sec.bits();
 
-    let mut reader = hasher.finalize_xof();
-    let mut e_bytes = [0_u8; SEC / 8];
-    reader.read(&mut e_bytes);
+        // The plaintexts are in [0, p) and the randomness is Gaussian
+        let x_bound = Integer::from(&params.p * sec as u64);
+        let r_bound = gaussian_bound(params) * sec as u64;
 
-    let mut e_bit_string = String::new();
-    for e_byte in e_bytes {
-        e_bit_string = format!("{}{:08b}", e_bit_string, &e_byte);
+        // Each coefficient is rejected with probability at most 1 / (sec * coefficients), so a
+        // response is rejected with probability about 2 / sec
+        let y_coefficients = if diagonal { rows } else { params.n * rows };
+        let y = Integer::from(&x_bound * (sec * y_coefficients) as u64);
+        let s = Integer::from(&r_bound * (sec * 3 * params.n * rows) as u64);
+        let z = Integer::from(&y - &x_bound);
+        let t = Integer::from(&s - &r_bound);
+
+        Bounds { rows, y, s, z, t }
     }
+}
 
-    let mut e_bits = Vec::with_capacity(SEC);
-    for c in e_bit_string.chars() {
-        match c {
-            '0' => e_bits.push(0),
-            '1' => e_bits.push(1),
-            _ => unreachable!("should only have 0's and 1's"),
-        }
+/// The number of rows of a challenge, such that there are at least 2^(sec + 2) challenges when
+/// every entry is 0 or 1 (`diagonal`) or 0 or a monomial
+pub(crate) fn challenge_rows(params: &Parameters, sec: Sec, diagonal: bool) -> usize {
+    let choices = if diagonal { 2 } else { 2 * params.n + 1 };
+    let challenges_needed = Integer::from(1) << (sec.bits() as u32 + 2);
+    let mut rows = 0;
+    let mut challenges = Integer::from(1);
+    while challenges < challenges_needed {
+        challenges *= choices as u64;
+        rows += 1;
     }
+    rows
+}
 
-    e_bits
+/// Samples `n` integers uniformly from [-bound, bound]
+pub(crate) fn sample_symmetric(bound: &Integer, n: usize) -> Vec<Integer> {
+    let range = Integer::from(bound * 2_i32) + 1_i32;
+    coefficients(&sample_from_uniform(&range, n), n)
+        .into_iter()
+        .map(|c| c - bound)
+        .collect()
 }
 
-/// A little hack to convert a polynomial to bytes
-fn polynomial_to_bytes(p: &Polynomial) -> Vec<u8> {
-    let json = serde_json::to_string(p).unwrap();
-    json.as_bytes().to_owned()
+/// The `n` coefficients of `pol`, padded with zeros
+pub(crate) fn coefficients(pol: &Polynomial, n: usize) -> Vec<Integer> {
+    (0..n).map(|i| pol.coefficient(i)).collect()
 }
 
-fn create_m_e_from_e(e: Vec<u8>) -> Vec<Vec<u8>> {
-    let mut m_e = vec![vec![0_u8; V]; SEC];
-    for (i, row) in m_e.iter_mut().enumerate() {
-        for (k, item) in row.iter_mut().enumerate() {
-            let e_index = i as i32 - k as i32;
+/// Splits the 3N coefficients of randomness into (v, e', e'')
+fn split_randomness(params: &Parameters, t: &[Integer]) -> (Polynomial, Polynomial, Polynomial) {
+    let (t_1, t_23) = t.split_at(params.n);
+    let (t_2, t_3) = t_23.split_at(params.n);
+    (
+        Polynomial::new(t_1.to_vec()),
+        Polynomial::new(t_2.to_vec()),
+        Polynomial::new(t_3.to_vec()),
+    )
+}
 
-            if (0..(SEC as i32)).contains(&e_index) {
-                *item = e[e_index as usize];
-            }
+/// Adds W * x to `acc`, where W is the challenge entry `w` and all vectors are coefficients of
+/// polynomials in Z[X]/(X^N + 1)
+pub(crate) fn add_monomial_multiple(acc: &mut [Integer], x: &[Integer], w: usize, n: usize) {
+    if w == 0 {
+        return;
+    }
+    let shift = w - 1;
+    for (i, x_i) in x.iter().enumerate() {
+        // X^i * X^shift = X^(i + shift), and X^N = -1
+        let j = (i + shift) % (2 * n);
+        if j < n {
+            acc[j] += x_i;
+        } else {
+            acc[j - n] -= x_i;
         }
     }
-    m_e
 }
 
-fn check_l_inf_norm_below_bound(pol: &Polynomial, bound: &Integer) -> bool {
-    &pol.l_inf_norm() <= bound
+/// W * pol in Rq, for the non-zero challenge entry `w`
+pub(crate) fn monomial_multiple(params: &Parameters, pol: &Polynomial, w: usize) -> Polynomial {
+    let mut res = vec![Integer::ZERO; params.n];
+    add_monomial_multiple(&mut res, &coefficients(pol, params.n), w, params.n);
+    Polynomial::new(res).modulo(&params.quotient_ring.q)
+}
+
+/// Checks that all but the constant coefficient are 0 mod p
+fn is_diagonal(params: &Parameters, coefficients: &[Integer]) -> bool {
+    coefficients
+        .iter()
+        .skip(1)
+        .all(|coefficient| coefficient.is_divisible(&params.p))
+}
+
+pub(crate) fn below_bound(coefficients: &[Integer], bound: &Integer) -> bool {
+    coefficients
+        .iter()
+        .all(|c| c.as_abs().cmp(bound) != std::cmp::Ordering::Greater)
 }
 
 #[cfg(test)]
 mod tests {
-    use rug::{rand::RandState, Integer};
+    use rug::{ops::Pow, rand::RandState, Integer};
 
     use crate::{
         encryption::{
             self, encrypt_with_rand, generate_key_pair, Ciphertext, Parameters, PublicKey,
             SecretKey,
         },
+        mpc::Sec,
         poly::Polynomial,
     };
 
-    use super::{make_zkpopk, verify_zkpopk, SEC};
+    use super::{
+        challenge_from_seed, challenge_rows, commit_zkpopk, respond_zkpopk,
+        PlaintextKnowledgeProof, ProofError, SerializationError,
+    };
 
     #[allow(clippy::type_complexity)]
     fn setup(
         params: &Parameters,
+        sec: Sec,
     ) -> (
         PublicKey,
         SecretKey,
@@ -338,10 +543,10 @@ mod tests {
     ) {
         let (pk, sk) = generate_key_pair(params);
 
-        let mut x = Vec::with_capacity(SEC);
-        let mut r = Vec::with_capacity(SEC);
-        let mut c = Vec::with_capacity(SEC);
-        for _ in 0..SEC {
+        let mut x = Vec::with_capacity(sec.bits());
+        let mut r = Vec::with_capacity(sec.bits());
+        let mut c = Vec::with_capacity(sec.bits());
+        for _ in 0..sec.bits() {
             let x_i = Polynomial::new(vec![random_integer()]).modulo(&params.p);
             let (c_i, r_i) = encrypt_with_rand(params, x_i.clone(), &pk);
             x.push(x_i);
@@ -360,39 +565,288 @@ mod tests {
     #[test]
     fn verify_accepts_valid_zkpopk() {
         let params = Parameters::default();
-        let (pk, _sk, x, r, c) = setup(&params);
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
 
-        let (a, z, t) = make_zkpopk(&params, x, r, c.clone(), false, &pk).unwrap();
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, false, &pk).unwrap();
 
-        assert!(
-            verify_zkpopk(&params, a, z, t, c, &pk),
-            "proof was not valid"
-        )
+        assert_eq!(proof.verify(&params, sec, &pk), Ok(()))
     }
 
     #[test]
     fn verify_accepts_valid_zkpopk_with_secure_params() {
         let params = encryption::secure_params();
-        let (pk, _sk, x, r, c) = setup(&params);
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
 
-        let (a, z, t) = make_zkpopk(&params, x, r, c.clone(), false, &pk).unwrap();
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, false, &pk).unwrap();
 
-        assert!(
-            verify_zkpopk(&params, a, z, t, c, &pk),
-            "proof was not valid"
-        )
+        assert_eq!(proof.verify(&params, sec, &pk), Ok(()))
     }
 
     #[test]
     fn verify_accepts_valid_zkpopk_diagonal() {
         let params = Parameters::default();
-        let (pk, _sk, x, r, c) = setup(&params);
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
 
-        let (a, z, t) = make_zkpopk(&params, x, r, c.clone(), true, &pk).unwrap();
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, true, &pk).unwrap();
 
-        assert!(
-            verify_zkpopk(&params, a, z, t, c, &pk),
-            "proof was not valid"
+        assert_eq!(proof.verify(&params, sec, &pk), Ok(()))
+    }
+
+    #[test]
+    fn verify_rejects_proof_of_wrong_length() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let (pk, _sk, _x, _r, c) = setup(&params, sec);
+
+        let proof = PlaintextKnowledgeProof::from_transcript(
+            &params,
+            sec,
+            c,
+            vec![],
+            (vec![], vec![]),
+            false,
+            &pk,
+        );
+
+        assert_eq!(
+            proof.verify(&params, sec, &pk),
+            Err(ProofError::WrongLength)
+        )
+    }
+
+    #[test]
+    fn verify_rejects_tampered_zkpopk() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
+
+        let mut proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, false, &pk).unwrap();
+        proof.z[0][0] += 1;
+
+        assert_eq!(
+            proof.verify(&params, sec, &pk),
+            Err(ProofError::EncryptionMismatch(0))
+        )
+    }
+
+    #[test]
+    fn verify_rejects_non_diagonal_zkpopk() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let (pk, _sk) = generate_key_pair(&params);
+
+        // Plaintexts with a non-constant coefficient
+        let mut x = Vec::with_capacity(sec.bits());
+        let mut r = Vec::with_capacity(sec.bits());
+        let mut c = Vec::with_capacity(sec.bits());
+        for _ in 0..sec.bits() {
+            let x_i = Polynomial::new(vec![random_integer(), Integer::from(1)]).modulo(&params.p);
+            let (c_i, r_i) = encrypt_with_rand(&params, x_i.clone(), &pk);
+            x.push(x_i);
+            r.push(r_i);
+            c.push(c_i);
+        }
+
+        // The same plaintexts can be proven, but not as diagonal ones
+        let proof = PlaintextKnowledgeProof::prove(
+            &params,
+            sec,
+            x.clone(),
+            r.clone(),
+            c.clone(),
+            false,
+            &pk,
         )
+        .unwrap();
+        assert_eq!(proof.verify(&params, sec, &pk), Ok(()));
+
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, true, &pk).unwrap();
+        assert!(matches!(
+            proof.verify(&params, sec, &pk),
+            Err(ProofError::NotDiagonal(_))
+        ));
+    }
+
+    #[test]
+    fn verify_accepts_zkpopk_with_joint_challenge() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
+
+        let (a, z, t, e) = loop {
+            let (a, prover_state) = commit_zkpopk(&params, sec, false, &pk);
+            let e = challenge_from_seed(&params, sec, &[7; 32], false);
+            if let Ok((z, t)) = respond_zkpopk(&params, x.clone(), r.clone(), prover_state, &e) {
+                break (a, z, t, e);
+            }
+        };
+
+        let proof =
+            PlaintextKnowledgeProof::from_transcript(&params, sec, c, a, (z, t), false, &pk);
+        assert_eq!(proof.verify_with_challenge(&params, sec, &e, &pk), Ok(()));
+
+        // The response only works for the challenge it was made for
+        let other_e = challenge_from_seed(&params, sec, &[8; 32], false);
+        assert!(matches!(
+            proof.verify_with_challenge(&params, sec, &other_e, &pk),
+            Err(ProofError::EncryptionMismatch(_))
+        ));
+    }
+
+    #[test]
+    fn monomial_challenges_need_fewer_rows() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let e = challenge_from_seed(&params, sec, &[7; 32], false);
+        let diagonal_e = challenge_from_seed(&params, sec, &[7; 32], true);
+
+        // Every row has 2N + 1 choices per entry instead of 2, so fewer rows give sec + 2 bits
+        assert!(e.len() < diagonal_e.len());
+        assert!(
+            Integer::from(2 * params.n + 1).pow(e.len() as u32)
+                >= Integer::from(1) << (sec.bits() + 2)
+        );
+        assert!(e.iter().flatten().all(|&w| w <= 2 * params.n));
+        assert!(e.iter().flatten().any(|&w| w > 1));
+        assert!(diagonal_e.iter().flatten().all(|&w| w <= 1));
+        assert!(e
+            .iter()
+            .chain(&diagonal_e)
+            .all(|e_i| e_i.len() == sec.bits()));
+    }
+
+    #[test]
+    fn verify_rejects_challenge_out_of_range() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
+
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, true, &pk).unwrap();
+
+        // A diagonal proof only allows the challenges 0 and 1
+        let mut e = challenge_from_seed(&params, sec, &[7; 32], true);
+        e[0][0] = 2;
+        assert_eq!(
+            proof.verify_with_challenge(&params, sec, &e, &pk),
+            Err(ProofError::WrongLength)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_proof_for_other_statement() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
+        let (other_pk, _other_sk) = generate_key_pair(&params);
+
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, false, &pk).unwrap();
+        assert_eq!(
+            proof.verify(&params, sec, &other_pk),
+            Err(ProofError::WrongStatement)
+        );
+
+        // A proof made for another security parameter does not match ours
+        assert_eq!(
+            proof.verify(&params, Sec::new(64).unwrap(), &pk),
+            Err(ProofError::WrongStatement)
+        );
+        let mut other_sec = proof.clone();
+        other_sec.sec = 64;
+        assert_eq!(
+            other_sec.verify(&params, sec, &pk),
+            Err(ProofError::WrongStatement)
+        );
+
+        // The flag is part of the hashed statement, so it cannot be changed either
+        let mut diagonal = proof;
+        diagonal.diagonal = true;
+        assert!(diagonal.verify(&params, sec, &pk).is_err());
+    }
+
+    #[test]
+    fn verify_accepts_zkpopk_with_higher_sec() {
+        let params = Parameters::default();
+        let sec = Sec::new(64).unwrap();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
+
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, false, &pk).unwrap();
+        assert_eq!(proof.sec, 64);
+        assert_eq!(proof.verify(&params, sec, &pk), Ok(()));
+        assert_eq!(
+            proof.verify(&params, Sec::default(), &pk),
+            Err(ProofError::WrongStatement)
+        );
+    }
+
+    #[test]
+    fn proof_round_trips_through_bytes() {
+        let params = Parameters::default();
+        let sec = Sec::default();
+        let (pk, _sk, x, r, c) = setup(&params, sec);
+        let proof = PlaintextKnowledgeProof::prove(&params, sec, x, r, c, true, &pk).unwrap();
+
+        let bytes = proof.to_bytes(&params);
+        let decoded = PlaintextKnowledgeProof::from_bytes(&params, &bytes).unwrap();
+        assert_eq!(decoded, proof);
+        assert_eq!(decoded.verify(&params, sec, &pk), Ok(()));
+
+        // The last bytes belong to the last response, which no longer matches
+        let mut tampered = bytes.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        let tampered = PlaintextKnowledgeProof::from_bytes(&params, &tampered).unwrap();
+        assert!(tampered.verify(&params, sec, &pk).is_err());
+
+        assert_eq!(
+            PlaintextKnowledgeProof::from_bytes(&params, &bytes[..bytes.len() - 1]),
+            Err(SerializationError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn from_bytes_bounds_response_rows() {
+        let params = Parameters::default();
+        let rows = challenge_rows(&params, Sec::default(), false);
+        let proof = PlaintextKnowledgeProof {
+            c: vec![],
+            pk_fingerprint: [0; 32],
+            diagonal: false,
+            sec: Sec::default().bits(),
+            a: vec![],
+            z: vec![],
+            t: vec![],
+        };
+        let bytes = proof.to_bytes(&params);
+
+        // Replaces the empty responses with `count` rows of coefficients of `width` bytes
+        let with_rows = |count: u32, width: u32| {
+            let mut bytes = bytes[..bytes.len() - 16].to_vec();
+            for (count, width) in [(count, width), (0, 0)] {
+                bytes.extend(count.to_le_bytes());
+                bytes.extend(width.to_le_bytes());
+            }
+            PlaintextKnowledgeProof::from_bytes(&params, &bytes)
+        };
+
+        assert!(with_rows(rows as u32, 0).is_ok());
+        assert_eq!(
+            with_rows(u32::MAX, 0),
+            Err(SerializationError::TooManyRows {
+                expected: rows,
+                found: u32::MAX as usize
+            })
+        );
+        assert_eq!(
+            with_rows(rows as u32, u32::MAX),
+            Err(SerializationError::UnexpectedEnd)
+        );
+
+        let unsupported = PlaintextKnowledgeProof { sec: 41, ..proof };
+        assert_eq!(
+            PlaintextKnowledgeProof::from_bytes(&params, &unsupported.to_bytes(&params)),
+            Err(SerializationError::UnsupportedSecurityParameter(41))
+        );
     }
 }
@@ -47,7 +47,7 @@ impl Polynomial {
         norm
     }
 
-    pub fn coefficients(&self) -> Iter<Integer> {
+    pub fn coefficients(&self) -> Iter<'_, Integer> {
         self.0.iter()
     }
 
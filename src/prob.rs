@@ -2,9 +2,14 @@ use crate::poly::Polynomial;
 use probability::prelude::{source, Gaussian, Independent};
 use rand::{rngs::OsRng, RngCore};
 use rug::{
+    integer::Order,
     rand::{RandGen, RandState},
     Integer,
 };
+use sha3::{
+    digest::{ExtendableOutput, Update, XofReader},
+    Shake256,
+};
 
 /// Creating Source to use rand package as source of randomness
 struct Source<T>(T);
@@ -62,3 +67,94 @@ pub fn sample_single(i: &Integer) -> Integer {
 
     i.to_owned().random_below(&mut rand_state)
 }
+
+/// Cryptographically secure PRG that expands a shared 32-byte seed using SHAKE256.
+///
+/// Every party holding the same seed obtains the same stream, so it can be used to derive public coins.
+pub struct Prg {
+    reader: <Shake256 as ExtendableOutput>::Reader,
+}
+
+impl Prg {
+    pub fn new(seed: &[u8; 32]) -> Self {
+        let mut hasher = Shake256::default();
+        hasher.update(seed);
+        Self {
+            reader: hasher.finalize_xof(),
+        }
+    }
+
+    /// Returns a uniform sample in the interval [0, bound) using rejection sampling, so there is no modulo bias.
+    pub fn sample_below(&mut self, bound: &Integer) -> Integer {
+        assert!(*bound > 0, "bound must be positive");
+
+        let bits = bound.significant_bits();
+        let mut bytes = vec![0_u8; bits.div_ceil(8) as usize];
+        let excess_bits = bytes.len() as u32 * 8 - bits;
+
+        loop {
+            self.reader.read(&mut bytes);
+            // Clear the excess high bits so the candidate is below 2^bits
+            bytes[0] &= 0xff >> excess_bits;
+
+            let candidate = Integer::from_digits(&bytes, Order::MsfBe);
+            if &candidate < bound {
+                return candidate;
+            }
+        }
+    }
+
+    /// Returns n uniform samples in the interval [0, bound)
+    pub fn sample_n_below(&mut self, bound: &Integer, n: usize) -> Vec<Integer> {
+        (0..n).map(|_| self.sample_below(bound)).collect()
+    }
+}
+
+impl RandGen for Prg {
+    fn gen(&mut self) -> u32 {
+        let mut bytes = [0_u8; 4];
+        self.reader.read(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rug::Integer;
+
+    use super::Prg;
+
+    #[test]
+    fn prg_is_deterministic_for_same_seed() {
+        let p = Integer::from(127);
+        let mut prg_1 = Prg::new(&[7; 32]);
+        let mut prg_2 = Prg::new(&[7; 32]);
+
+        assert_eq!(prg_1.sample_n_below(&p, 100), prg_2.sample_n_below(&p, 100));
+    }
+
+    #[test]
+    fn prg_differs_for_different_seeds() {
+        let p = Integer::from(1) << 128;
+        let mut prg_1 = Prg::new(&[1; 32]);
+        let mut prg_2 = Prg::new(&[2; 32]);
+
+        assert_ne!(prg_1.sample_n_below(&p, 4), prg_2.sample_n_below(&p, 4));
+    }
+
+    #[test]
+    fn prg_samples_are_below_bound_and_cover_range() {
+        let p = Integer::from(5);
+        let mut prg = Prg::new(&[42; 32]);
+
+        let samples = prg.sample_n_below(&p, 1000);
+        assert!(samples.iter().all(|x| *x >= 0 && *x < p));
+        for v in 0..5 {
+            assert!(
+                samples.contains(&Integer::from(v)),
+                "{} was never sampled",
+                v
+            );
+        }
+    }
+}
@@ -4,11 +4,16 @@ use rug::Integer;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    encryption::{Ciphertext, PublicKey},
+    encryption::{Parameters, PublicKey},
+    mpc::{
+        decryption_proof::DecryptionProof,
+        zk::{ChallengeMode, PlaintextKnowledgeProof},
+        Sec,
+    },
     poly::Polynomial,
 };
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct KeyMaterial {
     pub pk: PublicKey,
     pub sk_i1: Polynomial,
@@ -17,25 +22,78 @@ pub struct KeyMaterial {
 
 #[derive(Serialize, Deserialize, Debug)]
 pub enum PrepMessage {
-    Start(SocketAddr),
+    /// A player is ready, and tells whether it takes the preprocessing from a store, which needs
+    /// the same public key as when the store was produced
+    Start {
+        addr: SocketAddr,
+        stored_preprocessing: bool,
+    },
     PlayerConnected(SocketAddr),
-    KeyMaterial(KeyMaterial),
+    /// The parameters of the session, and key material encoded under them with
+    /// `serialization::key_material_to_bytes`. If `threshold` is set, the secret key shares are
+    /// Shamir shares from `mpc::threshold` instead of additive shares.
+    KeyMaterial {
+        params: Parameters,
+        key_material: Vec<u8>,
+        threshold: Option<usize>,
+    },
+    /// The parameters of the session, when the players generate their keys with `mpc::dkg`
+    Parameters(Parameters),
+    /// How the challenges of the ZKPoPKs are chosen, sent before the parameters if not the default
+    ChallengeMode(ChallengeMode),
+    /// The statistical security parameter, sent before the parameters if not the default
+    Sec(Sec),
+    /// The dealer cannot set up the session for the given reason
+    Abort(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum OnlineMessage {
     SharePoly(Polynomial),
-    ShareCiphertext(Ciphertext),
+    /// Ciphertext encoded with `serialization::ciphertext_to_bytes`
+    ShareCiphertext(Vec<u8>),
+    /// Public key encoded with `serialization::public_key_to_bytes`
+    SharePublicKey(Vec<u8>),
     ShareInteger(Integer),
     ShareCommitment(Vec<u8>),
     ShareCommitOpen(Vec<u8>),
     BeginInput,
-    ShareZKPoPK {
-        a: Vec<Vec<Polynomial>>,
+    ShareZKPoPK(PlaintextKnowledgeProof),
+    /// First message of a ZKPoPK with `ChallengeMode::Interactive`, along with the statement.
+    /// The ciphertexts are encoded with `serialization::ciphertext_to_bytes`.
+    ShareZKPoPKCommitment {
+        a: Vec<Vec<u8>>,
+        c: Vec<Vec<u8>>,
+    },
+    /// Response to the challenge of an interactive ZKPoPK
+    ShareZKPoPKResponse {
         z: Vec<Vec<Integer>>,
         t: Vec<Vec<Integer>>,
-        c: Vec<Vec<Polynomial>>,
     },
+    /// Sent instead of the response if the prover aborted, after which all players start over
+    AbortZKPoPK,
+    /// Proof that the decryption share sent before it is correct
+    ShareDecryptionProof(DecryptionProof),
+}
+
+impl OnlineMessage {
+    /// The name of the message type, used in error messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            OnlineMessage::SharePoly(_) => "SharePoly",
+            OnlineMessage::ShareCiphertext(_) => "ShareCiphertext",
+            OnlineMessage::SharePublicKey(_) => "SharePublicKey",
+            OnlineMessage::ShareInteger(_) => "ShareInteger",
+            OnlineMessage::ShareCommitment(_) => "ShareCommitment",
+            OnlineMessage::ShareCommitOpen(_) => "ShareCommitOpen",
+            OnlineMessage::BeginInput => "BeginInput",
+            OnlineMessage::ShareZKPoPK(_) => "ShareZKPoPK",
+            OnlineMessage::ShareZKPoPKCommitment { .. } => "ShareZKPoPKCommitment",
+            OnlineMessage::ShareZKPoPKResponse { .. } => "ShareZKPoPKResponse",
+            OnlineMessage::AbortZKPoPK => "AbortZKPoPK",
+            OnlineMessage::ShareDecryptionProof(_) => "ShareDecryptionProof",
+        }
+    }
 }
 
 pub trait Facilitator {
@@ -2,6 +2,7 @@ use rug::Integer;
 
 use crate::{poly::Polynomial, polynomial};
 
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Rq {
     pub q: Integer,
     pub modulo: Polynomial,
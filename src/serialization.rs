@@ -0,0 +1,816 @@
+//! Compact, versioned and canonical binary format for parameters, keys and ciphertexts.
+//!
+//! Every object starts with a header:
+//!
+//! | magic `"HE"` | version (u8) | kind (u8) | N (u32) | coefficient width in bytes (u32) |
+//! | parameter id (32 bytes) |
+//!
+//! The parameter id is the SHA-256 digest of N, q and p, so objects are only read under the exact
+//! parameters they were written under.
+//!
+//! Polynomials are always written as exactly N coefficients in [0, q), each stored as a
+//! fixed-width little-endian integer whose width is determined by q. All integers in the
+//! header and body are little-endian.
+
+use rug::{integer::Order, ops::RemRounding, Integer};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    encryption::{Ciphertext, Parameters, PublicKey, SecretKey, MAX_DEGREE},
+    mpc::{
+        store::PreprocessingStore,
+        zk::{challenge_rows, PlaintextKnowledgeProof},
+        AngleShare, Sec,
+    },
+    poly::Polynomial,
+    protocol::KeyMaterial,
+};
+
+const MAGIC: [u8; 2] = *b"HE";
+pub const FORMAT_VERSION: u8 = 2;
+const HEADER_LEN: usize = 44;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectKind {
+    Parameters = 0,
+    PublicKey = 1,
+    SecretKey = 2,
+    Ciphertext = 3,
+    KeyMaterial = 4,
+    PlaintextKnowledgeProof = 5,
+    PreprocessingStore = 6,
+}
+
+impl ObjectKind {
+    fn from_byte(byte: u8) -> Option<ObjectKind> {
+        match byte {
+            0 => Some(ObjectKind::Parameters),
+            1 => Some(ObjectKind::PublicKey),
+            2 => Some(ObjectKind::SecretKey),
+            3 => Some(ObjectKind::Ciphertext),
+            4 => Some(ObjectKind::KeyMaterial),
+            5 => Some(ObjectKind::PlaintextKnowledgeProof),
+            6 => Some(ObjectKind::PreprocessingStore),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SerializationError {
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    UnknownKind(u8),
+    WrongKind {
+        expected: ObjectKind,
+        found: ObjectKind,
+    },
+    /// The header describes different parameters than the given ones
+    ParameterMismatch,
+    CoefficientOutOfRange,
+    /// A boolean flag is neither 0 nor 1
+    InvalidFlag(u8),
+    /// The degree N of parameters is not a power of two up to `MAX_DEGREE`
+    InvalidDegree(usize),
+    /// A proof was made for a security parameter that is not supported
+    UnsupportedSecurityParameter(usize),
+    /// A response has more rows than its challenge
+    TooManyRows {
+        expected: usize,
+        found: usize,
+    },
+    UnexpectedEnd,
+    TrailingBytes,
+}
+
+/// The number of bytes used to store a single coefficient in [0, q)
+pub fn coefficient_width(q: &Integer) -> usize {
+    Integer::from(q - 1).significant_bits().div_ceil(8) as usize
+}
+
+/// Write a polynomial as exactly N fixed-width coefficients, without a header.
+///
+/// Coefficients are reduced to [0, q) first, so equal elements of R_q always give equal bytes.
+pub fn polynomial_to_bytes(params: &Parameters, pol: &Polynomial) -> Vec<u8> {
+    let q = &params.quotient_ring.q;
+    let width = coefficient_width(q);
+
+    let mut bytes = Vec::with_capacity(params.n * width);
+    for i in 0..params.n {
+        let coefficient = pol.coefficient(i).rem_euc(q);
+        let mut digits = coefficient.to_digits::<u8>(Order::Lsf);
+        digits.resize(width, 0);
+        bytes.extend(digits);
+    }
+    bytes
+}
+
+pub fn parameters_to_bytes(params: &Parameters) -> Vec<u8> {
+    let mut bytes = header(params, ObjectKind::Parameters);
+    write_integer(&mut bytes, &params.quotient_ring.q);
+    write_integer(&mut bytes, &params.p);
+    bytes.extend(params.r.to_le_bytes());
+    bytes.extend(params.r_prime.to_le_bytes());
+    bytes
+}
+
+pub fn parameters_from_bytes(bytes: &[u8]) -> Result<Parameters, SerializationError> {
+    let mut reader = Reader::new(bytes);
+    let (n, width, id) = reader.header(ObjectKind::Parameters)?;
+
+    let q = reader.integer()?;
+    let p = reader.integer()?;
+    let r = f64::from_le_bytes(reader.array()?);
+    let r_prime = f64::from_le_bytes(reader.array()?);
+    reader.finish()?;
+
+    // Building the ring allocates N + 1 coefficients, so N is checked first
+    if !n.is_power_of_two() || n > MAX_DEGREE {
+        return Err(SerializationError::InvalidDegree(n));
+    }
+    if coefficient_width(&q) != width || parameter_id(n, &q, &p) != id {
+        return Err(SerializationError::ParameterMismatch);
+    }
+    Ok(Parameters::new(q, r, r_prime, n, p))
+}
+
+pub fn public_key_to_bytes(params: &Parameters, pk: &PublicKey) -> Vec<u8> {
+    let mut bytes = header(params, ObjectKind::PublicKey);
+    bytes.extend(polynomial_to_bytes(params, &pk.0));
+    bytes.extend(polynomial_to_bytes(params, &pk.1));
+    bytes
+}
+
+pub fn public_key_from_bytes(
+    params: &Parameters,
+    bytes: &[u8],
+) -> Result<PublicKey, SerializationError> {
+    let mut reader = Reader::new(bytes);
+    reader.header_for(params, ObjectKind::PublicKey)?;
+    let pk = (reader.polynomial(params)?, reader.polynomial(params)?);
+    reader.finish()?;
+    Ok(pk)
+}
+
+pub fn secret_key_to_bytes(params: &Parameters, sk: &SecretKey) -> Vec<u8> {
+    let mut bytes = header(params, ObjectKind::SecretKey);
+    bytes.extend(polynomial_to_bytes(params, sk));
+    bytes
+}
+
+pub fn secret_key_from_bytes(
+    params: &Parameters,
+    bytes: &[u8],
+) -> Result<SecretKey, SerializationError> {
+    let mut reader = Reader::new(bytes);
+    reader.header_for(params, ObjectKind::SecretKey)?;
+    let sk = reader.polynomial(params)?;
+    reader.finish()?;
+    Ok(sk)
+}
+
+pub fn ciphertext_to_bytes(params: &Parameters, c: &Ciphertext) -> Vec<u8> {
+    let mut bytes = header(params, ObjectKind::Ciphertext);
+    bytes.extend((c.len() as u32).to_le_bytes());
+    for pol in c {
+        bytes.extend(polynomial_to_bytes(params, pol));
+    }
+    bytes
+}
+
+pub fn ciphertext_from_bytes(
+    params: &Parameters,
+    bytes: &[u8],
+) -> Result<Ciphertext, SerializationError> {
+    let mut reader = Reader::new(bytes);
+    reader.header_for(params, ObjectKind::Ciphertext)?;
+
+    // The length is not trusted for allocating
+    let len = reader.u32()?;
+    let mut c = Vec::new();
+    for _ in 0..len {
+        c.push(reader.polynomial(params)?);
+    }
+    reader.finish()?;
+    Ok(c)
+}
+
+pub fn key_material_to_bytes(params: &Parameters, key_material: &KeyMaterial) -> Vec<u8> {
+    let mut bytes = header(params, ObjectKind::KeyMaterial);
+    bytes.extend(polynomial_to_bytes(params, &key_material.pk.0));
+    bytes.extend(polynomial_to_bytes(params, &key_material.pk.1));
+    bytes.extend(polynomial_to_bytes(params, &key_material.sk_i1));
+    bytes.extend(polynomial_to_bytes(params, &key_material.sk_i2));
+    bytes
+}
+
+pub fn key_material_from_bytes(
+    params: &Parameters,
+    bytes: &[u8],
+) -> Result<KeyMaterial, SerializationError> {
+    let mut reader = Reader::new(bytes);
+    reader.header_for(params, ObjectKind::KeyMaterial)?;
+    let key_material = KeyMaterial {
+        pk: (reader.polynomial(params)?, reader.polynomial(params)?),
+        sk_i1: reader.polynomial(params)?,
+        sk_i2: reader.polynomial(params)?,
+    };
+    reader.finish()?;
+    Ok(key_material)
+}
+
+/// Write a ZKPoPK with its statement.
+///
+/// After the header come sec (u32), the diagonal flag (u8), the public key fingerprint (32 bytes),
+/// the ciphertexts c and a, and the responses z and t. Each response is written as the number of
+/// rows (u32) and a single width (u32), followed by all coefficients zigzag-encoded with that width.
+pub fn plaintext_knowledge_proof_to_bytes(
+    params: &Parameters,
+    proof: &PlaintextKnowledgeProof,
+) -> Vec<u8> {
+    let mut bytes = header(params, ObjectKind::PlaintextKnowledgeProof);
+    bytes.extend((proof.sec as u32).to_le_bytes());
+    bytes.push(proof.diagonal as u8);
+    bytes.extend(proof.pk_fingerprint);
+    for ciphertexts in [&proof.c, &proof.a] {
+        bytes.extend((ciphertexts.len() as u32).to_le_bytes());
+        for c in ciphertexts {
+            bytes.extend((c.len() as u32).to_le_bytes());
+            for pol in c {
+                bytes.extend(polynomial_to_bytes(params, pol));
+            }
+        }
+    }
+    write_signed_rows(&mut bytes, &proof.z);
+    write_signed_rows(&mut bytes, &proof.t);
+    bytes
+}
+
+pub fn plaintext_knowledge_proof_from_bytes(
+    params: &Parameters,
+    bytes: &[u8],
+) -> Result<PlaintextKnowledgeProof, SerializationError> {
+    let mut reader = Reader::new(bytes);
+    reader.header_for(params, ObjectKind::PlaintextKnowledgeProof)?;
+
+    let sec = reader.u32()? as usize;
+    let diagonal = match reader.array()? {
+        [0] => false,
+        [1] => true,
+        [flag] => return Err(SerializationError::InvalidFlag(flag)),
+    };
+    let pk_fingerprint = reader.array()?;
+    let c = reader.ciphertexts(params)?;
+    let a = reader.ciphertexts(params)?;
+
+    // The responses have one row per row of the challenge
+    let rows = Sec::new(sec)
+        .map(|sec| challenge_rows(params, sec, diagonal))
+        .map_err(|_| SerializationError::UnsupportedSecurityParameter(sec))?;
+    let z = reader.signed_rows(params.n, rows)?;
+    let t = reader.signed_rows(3 * params.n, rows)?;
+    reader.finish()?;
+
+    Ok(PlaintextKnowledgeProof {
+        c,
+        pk_fingerprint,
+        diagonal,
+        sec,
+        a,
+        z,
+        t,
+    })
+}
+
+/// Write the preprocessed material of a player.
+///
+/// After the header come the number of players (u32), the number of the player (u32), sec (u32),
+/// the public key fingerprint (32 bytes), alpha_i, the ciphertext e_alpha, and the pairs, triples, square pairs and
+/// bits, each as their count (u32) followed by their shares. Shares are reduced modulo p and
+/// written as integers.
+pub fn preprocessing_store_to_bytes(params: &Parameters, store: &PreprocessingStore) -> Vec<u8> {
+    let mut bytes = header(params, ObjectKind::PreprocessingStore);
+    bytes.extend((store.player_count as u32).to_le_bytes());
+    bytes.extend((store.player_number as u32).to_le_bytes());
+    bytes.extend((store.sec.bits() as u32).to_le_bytes());
+    bytes.extend(store.pk_fingerprint);
+    write_integer(&mut bytes, &store.alpha_i.clone().rem_euc(&params.p));
+    bytes.extend((store.e_alpha.len() as u32).to_le_bytes());
+    for pol in &store.e_alpha {
+        bytes.extend(polynomial_to_bytes(params, pol));
+    }
+
+    let mut write_shares = |count: usize, shares: Vec<&Integer>| {
+        bytes.extend((count as u32).to_le_bytes());
+        for share in shares {
+            write_integer(&mut bytes, &share.clone().rem_euc(&params.p));
+        }
+    };
+    write_shares(
+        store.pairs.len(),
+        store
+            .pairs
+            .iter()
+            .flat_map(|(r, (v, m))| [r, v, m])
+            .collect(),
+    );
+    write_shares(
+        store.triples.len(),
+        store
+            .triples
+            .iter()
+            .flat_map(|(a, b, c)| [&a.0, &a.1, &b.0, &b.1, &c.0, &c.1])
+            .collect(),
+    );
+    write_shares(
+        store.squares.len(),
+        store
+            .squares
+            .iter()
+            .flat_map(|(a, b)| [&a.0, &a.1, &b.0, &b.1])
+            .collect(),
+    );
+    write_shares(
+        store.bits.len(),
+        store.bits.iter().flat_map(|(v, m)| [v, m]).collect(),
+    );
+    bytes
+}
+
+pub fn preprocessing_store_from_bytes(
+    params: &Parameters,
+    bytes: &[u8],
+) -> Result<PreprocessingStore, SerializationError> {
+    let mut reader = Reader::new(bytes);
+    reader.header_for(params, ObjectKind::PreprocessingStore)?;
+
+    let player_count = reader.u32()? as usize;
+    let player_number = reader.u32()? as usize;
+    let sec = reader.u32()? as usize;
+    let sec = Sec::new(sec).map_err(|_| SerializationError::UnsupportedSecurityParameter(sec))?;
+    let pk_fingerprint = reader.array()?;
+    let alpha_i = reader.integer()?;
+    let len = reader.u32()?;
+    let mut e_alpha = Vec::new();
+    for _ in 0..len {
+        e_alpha.push(reader.polynomial(params)?);
+    }
+
+    // The counts are not trusted for allocating
+    let mut read_items = |shares_per_item: usize| -> Result<Vec<_>, SerializationError> {
+        let count = reader.u32()?;
+        let mut items = Vec::new();
+        for _ in 0..count {
+            let mut item = Vec::with_capacity(shares_per_item);
+            for _ in 0..shares_per_item {
+                item.push(reader.integer()?);
+            }
+            items.push(item.into_iter());
+        }
+        Ok(items)
+    };
+    fn angle(item: &mut impl Iterator<Item = Integer>) -> AngleShare {
+        (item.next().unwrap(), item.next().unwrap())
+    }
+
+    let pairs = read_items(3)?
+        .into_iter()
+        .map(|mut item| (item.next().unwrap(), angle(&mut item)))
+        .collect();
+    let triples = read_items(6)?
+        .into_iter()
+        .map(|mut item| (angle(&mut item), angle(&mut item), angle(&mut item)))
+        .collect();
+    let squares = read_items(4)?
+        .into_iter()
+        .map(|mut item| (angle(&mut item), angle(&mut item)))
+        .collect();
+    let bits = read_items(2)?
+        .into_iter()
+        .map(|mut item| angle(&mut item))
+        .collect();
+    reader.finish()?;
+
+    Ok(PreprocessingStore {
+        player_count,
+        player_number,
+        sec,
+        pk_fingerprint,
+        alpha_i,
+        e_alpha,
+        pairs,
+        triples,
+        squares,
+        bits,
+    })
+}
+
+fn header(params: &Parameters, kind: ObjectKind) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_LEN);
+    bytes.extend(MAGIC);
+    bytes.push(FORMAT_VERSION);
+    bytes.push(kind as u8);
+    bytes.extend((params.n as u32).to_le_bytes());
+    bytes.extend((coefficient_width(&params.quotient_ring.q) as u32).to_le_bytes());
+    bytes.extend(parameter_id(params.n, &params.quotient_ring.q, &params.p));
+    bytes
+}
+
+/// SHA-256 digest of N, q and p, identifying the parameters in the header
+fn parameter_id(n: usize, q: &Integer, p: &Integer) -> [u8; 32] {
+    let mut bytes = (n as u32).to_le_bytes().to_vec();
+    write_integer(&mut bytes, q);
+    write_integer(&mut bytes, p);
+    Sha256::digest(bytes).into()
+}
+
+/// Write an arbitrary-size non-negative integer as its length followed by its little-endian bytes
+fn write_integer(bytes: &mut Vec<u8>, i: &Integer) {
+    let digits = i.to_digits::<u8>(Order::Lsf);
+    bytes.extend((digits.len() as u32).to_le_bytes());
+    bytes.extend(digits);
+}
+
+/// Write rows of signed integers with a common width, mapping x to 2x for x >= 0 and to -2x - 1
+/// otherwise, so small integers of either sign take few bytes
+fn write_signed_rows(bytes: &mut Vec<u8>, rows: &[Vec<Integer>]) {
+    let zigzag = |x: &Integer| -> Integer {
+        if *x < 0 {
+            Integer::from(x * -2_i32) - 1_i32
+        } else {
+            Integer::from(x * 2_i32)
+        }
+    };
+    let width = rows
+        .iter()
+        .flatten()
+        .map(|x| zigzag(x).significant_bits().div_ceil(8) as usize)
+        .max()
+        .unwrap_or(0);
+
+    bytes.extend((rows.len() as u32).to_le_bytes());
+    bytes.extend((width as u32).to_le_bytes());
+    for x in rows.iter().flatten() {
+        let mut digits = zigzag(x).to_digits::<u8>(Order::Lsf);
+        digits.resize(width, 0);
+        bytes.extend(digits);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SerializationError> {
+        if self.bytes.len() < len {
+            return Err(SerializationError::UnexpectedEnd);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const LEN: usize>(&mut self) -> Result<[u8; LEN], SerializationError> {
+        Ok(self.take(LEN)?.try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> Result<u32, SerializationError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn integer(&mut self) -> Result<Integer, SerializationError> {
+        let len = self.u32()? as usize;
+        Ok(Integer::from_digits(self.take(len)?, Order::Lsf))
+    }
+
+    /// Read the header, returning N, the coefficient width and the parameter id
+    fn header(
+        &mut self,
+        expected: ObjectKind,
+    ) -> Result<(usize, usize, [u8; 32]), SerializationError> {
+        if self.take(MAGIC.len())? != MAGIC {
+            return Err(SerializationError::InvalidMagic);
+        }
+
+        let [version, kind] = self.array()?;
+        if version != FORMAT_VERSION {
+            return Err(SerializationError::UnsupportedVersion(version));
+        }
+        let found = ObjectKind::from_byte(kind).ok_or(SerializationError::UnknownKind(kind))?;
+        if found != expected {
+            return Err(SerializationError::WrongKind { expected, found });
+        }
+
+        let n = self.u32()? as usize;
+        let width = self.u32()? as usize;
+        let id = self.array()?;
+        Ok((n, width, id))
+    }
+
+    /// Read the header and check that it matches the given parameters
+    fn header_for(
+        &mut self,
+        params: &Parameters,
+        expected: ObjectKind,
+    ) -> Result<(), SerializationError> {
+        let (n, width, id) = self.header(expected)?;
+        if n != params.n
+            || width != coefficient_width(&params.quotient_ring.q)
+            || id != parameter_id(params.n, &params.quotient_ring.q, &params.p)
+        {
+            return Err(SerializationError::ParameterMismatch);
+        }
+        Ok(())
+    }
+
+    fn polynomial(&mut self, params: &Parameters) -> Result<Polynomial, SerializationError> {
+        let q = &params.quotient_ring.q;
+        let width = coefficient_width(q);
+
+        let mut coefficients = Vec::with_capacity(params.n);
+        for _ in 0..params.n {
+            let coefficient = Integer::from_digits(self.take(width)?, Order::Lsf);
+            if &coefficient >= q {
+                return Err(SerializationError::CoefficientOutOfRange);
+            }
+            coefficients.push(coefficient);
+        }
+        Ok(Polynomial::new(coefficients).trim_res())
+    }
+
+    /// Read a number of ciphertexts, written without headers
+    fn ciphertexts(&mut self, params: &Parameters) -> Result<Vec<Ciphertext>, SerializationError> {
+        // The lengths are not trusted for allocating
+        let count = self.u32()?;
+        let mut ciphertexts = Vec::new();
+        for _ in 0..count {
+            let len = self.u32()?;
+            let mut c = Vec::new();
+            for _ in 0..len {
+                c.push(self.polynomial(params)?);
+            }
+            ciphertexts.push(c);
+        }
+        Ok(ciphertexts)
+    }
+
+    /// Read at most `max_rows` rows of `columns` signed integers written with `write_signed_rows`
+    fn signed_rows(
+        &mut self,
+        columns: usize,
+        max_rows: usize,
+    ) -> Result<Vec<Vec<Integer>>, SerializationError> {
+        let count = self.u32()? as usize;
+        let width = self.u32()? as usize;
+
+        // Bound the rows before allocating them, also when the width is 0
+        if count > max_rows {
+            return Err(SerializationError::TooManyRows {
+                expected: max_rows,
+                found: count,
+            });
+        }
+        let len = count
+            .checked_mul(columns)
+            .and_then(|coefficients| coefficients.checked_mul(width));
+        if len.is_none_or(|len| len > self.bytes.len()) {
+            return Err(SerializationError::UnexpectedEnd);
+        }
+
+        let mut rows = Vec::new();
+        for _ in 0..count {
+            let mut row = Vec::with_capacity(columns);
+            for _ in 0..columns {
+                let zigzag = Integer::from_digits(self.take(width)?, Order::Lsf);
+                row.push(if zigzag.is_odd() {
+                    -(zigzag + 1_i32) / 2_i32
+                } else {
+                    zigzag / 2_i32
+                });
+            }
+            rows.push(row);
+        }
+        Ok(rows)
+    }
+
+    fn finish(self) -> Result<(), SerializationError> {
+        if !self.bytes.is_empty() {
+            return Err(SerializationError::TrailingBytes);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        encryption::{decrypt, encrypt, generate_key_pair, mpc_secure_params, Parameters},
+        poly::Polynomial,
+        polynomial,
+    };
+
+    use super::*;
+
+    #[test]
+    fn ciphertext_round_trip() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+        let c = encrypt(&params, polynomial![1, 2, 3], &pk);
+
+        let bytes = ciphertext_to_bytes(&params, &c);
+        let decoded = ciphertext_from_bytes(&params, &bytes).unwrap();
+
+        assert_eq!(decoded, c);
+        assert_eq!(
+            decrypt(&params, decoded, &sk).unwrap(),
+            polynomial![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn ciphertext_has_fixed_size() {
+        let params = mpc_secure_params();
+        let width = coefficient_width(&params.quotient_ring.q);
+
+        let bytes = ciphertext_to_bytes(&params, &vec![polynomial![0], polynomial![1]]);
+        assert_eq!(bytes.len(), HEADER_LEN + 4 + 2 * params.n * width);
+    }
+
+    #[test]
+    fn keys_round_trip() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+
+        let decoded_pk =
+            public_key_from_bytes(&params, &public_key_to_bytes(&params, &pk)).unwrap();
+        assert_eq!(decoded_pk, pk);
+
+        // The secret key has negative coefficients, so it comes back in its canonical form mod q
+        let decoded_sk =
+            secret_key_from_bytes(&params, &secret_key_to_bytes(&params, &sk)).unwrap();
+        assert_eq!(decoded_sk, sk.modulo(&params.quotient_ring.q));
+
+        let c = encrypt(&params, polynomial![5], &pk);
+        assert_eq!(decrypt(&params, c, &decoded_sk).unwrap(), polynomial![5]);
+    }
+
+    #[test]
+    fn key_material_round_trip() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+        let rq = &params.quotient_ring;
+        let key_material = KeyMaterial {
+            sk_i2: rq.mul(&sk, &sk),
+            sk_i1: sk.modulo(&rq.q),
+            pk,
+        };
+
+        let bytes = key_material_to_bytes(&params, &key_material);
+        let decoded = key_material_from_bytes(&params, &bytes).unwrap();
+
+        assert_eq!(decoded.pk, key_material.pk);
+        assert_eq!(decoded.sk_i1, key_material.sk_i1);
+        assert_eq!(decoded.sk_i2, key_material.sk_i2);
+    }
+
+    #[test]
+    fn parameters_round_trip() {
+        let params = mpc_secure_params();
+
+        let decoded = parameters_from_bytes(&parameters_to_bytes(&params)).unwrap();
+
+        assert_eq!(decoded.quotient_ring.q, params.quotient_ring.q);
+        assert_eq!(decoded.quotient_ring.modulo, params.quotient_ring.modulo);
+        assert_eq!(decoded.p, params.p);
+        assert_eq!(decoded.n, params.n);
+        assert_eq!(decoded.r, params.r);
+        assert_eq!(decoded.r_prime, params.r_prime);
+    }
+
+    #[test]
+    fn encoding_is_canonical() {
+        let params = Parameters::default();
+        let q = &params.quotient_ring.q;
+
+        let pol = polynomial![-1, 2];
+        let reduced = Polynomial::new(vec![Integer::from(q - 1), Integer::from(2)]);
+
+        assert_eq!(
+            polynomial_to_bytes(&params, &pol),
+            polynomial_to_bytes(&params, &reduced)
+        );
+    }
+
+    #[test]
+    fn rejects_parameter_mismatch() {
+        let params = mpc_secure_params();
+        let other_params = Parameters::default();
+        let (pk, _) = generate_key_pair(&params);
+        let c = encrypt(&params, polynomial![1], &pk);
+
+        let bytes = ciphertext_to_bytes(&params, &c);
+        assert_eq!(
+            ciphertext_from_bytes(&other_params, &bytes),
+            Err(SerializationError::ParameterMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_parameters_differing_only_in_p() {
+        let params = mpc_secure_params();
+        let other_params = Parameters::new(
+            params.quotient_ring.q.clone(),
+            params.r,
+            params.r_prime,
+            params.n,
+            Integer::from(&params.p + 2_i32),
+        );
+        let (pk, _) = generate_key_pair(&params);
+
+        let bytes = public_key_to_bytes(&params, &pk);
+        assert_eq!(
+            public_key_from_bytes(&other_params, &bytes),
+            Err(SerializationError::ParameterMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_parameters_with_invalid_degree() {
+        let params = mpc_secure_params();
+        let bytes = parameters_to_bytes(&params);
+        let with_n = |n: u32| {
+            let mut bytes = bytes.clone();
+            bytes[4..8].copy_from_slice(&n.to_le_bytes());
+            parameters_from_bytes(&bytes)
+        };
+
+        // Rejected before the ring is built
+        assert_eq!(
+            with_n(u32::MAX),
+            Err(SerializationError::InvalidDegree(u32::MAX as usize))
+        );
+        assert_eq!(
+            with_n(2 * MAX_DEGREE as u32),
+            Err(SerializationError::InvalidDegree(2 * MAX_DEGREE))
+        );
+        assert_eq!(with_n(64), Err(SerializationError::ParameterMismatch));
+    }
+
+    #[test]
+    fn rejects_ciphertext_length_beyond_input() {
+        let params = mpc_secure_params();
+
+        // Claims u32::MAX polynomials without containing any
+        let mut bytes = ciphertext_to_bytes(&params, &vec![]);
+        bytes[HEADER_LEN..].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            ciphertext_from_bytes(&params, &bytes),
+            Err(SerializationError::UnexpectedEnd)
+        );
+    }
+
+    #[test]
+    fn rejects_wrong_kind_and_version() {
+        let params = mpc_secure_params();
+        let (pk, _) = generate_key_pair(&params);
+
+        let mut bytes = public_key_to_bytes(&params, &pk);
+        assert_eq!(
+            ciphertext_from_bytes(&params, &bytes),
+            Err(SerializationError::WrongKind {
+                expected: ObjectKind::Ciphertext,
+                found: ObjectKind::PublicKey
+            })
+        );
+
+        bytes[2] = FORMAT_VERSION + 1;
+        assert_eq!(
+            public_key_from_bytes(&params, &bytes),
+            Err(SerializationError::UnsupportedVersion(FORMAT_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_and_trailing_bytes() {
+        let params = mpc_secure_params();
+        let (pk, _) = generate_key_pair(&params);
+        let bytes = public_key_to_bytes(&params, &pk);
+
+        assert_eq!(
+            public_key_from_bytes(&params, &bytes[..bytes.len() - 1]),
+            Err(SerializationError::UnexpectedEnd)
+        );
+
+        let mut longer = bytes;
+        longer.push(0);
+        assert_eq!(
+            public_key_from_bytes(&params, &longer),
+            Err(SerializationError::TrailingBytes)
+        );
+    }
+}
@@ -0,0 +1,345 @@
+//! Saving and loading keys and ciphertexts to files.
+//!
+//! Each file consists of the SHA-256 fingerprint of the `Parameters` it was created under,
+//! followed by the object in the binary format from `serialization`. Loading under different
+//! parameters fails with `StorageError::ParameterMismatch`.
+
+use std::{fmt::Display, fs, io, path::Path};
+
+use sha2::{Digest, Sha256};
+
+use crate::{
+    encryption::{generate_key_pair, Ciphertext, Parameters, PublicKey, SecretKey},
+    mpc::store::PreprocessingStore,
+    protocol::KeyMaterial,
+    serialization::*,
+};
+
+pub type Fingerprint = [u8; 32];
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    /// The file was created under different parameters than the ones it is loaded with
+    ParameterMismatch {
+        expected: Fingerprint,
+        found: Fingerprint,
+    },
+    Serialization(SerializationError),
+}
+
+impl Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Io(e) => write!(f, "I/O error: {}", e),
+            StorageError::ParameterMismatch { expected, found } => write!(
+                f,
+                "file was created with parameters {} but the current parameters are {}",
+                to_hex(found),
+                to_hex(expected)
+            ),
+            StorageError::Serialization(e) => write!(f, "malformed file: {:?}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<io::Error> for StorageError {
+    fn from(e: io::Error) -> Self {
+        StorageError::Io(e)
+    }
+}
+
+impl From<SerializationError> for StorageError {
+    fn from(e: SerializationError) -> Self {
+        StorageError::Serialization(e)
+    }
+}
+
+/// Hash the canonical encoding of the parameters
+pub fn fingerprint(params: &Parameters) -> Fingerprint {
+    Sha256::digest(parameters_to_bytes(params)).into()
+}
+
+pub fn save_public_key<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+    pk: &PublicKey,
+) -> Result<(), StorageError> {
+    save(path, params, public_key_to_bytes(params, pk))
+}
+
+pub fn load_public_key<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+) -> Result<PublicKey, StorageError> {
+    Ok(public_key_from_bytes(params, &load(path, params)?)?)
+}
+
+pub fn save_secret_key<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+    sk: &SecretKey,
+) -> Result<(), StorageError> {
+    save(path, params, secret_key_to_bytes(params, sk))
+}
+
+pub fn load_secret_key<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+) -> Result<SecretKey, StorageError> {
+    Ok(secret_key_from_bytes(params, &load(path, params)?)?)
+}
+
+pub fn save_key_material<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+    key_material: &KeyMaterial,
+) -> Result<(), StorageError> {
+    save(path, params, key_material_to_bytes(params, key_material))
+}
+
+pub fn load_key_material<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+) -> Result<KeyMaterial, StorageError> {
+    Ok(key_material_from_bytes(params, &load(path, params)?)?)
+}
+
+/// Load the key pair stored as `pk.bin` and `sk.bin` in `dir`, or generate a new one and store it
+/// there, so that several sessions can use the same key
+pub fn load_or_generate_key_pair<P: AsRef<Path>>(
+    dir: P,
+    params: &Parameters,
+) -> Result<(PublicKey, SecretKey), StorageError> {
+    let pk_path = dir.as_ref().join("pk.bin");
+    let sk_path = dir.as_ref().join("sk.bin");
+    if pk_path.exists() && sk_path.exists() {
+        return Ok((
+            load_public_key(&pk_path, params)?,
+            load_secret_key(&sk_path, params)?,
+        ));
+    }
+
+    let (pk, sk) = generate_key_pair(params);
+    fs::create_dir_all(dir)?;
+    save_public_key(&pk_path, params, &pk)?;
+    save_secret_key(&sk_path, params, &sk)?;
+    Ok((pk, sk))
+}
+
+pub fn save_preprocessing_store<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+    store: &PreprocessingStore,
+) -> Result<(), StorageError> {
+    save(path, params, preprocessing_store_to_bytes(params, store))
+}
+
+pub fn load_preprocessing_store<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+) -> Result<PreprocessingStore, StorageError> {
+    Ok(preprocessing_store_from_bytes(
+        params,
+        &load(path, params)?,
+    )?)
+}
+
+pub fn save_ciphertext<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+    c: &Ciphertext,
+) -> Result<(), StorageError> {
+    save(path, params, ciphertext_to_bytes(params, c))
+}
+
+pub fn load_ciphertext<P: AsRef<Path>>(
+    path: P,
+    params: &Parameters,
+) -> Result<Ciphertext, StorageError> {
+    Ok(ciphertext_from_bytes(params, &load(path, params)?)?)
+}
+
+fn save<P: AsRef<Path>>(path: P, params: &Parameters, object: Vec<u8>) -> Result<(), StorageError> {
+    let mut contents = fingerprint(params).to_vec();
+    contents.extend(object);
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Read a file and check its fingerprint, returning the serialized object
+fn load<P: AsRef<Path>>(path: P, params: &Parameters) -> Result<Vec<u8>, StorageError> {
+    let contents = fs::read(path)?;
+    if contents.len() < 32 {
+        return Err(SerializationError::UnexpectedEnd.into());
+    }
+
+    let (found, object) = contents.split_at(32);
+    let found: Fingerprint = found.try_into().unwrap();
+    let expected = fingerprint(params);
+    if found != expected {
+        return Err(StorageError::ParameterMismatch { expected, found });
+    }
+
+    Ok(object.to_vec())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, path::PathBuf};
+
+    use rug::Integer;
+
+    use crate::{
+        encryption::{
+            decrypt, encrypt, generate_key_pair, mpc_secure_params, params_16degree, Parameters,
+        },
+        mpc::Sec,
+        poly::Polynomial,
+        polynomial,
+    };
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("he-storage-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn keys_and_ciphertext_round_trip() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+        let c = encrypt(&params, polynomial![3, 1], &pk);
+
+        let pk_path = temp_path("pk");
+        let sk_path = temp_path("sk");
+        let c_path = temp_path("c");
+        save_public_key(&pk_path, &params, &pk).unwrap();
+        save_secret_key(&sk_path, &params, &sk).unwrap();
+        save_ciphertext(&c_path, &params, &c).unwrap();
+
+        let loaded_pk = load_public_key(&pk_path, &params).unwrap();
+        let loaded_sk = load_secret_key(&sk_path, &params).unwrap();
+        let loaded_c = load_ciphertext(&c_path, &params).unwrap();
+
+        assert_eq!(loaded_pk, pk);
+        assert_eq!(loaded_c, c);
+        assert_eq!(
+            decrypt(&params, loaded_c, &loaded_sk).unwrap(),
+            polynomial![3, 1]
+        );
+
+        let c_2 = encrypt(&params, polynomial![4], &loaded_pk);
+        assert_eq!(decrypt(&params, c_2, &sk).unwrap(), polynomial![4]);
+
+        for path in [pk_path, sk_path, c_path] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn key_material_round_trip() {
+        let params = mpc_secure_params();
+        let (pk, sk) = generate_key_pair(&params);
+        let key_material = KeyMaterial {
+            sk_i2: params.quotient_ring.mul(&sk, &sk),
+            sk_i1: sk.modulo(&params.quotient_ring.q),
+            pk,
+        };
+
+        let path = temp_path("key-material");
+        save_key_material(&path, &params, &key_material).unwrap();
+        let loaded = load_key_material(&path, &params).unwrap();
+
+        assert_eq!(loaded.pk, key_material.pk);
+        assert_eq!(loaded.sk_i1, key_material.sk_i1);
+        assert_eq!(loaded.sk_i2, key_material.sk_i2);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn preprocessing_store_round_trip() {
+        let params = mpc_secure_params();
+        let share = |v: i32| (Integer::from(v), Integer::from(v + 1));
+        let store = PreprocessingStore {
+            player_count: 3,
+            player_number: 1,
+            sec: Sec::new(64).unwrap(),
+            pk_fingerprint: [7; 32],
+            alpha_i: Integer::from(42),
+            e_alpha: vec![polynomial![1, 2], polynomial![3]],
+            pairs: vec![(Integer::from(1), share(2)), (Integer::from(3), share(4))],
+            triples: vec![(share(5), share(7), share(9))],
+            squares: vec![(share(11), share(13))],
+            bits: vec![share(0), share(125), share(1)],
+        };
+
+        let path = temp_path("preprocessing");
+        save_preprocessing_store(&path, &params, &store).unwrap();
+        let loaded = load_preprocessing_store(&path, &params).unwrap();
+        assert_eq!(loaded, store);
+
+        // Shares are stored modulo p
+        let mut negative = store.clone();
+        negative.bits[0].0 = Integer::from(-1);
+        save_preprocessing_store(&path, &params, &negative).unwrap();
+        let loaded = load_preprocessing_store(&path, &params).unwrap();
+        assert_eq!(loaded.bits[0].0, Integer::from(126));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn load_with_other_parameters_fails() {
+        let params = mpc_secure_params();
+        let (pk, _) = generate_key_pair(&params);
+
+        let path = temp_path("mismatch");
+        save_public_key(&path, &params, &pk).unwrap();
+
+        // Same N and q, but a different plaintext modulus
+        let other_params = Parameters::new(
+            params.quotient_ring.q.clone(),
+            3.2,
+            3.2,
+            32,
+            Integer::from(257),
+        );
+        let res = load_public_key(&path, &other_params);
+        assert!(
+            matches!(res, Err(StorageError::ParameterMismatch { .. })),
+            "expected parameter mismatch, got {:?}",
+            res
+        );
+
+        let res = load_public_key(&path, &params_16degree());
+        assert!(matches!(res, Err(StorageError::ParameterMismatch { .. })));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fingerprint_depends_on_parameters() {
+        assert_eq!(
+            fingerprint(&mpc_secure_params()),
+            fingerprint(&mpc_secure_params())
+        );
+        assert_ne!(
+            fingerprint(&mpc_secure_params()),
+            fingerprint(&params_16degree())
+        );
+    }
+
+    #[test]
+    fn load_missing_file_fails() {
+        let res = load_ciphertext(temp_path("does-not-exist"), &mpc_secure_params());
+        assert!(matches!(res, Err(StorageError::Io(_))));
+    }
+}